@@ -0,0 +1,217 @@
+//! Known vulnerabilities of the dependencies from the OSV database, reported by `dep audit`.
+//!
+//! Online, every dependency is looked up by its commit (or tag) with the OSV API. Offline, a downloaded
+//! copy of the database is searched for advisories of the repository, which are matched against the
+//! affected tags and, for vendored checkouts, against the history of the affected commit ranges.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::history::vendored_commit;
+use crate::manifest::{dependency_dir, get_lib_dir, TomlManifest};
+use crate::report::Reporter;
+use crate::update::git_url;
+
+const OSV_QUERY: &str = "https://api.osv.dev/v1/query";
+
+/// An advisory that affects a dependency.
+#[derive(Debug, Clone)]
+pub struct Advisory {
+    pub id: String,
+    // CVE and GHSA ids of the advisory
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    // commits or versions which fix it, for the repository of the dependency
+    pub fixed: Vec<String>,
+}
+
+/// What an advisory is matched against.
+struct Target {
+    name: String,
+    url: String,
+    commit: Option<git2::Oid>,
+    tag: Option<String>,
+    // the vendored checkout, for the history of commit ranges
+    dir: PathBuf,
+}
+
+/// Urls are compared without scheme, user, .git and case, so `git@github.com:a/b.git` matches `https://github.com/a/b`.
+fn normalize_url(url: &str) -> String {
+    let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let url = url.split_once('@').map(|(_, rest)| rest).unwrap_or(url);
+    let url = url.replacen(':', "/", 1);
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_lowercase()
+}
+
+/// Git dependencies with their urls, skipping those which aren't pinned or vendored.
+fn targets(man: &TomlManifest, opts: &GlobalOptions, reporter: &dyn Reporter) -> Vec<Target> {
+    let libdir = get_lib_dir(man, opts);
+    let mut targets = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        if dep.path.is_some() || dep.url.is_some() {
+            continue;
+        }
+        let dir = dependency_dir(&libdir, name, dep);
+        let url = git_url(opts, man.project.git_server.as_ref(), dep).ok().or_else(|| {
+            let repo = git2::Repository::open(&dir).ok()?;
+            let remote = repo.find_remote("origin").ok()?;
+            remote.url().map(|url| url.to_owned())
+        });
+        let url = match url {
+            Some(url) => url,
+            None => {
+                reporter.info(&format!("Skipping \"{}\", it has no git url", name));
+                continue;
+            }
+        };
+        let commit = vendored_commit(&dir).or_else(|| dep.rev.as_ref().filter(|rev| rev.len() == 40).and_then(|rev| git2::Oid::from_str(rev).ok()));
+        let tag = dep.tag.clone().or_else(|| dep.release.clone());
+        if commit.is_none() && tag.is_none() {
+            reporter.info(&format!("Skipping \"{}\", it's neither vendored nor pinned", name));
+            continue;
+        }
+        targets.push(Target { name: name.clone(), url, commit, tag, dir });
+    }
+    targets
+}
+
+/// The affected entries of a vulnerability which are about the git repository at `url`.
+fn affected_ranges<'a>(vulnerability: &'a Value, url: &str) -> Vec<&'a Value> {
+    let url = normalize_url(url);
+    vulnerability.get("affected").and_then(|affected| affected.as_array()).into_iter().flatten()
+        .flat_map(|affected| affected.get("ranges").and_then(|ranges| ranges.as_array()).into_iter().flatten())
+        .filter(|range| range.get("type").and_then(|kind| kind.as_str()) == Some("GIT"))
+        .filter(|range| range.get("repo").and_then(|repo| repo.as_str()).map(normalize_url).as_deref() == Some(url.as_str()))
+        .collect()
+}
+
+fn advisory(vulnerability: &Value, url: &str) -> Advisory {
+    let strings = |key: &str| vulnerability.get(key).and_then(|values| values.as_array()).into_iter().flatten()
+        .filter_map(|value| value.as_str().map(|value| value.to_owned()))
+        .collect::<Vec<_>>();
+    let mut fixed = Vec::new();
+    for range in affected_ranges(vulnerability, url) {
+        for event in range.get("events").and_then(|events| events.as_array()).into_iter().flatten() {
+            if let Some(commit) = event.get("fixed").and_then(|fixed| fixed.as_str()) {
+                fixed.push(commit.to_owned());
+            }
+        }
+    }
+    Advisory {
+        id: vulnerability.get("id").and_then(|id| id.as_str()).unwrap_or_default().to_owned(),
+        aliases: strings("aliases"),
+        summary: vulnerability.get("summary").and_then(|summary| summary.as_str()).map(|summary| summary.to_owned()),
+        fixed,
+    }
+}
+
+/// Looks the dependency up with the OSV API, by commit if it's known and by tag otherwise.
+fn query(target: &Target) -> Result<Vec<Advisory>> {
+    let body = match (&target.commit, &target.tag) {
+        (Some(commit), _) => json!({ "commit": commit.to_string() }),
+        (None, Some(tag)) => json!({ "package": { "name": target.url, "ecosystem": "GIT" }, "version": tag }),
+        (None, None) => return Ok(Vec::new()),
+    };
+    let response = ureq::post(OSV_QUERY).set("Content-Type", "application/json").send_string(&body.to_string())?;
+    let response: Value = serde_json::from_str(&response.into_string()?)?;
+    let vulnerabilities = response.get("vulns").and_then(|vulns| vulns.as_array()).cloned().unwrap_or_default();
+    Ok(vulnerabilities.iter().map(|vulnerability| advisory(vulnerability, &target.url)).collect())
+}
+
+/// The advisories of a downloaded database, a directory or zip file (like `all.zip` of an ecosystem) of OSV json files.
+pub fn read_database(path: &Path) -> Result<Vec<Value>> {
+    let mut contents = Vec::new();
+    if path.is_dir() {
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?.path();
+                if entry.is_dir() {
+                    dirs.push(entry);
+                } else if entry.extension().map(|extension| extension == "json").unwrap_or(false) {
+                    contents.push(std::fs::read_to_string(&entry)?);
+                }
+            }
+        }
+    } else {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            if file.name().ends_with(".json") {
+                let mut content = String::new();
+                file.read_to_string(&mut content)?;
+                contents.push(content);
+            }
+        }
+    }
+    // other files in the directory aren't advisories
+    Ok(contents.iter().filter_map(|content| serde_json::from_str(content).ok()).collect())
+}
+
+/// Whether `commit` is `other` or comes after it.
+fn contains(repo: &git2::Repository, commit: git2::Oid, other: &str) -> bool {
+    let other = match git2::Oid::from_str(other) {
+        Ok(other) => other,
+        Err(_) => return false,
+    };
+    commit == other || repo.graph_descendant_of(commit, other).unwrap_or(false)
+}
+
+/// Whether an advisory of the database affects the dependency, by its tag or by the history of the checkout.
+fn is_affected(vulnerability: &Value, target: &Target) -> bool {
+    let ranges = affected_ranges(vulnerability, &target.url);
+    if ranges.is_empty() {
+        return false;
+    }
+    let versions = vulnerability.get("affected").and_then(|affected| affected.as_array()).into_iter().flatten()
+        .flat_map(|affected| affected.get("versions").and_then(|versions| versions.as_array()).into_iter().flatten())
+        .filter_map(|version| version.as_str())
+        .collect::<Vec<_>>();
+    if let Some(tag) = &target.tag {
+        if versions.contains(&tag.as_str()) {
+            return true;
+        }
+    }
+    let (repo, commit) = match (git2::Repository::open(&target.dir), target.commit) {
+        (Ok(repo), Some(commit)) => (repo, commit),
+        _ => return false,
+    };
+    ranges.iter().any(|range| {
+        let events = range.get("events").and_then(|events| events.as_array()).cloned().unwrap_or_default();
+        let event = |kind: &str| events.iter().filter_map(|event| event.get(kind).and_then(|value| value.as_str()).map(|value| value.to_owned())).collect::<Vec<_>>();
+        // "0" means the whole history up to the fix
+        let introduced = event("introduced").iter().any(|introduced| introduced == "0" || contains(&repo, commit, introduced));
+        let fixed = event("fixed").iter().chain(event("limit").iter()).any(|fixed| contains(&repo, commit, fixed));
+        let after_last = event("last_affected").iter().any(|last| last.as_str() != commit.to_string() && contains(&repo, commit, last));
+        introduced && !fixed && !after_last
+    })
+}
+
+/// The advisories which affect each dependency, leaving out the ones without any. With a `database`,
+/// nothing is fetched, otherwise the OSV API is asked.
+pub fn audit(man: &TomlManifest, opts: &GlobalOptions, database: Option<&Path>, offline: bool, reporter: &dyn Reporter) -> Result<Vec<(String, Vec<Advisory>)>> {
+    let database = match database {
+        Some(path) => Some(read_database(path)?),
+        None if offline => return Err(Error::msg("Auditing offline needs a copy of the database, pass it with --database")),
+        None => None,
+    };
+    let mut findings = Vec::new();
+    for target in targets(man, opts, reporter) {
+        let advisories = match &database {
+            Some(database) => database.iter()
+                .filter(|vulnerability| is_affected(vulnerability, &target))
+                .map(|vulnerability| advisory(vulnerability, &target.url))
+                .collect(),
+            None => query(&target)?,
+        };
+        if !advisories.is_empty() {
+            findings.push((target.name, advisories));
+        }
+    }
+    Ok(findings)
+}
@@ -0,0 +1,268 @@
+//! Bundles of the vendored dependencies, made by `dep bundle` to carry them across an air gap
+//! and unpacked by `dep restore`.
+//!
+//! A bundle is a gzipped tar file with a `bundle.toml` describing it, the manifests of the project,
+//! a git bundle of the checked out commit of every git dependency, and the files of downloads and of
+//! checkouts with submodules, which a git bundle can't hold.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::filelock::lock_dir;
+use crate::git::run_git;
+use crate::history::vendored_commit;
+use crate::manifest::{dependency_dir, get_lib_dir, load_manifest, load_manifest_with_origins};
+use crate::paths::{absolute_path, relative_path, replace_dir};
+use crate::report::Reporter;
+use crate::status::changed_files;
+
+pub const BUNDLE_INDEX: &str = "bundle.toml";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BundledFormat {
+    // a git bundle at git/<name>.bundle
+    Git,
+    // the directory as it is, at files/<name>
+    Files,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct BundledDependency {
+    pub format: BundledFormat,
+    // where the dependency is vendored, relative to the project
+    pub path: PathBuf,
+    // the checked out commit and branch of git dependencies
+    pub commit: Option<String>,
+    pub branch: Option<String>,
+    // the url of the origin remote
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct BundleIndex {
+    // the manifest of the project and those it includes, relative to the project, stored at manifests/<path>
+    pub manifests: Vec<PathBuf>,
+    pub dependencies: BTreeMap<String, BundledDependency>,
+}
+
+/// Adds `data` as a file to the bundle.
+fn append_file<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Writes a git bundle of the checked out commit (and the branch it's on) of `dst` into `file`.
+fn git_bundle(dst: &Path, branch: Option<&str>, file: &Path) -> Result<()> {
+    let mut refs = vec!["HEAD".to_owned()];
+    refs.extend(branch.map(|branch| format!("refs/heads/{}", branch)));
+    let output = match Command::new("git").arg("bundle").arg("create").arg(file).args(&refs).current_dir(dst).output() {
+        Ok(output) => output,
+        Err(e) => return Err(Error::msg(format!("Could not run git to create a bundle, is it installed? ({})", e))),
+    };
+    if !output.status.success() {
+        return Err(Error::msg(format!("git bundle failed in \"{}\": {}", dst.to_string_lossy(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// Writes every vendored dependency of the manifest at `file_path`, at its checked out commit, into the bundle `output`.
+/// Returns how many dependencies were bundled. Path dependencies are left out, they aren't vendored.
+///
+/// The bundle is written next to `output` first, and only moved there once it's complete.
+pub fn bundle(file_path: &Path, opts: &GlobalOptions, output: &Path, reporter: &dyn Reporter) -> Result<usize> {
+    let file_name = output.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let partial = output.with_file_name(format!(".{}.tmp", file_name));
+    match write_bundle(file_path, opts, &partial, reporter) {
+        Ok(count) => {
+            std::fs::rename(&partial, output)?;
+            Ok(count)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+fn write_bundle(file_path: &Path, opts: &GlobalOptions, output: &Path, reporter: &dyn Reporter) -> Result<usize> {
+    let (man, origins) = load_manifest_with_origins(file_path)?;
+    let project = std::env::current_dir()?;
+    let libdir = get_lib_dir(&man, opts);
+    let _lock = lock_dir(&libdir, true, reporter)?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(File::create(output)?, Compression::default()));
+    // symlinks in downloads stay symlinks
+    builder.follow_symlinks(false);
+
+    let mut manifests = Vec::new();
+    for manifest in std::iter::once(&file_path.to_path_buf()).chain(origins.values()) {
+        let relative = relative_path(&project, &absolute_path(manifest)?);
+        if manifests.contains(&relative) {
+            continue;
+        }
+        if relative.starts_with("..") {
+            reporter.info(&format!("Skipping the manifest \"{}\", it's outside of the project", manifest.to_string_lossy()));
+            continue;
+        }
+        append_file(&mut builder, &format!("manifests/{}", relative.to_string_lossy()), &std::fs::read(manifest)?)?;
+        manifests.push(relative);
+    }
+
+    let mut dependencies = BTreeMap::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        if dep.path.is_some() {
+            continue;
+        }
+        let dst = dependency_dir(&libdir, name, dep);
+        if !dst.exists() {
+            return Err(Error::msg(format!("\"{}\" isn't vendored, run \"dep update\" first", name)));
+        }
+        let path = relative_path(&project, &absolute_path(&dst)?);
+        let is_git = dep.url.is_none() && dep.release.is_none();
+        let dependency = match vendored_commit(&dst) {
+            Some(commit) if is_git => {
+                let repo = git2::Repository::open(&dst)?;
+                let head = repo.head()?;
+                let branch = if head.is_branch() { head.shorthand().map(|branch| branch.to_owned()) } else { None };
+                let url = repo.find_remote("origin").ok().and_then(|remote| remote.url().map(|url| url.to_owned()));
+                if !changed_files(&dst)?.is_empty() {
+                    reporter.info(&format!("\"{}\" has uncommitted changes, only its commit gets bundled", name));
+                }
+                // git bundles don't contain submodules, so those checkouts are bundled as they are
+                let format = if dst.join(".gitmodules").exists() { BundledFormat::Files } else { BundledFormat::Git };
+                BundledDependency { format, path, commit: Some(commit.to_string()), branch, url }
+            }
+            None if is_git => return Err(Error::msg(format!("\"{}\" has no checked out commit", name))),
+            _ => BundledDependency { format: BundledFormat::Files, path, commit: None, branch: None, url: dep.url.clone() },
+        };
+        reporter.info(&format!("Bundling \"{}\"{}", name, dependency.commit.as_ref().map(|commit| format!(" at {}", &commit[..10])).unwrap_or_default()));
+        match dependency.format {
+            BundledFormat::Git => {
+                let file = libdir.join(format!(".{}.bundle.tmp", name));
+                let result = git_bundle(&dst, dependency.branch.as_deref(), &absolute_path(&file)?)
+                    .and_then(|_| Ok(builder.append_path_with_name(&file, format!("git/{}.bundle", name))?));
+                let _ = std::fs::remove_file(&file);
+                result?;
+            }
+            BundledFormat::Files => builder.append_dir_all(format!("files/{}", name), &dst)?,
+        }
+        dependencies.insert(name.clone(), dependency);
+    }
+
+    let count = dependencies.len();
+    let index = BundleIndex { manifests, dependencies };
+    append_file(&mut builder, BUNDLE_INDEX, toml::to_string(&index)?.as_bytes())?;
+    builder.into_inner()?.finish()?;
+    Ok(count)
+}
+
+/// Paths of a bundle have to stay inside the project.
+fn project_path(project: &Path, path: &Path) -> Result<PathBuf> {
+    let inside = path.components().all(|component| matches!(component, std::path::Component::Normal(_) | std::path::Component::CurDir));
+    if !inside {
+        return Err(Error::msg(format!("The bundle contains the path \"{}\", which is outside of the project", path.to_string_lossy())));
+    }
+    Ok(project.join(path))
+}
+
+/// Clones the git bundle of a dependency into `work`, checked out at its commit and on its branch,
+/// with the origin remote pointing to where it was cloned from originally.
+fn clone_bundle(file: &Path, work: &Path, dependency: &BundledDependency) -> Result<()> {
+    let commit = dependency.commit.as_deref().ok_or_else(|| Error::msg("A git bundle without a commit"))?;
+    let parent = work.parent().unwrap_or(work);
+    std::fs::create_dir_all(parent)?;
+    run_git(parent, &["clone", "--quiet", "--no-checkout", &file.to_string_lossy(), &work.to_string_lossy()])?;
+    match &dependency.branch {
+        Some(branch) => run_git(work, &["checkout", "--quiet", "-B", branch, commit])?,
+        None => run_git(work, &["checkout", "--quiet", "--detach", commit])?,
+    }
+    if let Some(url) = &dependency.url {
+        git2::Repository::open(work)?.remote_set_url("origin", url)?;
+    }
+    Ok(())
+}
+
+/// Unpacks the bundle `file` into the current project, without touching the network: manifests which
+/// don't exist yet are added, and every dependency is vendored at the commit it was bundled with.
+/// Returns how many dependencies were restored, those at the bundled commit already are left as they are.
+pub fn restore(file: &Path, opts: &GlobalOptions, reporter: &dyn Reporter) -> Result<usize> {
+    let project = std::env::current_dir()?;
+    let staging = project.join(".dep-restore.tmp");
+    if staging.exists() {
+        remove_dir_all::remove_dir_all(&staging)?;
+    }
+    let result = restore_from(file, opts, &project, &staging, reporter);
+    if staging.exists() {
+        remove_dir_all::remove_dir_all(&staging)?;
+    }
+    result
+}
+
+fn restore_from(file: &Path, opts: &GlobalOptions, project: &Path, staging: &Path, reporter: &dyn Reporter) -> Result<usize> {
+    let file = absolute_path(file)?;
+    tar::Archive::new(GzDecoder::new(File::open(&file)?)).unpack(staging)?;
+    let index = match std::fs::read_to_string(staging.join(BUNDLE_INDEX)) {
+        Ok(index) => toml::from_str::<BundleIndex>(&index)?,
+        Err(_) => return Err(Error::msg(format!("\"{}\" isn't a bundle made by dep bundle", file.to_string_lossy()))),
+    };
+
+    for manifest in &index.manifests {
+        let dst = project_path(project, manifest)?;
+        if dst.exists() {
+            continue;
+        }
+        reporter.info(&format!("Restoring the manifest \"{}\"", manifest.to_string_lossy()));
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(staging.join("manifests").join(manifest), &dst)?;
+    }
+    let _lock = match index.manifests.first() {
+        Some(manifest) => {
+            let libdir = project.join(get_lib_dir(&load_manifest(&project.join(manifest))?, opts));
+            std::fs::create_dir_all(&libdir)?;
+            Some(lock_dir(&libdir, true, reporter)?)
+        }
+        None => None,
+    };
+
+    let mut restored = 0;
+    for (name, dependency) in &index.dependencies {
+        let dst = project_path(project, &dependency.path)?;
+        if dependency.commit.is_some() && vendored_commit(&dst).map(|commit| commit.to_string()) == dependency.commit {
+            reporter.info(&format!("\"{}\" is already at the bundled commit", name));
+            continue;
+        }
+        let src = match dependency.format {
+            BundledFormat::Git => {
+                let work = staging.join("checkouts").join(name);
+                clone_bundle(&staging.join("git").join(format!("{}.bundle", name)), &work, dependency)?;
+                work
+            }
+            BundledFormat::Files => staging.join("files").join(name),
+        };
+        reporter.info(&format!("Restoring \"{}\" into \"{}\"{}", name, dependency.path.to_string_lossy(),
+            dependency.commit.as_ref().map(|commit| format!(" at {}", &commit[..10])).unwrap_or_default()));
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        replace_dir(&src, &dst)?;
+        restored += 1;
+    }
+    Ok(restored)
+}
@@ -0,0 +1,326 @@
+//! Bookkeeping of the cache dir: which projects use the cached repositories and indexes, and when
+//! they were used last, so `dep cache gc` knows what can go.
+//!
+//! Commands which use the cache record it in `usage.toml`, while they hold the lock of the cache dir.
+//!
+//! Bare repositories in `git/` are borrowed from by new clones (with git alternates), so they are never
+//! collected and their objects are never pruned: clones of projects which moved, or which aren't recorded,
+//! would lose their objects. The checkouts in `checkouts/`, which the lib dirs of projects link to,
+//! aren't collected while a project uses them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::filelock::{lock_dir, LOCK_FILE};
+use crate::git::{mirror_repository, Credentials};
+use crate::mirror::mirror_path;
+use crate::paths::cache_name;
+use crate::policy::{enforce_url, mirror_url};
+use crate::report::Reporter;
+
+const USAGE_FILE: &str = "usage.toml";
+
+/// Directories of the cache dir with cached repositories (or index files) in them.
+pub const CACHE_KINDS: [&str; 4] = ["registry", "templates", "git", "checkouts"];
+
+/// Directory of the bare repositories which clones borrow objects from, `dep cache gc` keeps them.
+pub const BORROWED: &str = "git";
+
+/// Directory of the checkouts which projects share.
+const SHARED: &str = "checkouts";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheEntry {
+    pub url: String,
+    // seconds since the epoch
+    pub last_used: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheUsage {
+    // entries by their path in the cache dir, like registry/<name>
+    pub entries: BTreeMap<String, CacheEntry>,
+    // absolute path of the manifest of a project -> entries it uses
+    pub projects: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The bare repository of `url` in the cache, which new clones borrow their objects from, if it exists.
+/// It's laid out like the mirrors of `dep mirror`, so `dep mirror <cache dir>/git` fills it.
+pub fn cached_repository(opts: &GlobalOptions, url: &str) -> Result<PathBuf> {
+    Ok(mirror_path(&opts.cache_dir()?.join(BORROWED), url))
+}
+
+/// Where the checkout of `url` at `rev` lives in the cache, which every project vendoring
+/// the repository at that rev links to.
+pub fn shared_checkout(opts: &GlobalOptions, url: &str, rev: &str) -> Result<PathBuf> {
+    Ok(opts.cache_dir()?.join(SHARED).join(format!("{}@{}", cache_name(url), cache_name(rev))))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or_default()
+}
+
+/// The recorded usage of the cache dir `cache`, empty if nothing was recorded yet.
+pub fn read_usage(cache: &Path) -> Result<CacheUsage> {
+    match std::fs::read_to_string(cache.join(USAGE_FILE)) {
+        Ok(content) => Ok(toml::from_str(&content)?),
+        Err(_) => Ok(CacheUsage::default()),
+    }
+}
+
+fn write_usage(cache: &Path, usage: &CacheUsage) -> Result<()> {
+    std::fs::create_dir_all(cache)?;
+    std::fs::write(cache.join(USAGE_FILE), toml::to_string(usage)?)?;
+    Ok(())
+}
+
+/// The key of `path` in the usage of `cache`.
+fn entry_key(cache: &Path, path: &Path) -> String {
+    path.strip_prefix(cache).unwrap_or(path).to_string_lossy().replace('\\', "/")
+}
+
+/// Records that the cached `entry` (fetched from `url`) was used just now, by the project with the manifest `project`, if any.
+/// The caller has to hold the lock of the cache dir.
+pub fn record_use(cache: &Path, entry: &Path, url: &str, project: Option<&Path>) -> Result<()> {
+    let mut usage = read_usage(cache)?;
+    let key = entry_key(cache, entry);
+    usage.entries.insert(key.clone(), CacheEntry { url: url.to_owned(), last_used: now() });
+    if let Some(project) = project {
+        usage.projects.entry(project.to_string_lossy().to_string()).or_default().insert(key);
+    }
+    write_usage(cache, &usage)
+}
+
+/// Projects whose manifest still exists, the others don't keep anything in the cache.
+/// Older versions recorded the directory of the project instead.
+fn is_project(project: &str) -> bool {
+    let path = Path::new(project);
+    path.is_file() || ["deps.toml", "deps.json", "deps.yaml", "deps.yml"].iter().any(|manifest| path.join(manifest).exists())
+}
+
+/// Size of a file, or of everything in a directory, in bytes. Symlinks aren't followed.
+pub fn disk_size(path: &Path) -> u64 {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path).map(|entries| entries.flatten().map(|entry| disk_size(&entry.path())).sum()).unwrap_or_default()
+}
+
+/// Sizes like 1.5 MiB.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", size, units[unit]) }
+}
+
+/// Every entry in the cache dir, by its key.
+fn cached_entries(cache: &Path) -> Vec<(String, PathBuf)> {
+    let mut entries = Vec::new();
+    for kind in CACHE_KINDS.iter() {
+        let dir = cache.join(kind);
+        let mut paths = std::fs::read_dir(&dir).map(|paths| paths.flatten().map(|entry| entry.path()).collect::<Vec<_>>()).unwrap_or_default();
+        paths.sort();
+        // lock files and unfinished clones aren't entries
+        paths.retain(|path| path.file_name().map(|name| name != LOCK_FILE && !name.to_string_lossy().ends_with(".tmp")).unwrap_or(false));
+        entries.extend(paths.into_iter().map(|path| (entry_key(cache, &path), path)));
+    }
+    entries
+}
+
+/// An entry of the cache dir, as `dep cache list` shows it.
+#[derive(Debug, Clone)]
+pub struct CacheListing {
+    // the path in the cache dir, like registry/<name>
+    pub key: String,
+    pub url: Option<String>,
+    pub size: u64,
+    pub last_used: Option<u64>,
+    // manifests of the existing projects which use it
+    pub projects: Vec<String>,
+}
+
+/// Every entry of the cache dir, with its size and the projects using it. Entries from before
+/// the usage got recorded have the url of their origin remote and no last use.
+pub fn list(opts: &GlobalOptions) -> Result<Vec<CacheListing>> {
+    let cache = opts.cache_dir()?;
+    let usage = read_usage(&cache)?;
+    let listings = cached_entries(&cache).into_iter().map(|(key, path)| {
+        let entry = usage.entries.get(&key);
+        let url = entry.map(|entry| entry.url.clone()).or_else(|| {
+            let repo = git2::Repository::open(&path).ok()?;
+            let remote = repo.find_remote("origin").ok()?;
+            remote.url().map(|url| url.to_owned())
+        });
+        let projects = usage.projects.iter()
+            .filter(|(dir, entries)| entries.contains(&key) && is_project(dir))
+            .map(|(dir, _)| dir.clone())
+            .collect();
+        CacheListing { size: disk_size(&path), url, last_used: entry.map(|entry| entry.last_used), projects, key }
+    }).collect();
+    Ok(listings)
+}
+
+/// How long ago `time` (seconds since the epoch) was, like "3 days ago".
+pub fn format_age(time: u64) -> String {
+    let days = now().saturating_sub(time) / 86400;
+    match days {
+        0 => "today".to_owned(),
+        1 => "yesterday".to_owned(),
+        days => format!("{} days ago", days),
+    }
+}
+
+/// Fetches every url into its repository in the cache (see [`cached_repository`]), without touching any lib dir,
+/// so later updates only fetch what's new. The urls are recorded as used by `project`, if there is one.
+/// Urls which fail don't stop the others, they are reported at the end. Returns how many were fetched.
+pub fn prefetch(opts: &GlobalOptions, credentials: &Credentials, urls: &[String], project: Option<&Path>, offline: bool) -> Result<usize> {
+    if offline {
+        return Err(Error::msg("The cache can't be prefetched in offline mode"));
+    }
+    let cache = opts.cache_dir()?;
+    let mut fetched = Vec::new();
+    let mut failures = Vec::new();
+    for url in urls {
+        enforce_url(opts, url, url)?;
+        let path = cached_repository(opts, url)?;
+        let existed = path.exists();
+        credentials.reporter().info(&format!("Fetching \"{}\" into \"{}\"", url, path.to_string_lossy()));
+        // updates with worktrees fetch into the same repository
+        let result = lock_dir(&path, true, credentials.reporter()).and_then(|_lock| mirror_repository(credentials, &mirror_url(opts, url), &path));
+        match result {
+            Ok(()) => fetched.push((url, path)),
+            Err(e) => {
+                credentials.reporter().info(&format!("Failed to fetch \"{}\": {}", url, e));
+                if !existed {
+                    remove_dir_all::remove_dir_all(&path)?;
+                }
+                failures.push(url.as_str());
+            }
+        }
+    }
+    let _lock = lock_dir(&cache, true, credentials.reporter())?;
+    for (url, path) in &fetched {
+        record_use(&cache, path, url, project)?;
+    }
+    if !failures.is_empty() {
+        return Err(Error::msg(format!("Failed to fetch {}", failures.join(", "))));
+    }
+    Ok(fetched.len())
+}
+
+/// Removes cached entries no existing project uses, and with `max_age` (in seconds) also those which weren't
+/// used for longer, then repacks the git repositories that are left. Repositories clones borrow from are always kept.
+/// Returns the number of bytes it freed.
+pub fn gc(opts: &GlobalOptions, max_age: Option<u64>, reporter: &dyn Reporter) -> Result<u64> {
+    let cache = opts.cache_dir()?;
+    let _lock = lock_dir(&cache, true, reporter)?;
+    let before = disk_size(&cache);
+
+    let mut usage = read_usage(&cache)?;
+    usage.projects.retain(|dir, _| is_project(dir));
+    let used = usage.projects.values().flatten().cloned().collect::<BTreeSet<_>>();
+    let now = now();
+    for (key, path) in cached_entries(&cache) {
+        let entry = usage.entries.get(&key);
+        let is_borrowed = key.starts_with(&format!("{}/", BORROWED));
+        let is_shared = key.starts_with(&format!("{}/", SHARED));
+        let reason = if is_borrowed {
+            // clones of projects which moved, or used another manifest, can't do without its objects
+            None
+        } else if !used.contains(&key) {
+            Some("no project uses it".to_owned())
+        } else if is_shared {
+            // the lib dirs of the projects link to it
+            None
+        } else {
+            let age = now.saturating_sub(entry.map(|entry| entry.last_used).unwrap_or_default());
+            match max_age {
+                Some(max_age) if age > max_age => Some(format!("unused for {} days", age / 86400)),
+                _ => None,
+            }
+        };
+        match reason {
+            Some(reason) => {
+                reporter.info(&format!("Removing \"{}\" from the cache, {}", entry.map(|entry| entry.url.as_str()).unwrap_or(&key), reason));
+                if path.is_dir() {
+                    remove_dir_all::remove_dir_all(&path)?;
+                } else {
+                    std::fs::remove_file(&path)?;
+                }
+                usage.entries.remove(&key);
+            }
+            // a shared worktree would repack the repository it belongs to
+            None if !is_shared && git2::Repository::open(&path).is_ok() => {
+                reporter.info(&format!("Repacking \"{}\"", entry.map(|entry| entry.url.as_str()).unwrap_or(&key)));
+                // repacking is up to git, git2 can't, and objects clones borrow have to stay even if they are unreachable
+                let args: &[&str] = if is_borrowed { &["repack", "-a", "-d", "-q", "--keep-unreachable"] } else { &["gc", "--quiet"] };
+                let _ = Command::new("git").args(args).current_dir(&path).status();
+            }
+            None => (),
+        }
+    }
+    // entries which are gone from the disk
+    usage.entries.retain(|key, _| cache.join(key).exists());
+    let CacheUsage { entries, projects } = &mut usage;
+    for used in projects.values_mut() {
+        used.retain(|key| entries.contains_key(key));
+    }
+    write_usage(&cache, &usage)?;
+    Ok(before.saturating_sub(disk_size(&cache)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Silent;
+
+    #[test]
+    fn gc_keeps_the_repositories_clones_borrow_from() {
+        let cache = std::env::temp_dir().join(format!("dep-cache-{}", std::process::id()));
+        let borrowed = cache.join(BORROWED).join("github.com_org_repo.git");
+        let registry = cache.join("registry").join("index.json");
+        std::fs::create_dir_all(&borrowed).unwrap();
+        std::fs::create_dir_all(registry.parent().unwrap()).unwrap();
+        std::fs::write(&registry, "{}").unwrap();
+        // used by a project which moved away
+        let project = cache.join("moved").join("deps.toml");
+        record_use(&cache, &borrowed, "https://github.com/org/repo", Some(&project)).unwrap();
+
+        let mut opts = GlobalOptions::default();
+        opts.general.cache_dir = Some(cache.clone());
+        gc(&opts, None, &Silent).unwrap();
+        assert!(borrowed.exists());
+        assert!(!registry.exists());
+        remove_dir_all::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn projects_are_their_manifests() {
+        let dir = std::env::temp_dir().join(format!("dep-cache-project-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("other.toml");
+        assert!(!is_project(&manifest.to_string_lossy()));
+        std::fs::write(&manifest, "").unwrap();
+        assert!(is_project(&manifest.to_string_lossy()));
+        // recorded by older versions
+        assert!(!is_project(&dir.to_string_lossy()));
+        std::fs::write(dir.join("deps.toml"), "").unwrap();
+        assert!(is_project(&dir.to_string_lossy()));
+        remove_dir_all::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use crate::config::{apply_env_overrides, GlobalOptions};
+use crate::manifest::{dependency_order, ManifestFormat, TomlDependency, TomlManifest, TomlManifestV2};
+use crate::policy::check_url;
+use crate::registry::is_registry_dependency;
+use crate::update::git_url;
+use crate::url::repo_path;
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb { previous } else { 1 + previous.min(row[j]).min(row[j + 1]) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+/// The known key closest to `key`, if it's close enough to be a typo.
+pub fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known.iter()
+        .map(|k| (edit_distance(key, k), *k))
+        .filter(|(distance, k)| *distance <= std::cmp::max(1, k.len() / 3))
+        .min()
+        .map(|(_, k)| k)
+}
+
+/// Adds a "did you mean" hint to serde's unknown field errors,
+/// which look like "unknown field `brnach`, expected one of `path`, `repo`, ...".
+pub fn with_suggestion(message: &str) -> String {
+    let field = message.split("unknown field `").nth(1).and_then(|rest| rest.split('`').next());
+    let expected = message.split("expected one of ").nth(1)
+        .or_else(|| message.split("expected ").nth(1))
+        .map(|rest| rest.split(',')
+            .filter_map(|k| k.split('`').nth(1))
+            .collect::<Vec<_>>());
+    match (field, expected) {
+        (Some(field), Some(expected)) => match suggest(field, &expected) {
+            Some(suggestion) => format!("{}, did you mean `{}`?", message, suggestion),
+            None => message.to_owned(),
+        },
+        _ => message.to_owned(),
+    }
+}
+
+pub const MANIFEST_KEYS: &[&str] = &["include", "project", "dependencies", "config"];
+pub const PROJECT_KEYS: &[&str] = &["name", "lib-dir", "git-server", "authors", "description", "homepage", "repository", "metadata"];
+pub const DEPENDENCY_KEYS: &[&str] = &[
+    "path", "repo", "git", "branch", "tag", "rev", "into", "server", "version",
+    "url", "sha256", "release", "asset", "submodules", "lfs", "filter", "shallow-since", "ssh-key", "archive", "protocol", "as", "needs",
+];
+
+/// Finds the line of a dependency (or of one of its keys) in the manifest.
+/// Works for inline tables, `[dependencies.<name>]` tables and `[[dependency]]` entries.
+pub fn find_line(content: &str, name: &str, key: Option<&str>) -> Option<usize> {
+    let starts_with_key = |line: &str, key: &str| {
+        let line = line.trim_start();
+        [key.to_owned(), format!("\"{}\"", key), format!("'{}'", key)].iter()
+            .any(|k| line.strip_prefix(k.as_str()).map(|rest| rest.trim_start().starts_with('=')).unwrap_or(false))
+    };
+
+    let mut table = String::new();
+    let mut table_line = None;
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            table = trimmed.trim_matches(|c| c == '[' || c == ']').replace(['"', '\''], "");
+            if table == format!("dependencies.{}", name) {
+                table_line = Some(index + 1);
+            }
+        } else if table == "dependencies" && starts_with_key(line, name) {
+            return Some(index + 1);
+        } else if table == format!("dependencies.{}", name) {
+            if let Some(key) = key {
+                if starts_with_key(line, key) {
+                    return Some(index + 1);
+                }
+            }
+        }
+    }
+    // version 2 manifests have a `name` key in every dependency
+    table_line.or_else(|| content.lines()
+        .position(|line| [format!("name=\"{}\"", name), format!("name='{}'", name)].contains(&line.replace(' ', "")))
+        .map(|index| index + 1))
+}
+
+/// Checks a single dependency for contradicting or missing keys.
+pub fn check_dependency(dep: &TomlDependency, git_server: Option<&String>, opts: &GlobalOptions) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut problem = |message: String| problems.push(message);
+
+    let sources = [("path", dep.path.is_some()), ("git", dep.git.is_some()), ("repo", dep.repo.is_some()), ("url", dep.url.is_some())];
+    let given = sources.iter().filter(|(_, set)| *set).map(|(key, _)| *key).collect::<Vec<_>>();
+    if given.len() > 1 {
+        problem(format!("only one of path, git, repo and url can be used, found {}", given.join(", ")));
+    }
+
+    if dep.tag.is_some() && (dep.branch.is_some() || dep.rev.is_some()) {
+        problem("tag can't be combined with branch or rev".to_owned());
+    }
+    if (dep.branch.is_some() || dep.tag.is_some() || dep.rev.is_some()) && (dep.path.is_some() || dep.url.is_some()) {
+        problem("branch, tag and rev can only be used with git dependencies".to_owned());
+    }
+    if let Some(rev) = &dep.rev {
+        if git2::Oid::from_str(rev).is_err() {
+            problem(format!("\"{}\" is not a valid revision", rev));
+        }
+    }
+
+    if dep.repo.is_some() && dep.server.is_none() && git_server.is_none() {
+        problem("repo needs a server or a git-server in [project]".to_owned());
+    }
+    if let Some(repo) = &dep.repo {
+        let path = repo_path(repo);
+        if path.is_empty() || path.split('/').any(|segment| segment == "." || segment == ".." || segment == ".git") || repo.contains("://") {
+            problem(format!("repo \"{}\" is not a path like group/project", repo));
+        }
+    }
+    if dep.server.is_some() && dep.repo.is_none() {
+        problem("server can only be used with repo".to_owned());
+    }
+    if dep.protocol.is_some() && dep.repo.is_none() {
+        problem("protocol can only be used with repo".to_owned());
+    }
+
+    if let Some(path) = &dep.path {
+        if !path.exists() {
+            problem(format!("path \"{}\" does not exist", path.to_string_lossy()));
+        }
+    }
+    let archive = dep.archive.unwrap_or(false);
+    if dep.sha256.is_some() && dep.url.is_none() && dep.release.is_none() && !archive {
+        problem("sha256 can only be used with url, release or archive".to_owned());
+    }
+    if archive && dep.git.is_none() && dep.repo.is_none() {
+        problem("archive can only be used with git dependencies".to_owned());
+    } else if archive && dep.tag.is_none() && dep.rev.is_none() {
+        problem("archive needs a tag or rev".to_owned());
+    }
+    if (dep.url.is_some() || dep.release.is_some()) && dep.sha256.is_none() {
+        problem("url and release need a sha256 checksum".to_owned());
+    }
+    match (&dep.release, &dep.asset) {
+        (Some(_), None) => problem("release needs an asset".to_owned()),
+        (None, Some(_)) => problem("asset needs a release".to_owned()),
+        (Some(_), Some(_)) if dep.git.is_none() && dep.repo.is_none() => problem("release needs git or repo".to_owned()),
+        _ => (),
+    }
+    if is_registry_dependency(dep) && opts.registry.is_none() {
+        problem("no source given and no registry configured".to_owned());
+    }
+    if let (Some(policy), None) = (&opts.policy, &dep.path) {
+        let url = dep.url.clone().or_else(|| git_url(opts, git_server, dep).ok());
+        if let Some(violation) = url.and_then(|url| check_url(policy, &url)) {
+            problem(violation);
+        }
+    }
+
+    problems
+}
+
+/// Validates the manifest without touching the network or the file system, with the environment overrides
+/// of `load_manifest` applied. Returns the problems found, each with the line it refers to.
+pub fn check_manifest(content: &str, format: ManifestFormat, opts: &GlobalOptions) -> Vec<(Option<usize>, String)> {
+    let mut problems = Vec::new();
+
+    let parsed = match format {
+        ManifestFormat::Toml => toml::de::from_str(content).map_err(|e| (e.line_col().map(|(line, _)| line + 1), e.to_string())),
+        ManifestFormat::Json => serde_json::from_str(content).map_err(|e| (Some(e.line()), e.to_string())),
+        ManifestFormat::Yaml => serde_yaml::from_str(content).map_err(|e| (e.location().map(|l| l.line()), e.to_string())),
+    };
+    let mut value: toml::Value = match parsed {
+        Ok(value) => value,
+        Err(problem) => {
+            problems.push(problem);
+            return problems;
+        }
+    };
+
+    let mut man: TomlManifest = if value.get("version").and_then(|v| v.as_integer()) == Some(2) {
+        match value.try_into::<TomlManifestV2>() {
+            Ok(man) => match man.into_v1() {
+                Ok(man) => man,
+                Err(e) => {
+                    problems.push((None, e));
+                    return problems;
+                }
+            },
+            Err(e) => {
+                problems.push((None, with_suggestion(&e.to_string())));
+                return problems;
+            }
+        }
+    } else {
+        // unknown keys get reported and removed, so the rest of the manifest can still be checked
+        fn remove_unknown_keys(table: Option<&mut toml::Value>, known: &[&'static str]) -> Vec<(String, Option<&'static str>)> {
+            match table.and_then(|t| t.as_table_mut()) {
+                Some(table) => {
+                    let unknown = table.keys().filter(|key| !known.contains(&key.as_str())).cloned().collect::<Vec<_>>();
+                    unknown.into_iter().map(|key| {
+                        table.remove(&key);
+                        let suggestion = suggest(&key, known);
+                        (key, suggestion)
+                    }).collect()
+                }
+                None => Vec::new(),
+            }
+        }
+        let hint = |suggestion: Option<&str>| suggestion.map(|s| format!(", did you mean \"{}\"?", s)).unwrap_or_default();
+
+        for (key, suggestion) in remove_unknown_keys(Some(&mut value), MANIFEST_KEYS) {
+            problems.push((None, format!("unknown key \"{}\"{}", key, hint(suggestion))));
+        }
+        for (key, suggestion) in remove_unknown_keys(value.get_mut("project"), PROJECT_KEYS) {
+            problems.push((None, format!("unknown key \"{}\" in [project]{}", key, hint(suggestion))));
+        }
+        if let Some(deps) = value.get_mut("dependencies").and_then(|d| d.as_table_mut()) {
+            for (name, dep) in deps.iter_mut() {
+                for (key, suggestion) in remove_unknown_keys(Some(dep), DEPENDENCY_KEYS) {
+                    problems.push((find_line(content, name, Some(&key)), format!("\"{}\": unknown key \"{}\"{}", name, key, hint(suggestion))));
+                }
+            }
+        }
+
+        match value.try_into() {
+            Ok(man) => man,
+            Err(e) => {
+                problems.push((None, e.to_string()));
+                return problems;
+            }
+        }
+    };
+
+    apply_env_overrides(&mut man);
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let line = find_line(content, name, None);
+        for message in check_dependency(dep, man.project.git_server.as_ref(), opts) {
+            problems.push((line, format!("\"{}\": {}", name, message)));
+        }
+    }
+    // needs can refer to the dependencies of includes, which aren't read here
+    if let (None, Some(deps)) = (&man.include, &man.dependencies) {
+        if let Err(e) = dependency_order(deps) {
+            problems.push((None, e.to_string()));
+        }
+    }
+
+    problems
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_keys_with_typos() {
+        assert_eq!(suggest("brnach", DEPENDENCY_KEYS), Some("branch"));
+        assert_eq!(suggest("shalow-since", DEPENDENCY_KEYS), Some("shallow-since"));
+        assert_eq!(suggest("lib_dir", PROJECT_KEYS), Some("lib-dir"));
+        // short keys only get a suggestion with a single typo
+        assert_eq!(suggest("tga", DEPENDENCY_KEYS), None);
+        assert_eq!(suggest("checksum", DEPENDENCY_KEYS), None);
+    }
+
+    #[test]
+    fn adds_suggestions_to_unknown_field_errors() {
+        let message = "unknown field `brnach`, expected one of `path`, `repo`, `branch`, `tag`";
+        assert_eq!(with_suggestion(message), format!("{}, did you mean `branch`?", message));
+
+        let message = "unknown field `revision`, expected `rev`";
+        assert_eq!(with_suggestion(message), message);
+        assert_eq!(with_suggestion("missing field `project`"), "missing field `project`");
+    }
+
+    #[test]
+    fn reports_unknown_keys_with_their_line() {
+        let content = "[project]\nname = \"test\"\n\n[dependencies]\ndep = { git = \"https://host/dep\", brnach = \"main\" }\n";
+        let problems = check_manifest(content, ManifestFormat::Toml, &GlobalOptions::default());
+        assert_eq!(problems, vec![(Some(5), "\"dep\": unknown key \"brnach\", did you mean \"branch\"?".to_owned())]);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+
+use crate::error::{Error, Result};
+use crate::manifest::{parse, ManifestFormat, TomlManifest};
+use crate::paths;
+use crate::policy::{matches_host, url_origin};
+use crate::report::Reporter;
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SshOptions {
+    pub private: PathBuf,
+    pub public: PathBuf,
+    pub protected: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct GeneralOptions {
+    pub default_lib_dir: PathBuf,
+    // stash uncommitted changes of vendored repos before updating them, and re-apply them afterwards
+    pub preserve_local_changes: Option<bool>,
+    // registry indexes and templates get cached here, instead of the cache dir of the user
+    pub cache_dir: Option<PathBuf>,
+    // vendors git dependencies as worktrees of one shared clone per url in the cache
+    pub worktrees: Option<bool>,
+    // vendors git dependencies pinned to a rev once in the cache, and links them into the lib dirs
+    pub shared_checkouts: Option<bool>,
+    // partial clone filter of new clones, for dependencies without their own
+    pub filter: Option<String>,
+    // what clones, fetches and checks out git dependencies, libgit2 by default
+    pub backend: Option<Backend>,
+    // what happens when manifests ask for a repository at different refs, override by default
+    pub conflicts: Option<Conflicts>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    Libgit2,
+    // the git command line, with the config of git (credential helpers, ssh wrappers, lfs)
+    Cli,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflicts {
+    // the update fails
+    Error,
+    // the highest of the tags asked for is vendored, if all of them ask for tags
+    HighestTag,
+    // the ref the project asks for is vendored, the others are reported
+    Override,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct GlobalOptions {
+    pub general: GeneralOptions,
+    pub ssh: Option<SshOptions>,
+    // named git servers, usable as `server = "<alias>"` in manifests
+    pub servers: Option<BTreeMap<String, String>>,
+    pub registry: Option<RegistryOptions>,
+    // api tokens per host, used to download release assets
+    pub tokens: Option<BTreeMap<String, String>>,
+    // credentials per host (or *.domain), selected by the url. Hosts without any use [ssh]
+    pub credentials: Option<BTreeMap<String, HostCredentials>>,
+    pub network: Option<NetworkOptions>,
+    pub policy: Option<PolicyOptions>,
+}
+
+/// Credentials for the urls of one host, from `[credentials."<host>"]`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HostCredentials {
+    // private key for ssh urls, instead of [ssh]. The public key is expected at <key>.pub
+    pub ssh_key: Option<PathBuf>,
+    // access token for https urls, sent as the password. Also used to download release assets
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // git credential helper asked for the login of https urls, like "store" or "!pass show git"
+    pub helper: Option<String>,
+    // protocol of the urls made from server and repo, for dependencies without their own
+    pub protocol: Option<Protocol>,
+    // github or gitlab, for the source archives and release assets of self hosted servers
+    pub kind: Option<ServerKind>,
+}
+
+/// The software a server runs, which decides how source archives and release assets are downloaded.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServerKind {
+    Github,
+    Gitlab,
+}
+
+/// How the url of a repository on a server is made.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    // git@host:repo, or the ssh:// url of the server
+    Ssh,
+    // https://host/repo, logging in with the token or login of [credentials]
+    Https,
+}
+
+/// The credentials of `host`. `*.corp.com` applies to the subdomains of corp.com, unless they have their own.
+pub fn host_credentials<'a>(credentials: &'a BTreeMap<String, HostCredentials>, host: &str) -> Option<&'a HostCredentials> {
+    credentials.iter().find(|(pattern, _)| pattern.eq_ignore_ascii_case(host))
+        .or_else(|| credentials.iter().find(|(pattern, _)| matches_host(pattern, host)))
+        .map(|(_, credentials)| credentials)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkOptions {
+    // same as --offline
+    pub offline: Option<bool>,
+    // how host keys of ssh servers are checked against ~/.ssh/known_hosts, accept-new by default
+    pub host_key_checking: Option<HostKeyChecking>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    // unknown hosts are refused
+    Strict,
+    // unknown hosts are added to known_hosts, changed keys are refused
+    AcceptNew,
+    Off,
+}
+
+/// Where dependencies may come from, enforced by `dep update` and `dep check`.
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyOptions {
+    // hosts like git.corp.com, or *.corp.com for all of its subdomains. Any host, if unset
+    pub allowed_hosts: Option<Vec<String>>,
+    pub denied_hosts: Option<Vec<String>>,
+    // ssh, https, http, git or file. Any protocol, if unset
+    pub allowed_protocols: Option<Vec<String>>,
+    // refuses plain http urls, even if the protocols allow them
+    pub forbid_http: Option<bool>,
+    // url prefix -> mirror prefix, applied to every url that gets fetched
+    pub mirrors: Option<BTreeMap<String, String>>,
+    // fails updates of checkouts whose commit (or tag) has no valid signature, checked with git verify-commit
+    pub require_signatures: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryOptions {
+    // git repo containing an `index.json`, or the url of a json index served over http(s)
+    pub index: String,
+}
+
+impl Default for GlobalOptions {
+    fn default() -> GlobalOptions {
+        GlobalOptions {
+            // the default keys of ~/.ssh get tried without one
+            ssh: None,
+            general: GeneralOptions {
+                default_lib_dir: Path::new("VENDOR").to_path_buf(),
+                preserve_local_changes: None,
+                cache_dir: None,
+                worktrees: None,
+                shared_checkouts: None,
+                filter: None,
+                backend: None,
+                conflicts: None,
+            },
+            servers: None,
+            registry: None,
+            tokens: None,
+            credentials: None,
+            network: None,
+            policy: None,
+        }
+    }
+}
+
+impl GlobalOptions {
+    pub fn is_offline(&self) -> bool {
+        self.network.as_ref().and_then(|n| n.offline).unwrap_or(false)
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.general.backend.unwrap_or(Backend::Libgit2)
+    }
+
+    pub fn conflicts(&self) -> Conflicts {
+        self.general.conflicts.unwrap_or(Conflicts::Override)
+    }
+
+    pub fn host_key_checking(&self) -> HostKeyChecking {
+        self.network.as_ref().and_then(|n| n.host_key_checking).unwrap_or(HostKeyChecking::AcceptNew)
+    }
+
+    /// The `cache-dir` of `[general]`, or the default cache dir.
+    pub fn cache_dir(&self) -> Result<PathBuf> {
+        Ok(paths::get_cache_dir(self.general.cache_dir.as_deref())?)
+    }
+}
+
+/// Looks up `server` in the `[servers]` table of the global config,
+/// falling back to the literal value if it isn't an alias.
+pub fn resolve_server(opts: &GlobalOptions, server: &str) -> String {
+    opts.servers.as_ref()
+        .and_then(|servers| servers.get(server))
+        .cloned()
+        .unwrap_or_else(|| server.to_owned())
+}
+
+/// The `protocol` in `[credentials]` of the host of `server` (resolved already), if there is one.
+pub fn server_protocol(opts: &GlobalOptions, server: &str) -> Option<Protocol> {
+    let host = url_origin(server).1?;
+    host_credentials(opts.credentials.as_ref()?, &host)?.protocol
+}
+
+/// The `kind` in `[credentials]` of `host`, github.com and gitlab.com are known without one.
+pub fn server_kind(opts: &GlobalOptions, host: &str) -> Option<ServerKind> {
+    let configured = opts.credentials.as_ref().and_then(|credentials| host_credentials(credentials, host)?.kind);
+    configured.or(match host {
+        "github.com" => Some(ServerKind::Github),
+        "gitlab.com" => Some(ServerKind::Gitlab),
+        _ => None,
+    })
+}
+
+/// Moves a legacy `$HOME/.deprc` to the current location of the global config.
+pub fn migrate_global_config(reporter: &dyn Reporter) -> Result<()> {
+    let legacy = paths::get_legacy_global_config_path()?;
+    let path = paths::get_default_global_config_path()?;
+    if !legacy.exists() {
+        reporter.info(&format!("Nothing to migrate, \"{}\" doesn't exist", legacy.to_string_lossy()));
+        return Ok(());
+    }
+    if path.exists() {
+        return Err(Error::msg(format!(
+            "\"{}\" already exists, remove one of the configs manually",
+            path.to_string_lossy()
+        )));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(&legacy, &path)?;
+    std::fs::remove_file(&legacy)?;
+    reporter.info(&format!("Moved \"{}\" to \"{}\"", legacy.to_string_lossy(), path.to_string_lossy()));
+    Ok(())
+}
+
+pub fn merge_config(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_config(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Only used to read the `[config]` section of a manifest, before the manifest is read completely.
+#[derive(Deserialize)]
+struct ManifestConfig {
+    config: Option<toml::Value>,
+}
+
+/// Reads the global config, creating it on the first run, and applies the overrides of the project:
+/// first a `.deprc` in the project directory, then the `[config]` section of the manifest,
+/// then the environment variables. Settings of the organization policy file win over all of them.
+///
+/// `explicit_config` is the config given with `--config`, which has to exist.
+pub fn load_global_options(explicit_config: Option<&Path>, manifest_path: &Path, reporter: &dyn Reporter) -> Result<GlobalOptions> {
+    let mut config = toml::Value::try_from(GlobalOptions::default())?;
+    let global_config_path = paths::get_global_config_path(explicit_config);
+    match &global_config_path {
+        Ok(global_config_path) => {
+            if !global_config_path.exists() && paths::get_explicit_config_path(explicit_config).is_some() {
+                return Err(Error::msg(format!(
+                    "Config file \"{}\" doesn't exist",
+                    global_config_path.to_string_lossy()
+                )));
+            } else if !global_config_path.exists() {
+                if let Some(parent) = global_config_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(global_config_path)?;
+                let val = toml::ser::to_string_pretty(&GlobalOptions::default())?;
+
+                file.write_all(val.as_bytes())?;
+                file.flush()?;
+                reporter.warn("Initializing global configuration.");
+            } else {
+                config = toml::de::from_str(&std::fs::read_to_string(global_config_path)?)?;
+            }
+        }
+        _ => {
+            reporter.warn("Could not get homedir, using default global config");
+        }
+    };
+
+    let project_config_path = Path::new("./.deprc");
+    let is_global = match &global_config_path {
+        Ok(global) => project_config_path.canonicalize().ok() == global.canonicalize().ok(),
+        Err(_) => false,
+    };
+    if project_config_path.exists() && !is_global {
+        merge_config(&mut config, toml::de::from_str(&std::fs::read_to_string(project_config_path)?)?);
+    }
+
+    if manifest_path.exists() {
+        let content = std::fs::read_to_string(manifest_path)?;
+        if let Ok(ManifestConfig { config: Some(overrides) }) = parse(&content, ManifestFormat::from_path(manifest_path)) {
+            merge_config(&mut config, overrides);
+        }
+    }
+
+    let mut opts: GlobalOptions = match config.try_into() {
+        Ok(opts) => opts,
+        Err(e) => return Err(Error::Config(e.to_string())),
+    };
+
+    if let Some(key) = env_override("DEP_SSH_KEY") {
+        let protected = opts.ssh.as_ref().map(|ssh| ssh.protected).unwrap_or(false);
+        opts.ssh = Some(SshOptions {
+            private: PathBuf::from(&key),
+            public: PathBuf::from(format!("{}.pub", key)),
+            protected,
+        });
+    }
+    if let Some(cache_dir) = env_override("DEP_CACHE") {
+        opts.general.cache_dir = Some(PathBuf::from(cache_dir));
+    }
+    if let Some(offline) = env_override("DEP_OFFLINE") {
+        let offline = match offline.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => return Err(Error::Config(format!("invalid value for DEP_OFFLINE: \"{}\"", offline))),
+        };
+        opts.network.get_or_insert_with(NetworkOptions::default).offline = Some(offline);
+    }
+
+    if let Some(locked) = read_policy_file()? {
+        let mut config = toml::Value::try_from(&opts)?;
+        merge_config(&mut config, locked);
+        opts = match config.try_into() {
+            Ok(opts) => opts,
+            Err(e) => return Err(Error::Config(format!("policy file: {}", e))),
+        };
+    }
+
+    Ok(opts)
+}
+
+/// The settings of the organization policy files, if there are any. A file given with `DEP_POLICY` has to exist.
+fn read_policy_file() -> Result<Option<toml::Value>> {
+    let system = paths::get_system_policy_path().ok().filter(|path| path.exists());
+    merge_policies(system.as_deref(), paths::get_env_policy_path().as_deref())
+}
+
+/// The system policy merged over the one of `DEP_POLICY`, so the latter can add locks but never
+/// change or drop the ones of the system.
+fn merge_policies(system: Option<&Path>, extra: Option<&Path>) -> Result<Option<toml::Value>> {
+    let mut policy = match extra {
+        Some(path) if !path.exists() => {
+            return Err(Error::msg(format!("Policy file \"{}\" doesn't exist", path.to_string_lossy())));
+        }
+        Some(path) => Some(toml::de::from_str(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
+    if let Some(path) = system {
+        let locked = toml::de::from_str(&std::fs::read_to_string(path)?)?;
+        match &mut policy {
+            Some(policy) => merge_config(policy, locked),
+            None => policy = Some(locked),
+        }
+    }
+    Ok(policy)
+}
+
+/// Reads a setting from the environment. Empty variables count as unset.
+pub fn env_override(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Applies the environment overrides of manifest values, `DEP_LIB_DIR` and `DEP_GIT_SERVER`.
+pub fn apply_env_overrides(man: &mut TomlManifest) {
+    if let Some(lib_dir) = env_override("DEP_LIB_DIR") {
+        man.project.lib_dir = Some(PathBuf::from(lib_dir));
+    }
+    if let Some(git_server) = env_override("DEP_GIT_SERVER") {
+        man.project.git_server = Some(git_server);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dep-config-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn policy_of(policy: toml::Value) -> PolicyOptions {
+        let mut config = toml::Value::try_from(GlobalOptions::default()).unwrap();
+        merge_config(&mut config, policy);
+        let opts: GlobalOptions = config.try_into().unwrap();
+        opts.policy.unwrap()
+    }
+
+    #[test]
+    fn env_policies_cannot_unlock_the_system_policy() {
+        let system = write("system", "[policy]\nforbid-http = true\n\n[policy.mirrors]\n'https://github.com/' = 'ssh://git@git.corp.com/github/'\n");
+        let empty = write("empty", "");
+        let policy = policy_of(merge_policies(Some(&system), Some(&empty)).unwrap().unwrap());
+        assert_eq!(policy.forbid_http, Some(true));
+        assert_eq!(policy.mirrors.unwrap()["https://github.com/"], "ssh://git@git.corp.com/github/");
+
+        let unlocking = write("unlocking", "[policy]\nforbid-http = false\nrequire-signatures = true\n");
+        let policy = policy_of(merge_policies(Some(&system), Some(&unlocking)).unwrap().unwrap());
+        assert_eq!(policy.forbid_http, Some(true));
+        assert_eq!(policy.require_signatures, Some(true));
+        assert!(policy.mirrors.is_some());
+
+        let policy = policy_of(merge_policies(None, Some(&unlocking)).unwrap().unwrap());
+        assert_eq!(policy.forbid_http, Some(false));
+
+        assert!(merge_policies(Some(&system), Some(Path::new("/nonexistent/policy.toml"))).is_err());
+        assert!(merge_policies(None, None).unwrap().is_none());
+        for path in [system, empty, unlocking] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Checks of the environment dep runs in, used by `dep doctor`.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{resolve_server, GlobalOptions};
+use crate::git::default_ssh_keys;
+use crate::manifest::TomlManifest;
+use crate::paths::{get_global_config_path, get_home_dir, get_home_dir_env_var, make_symlink, normalize, remove_link};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    // dep works, but some features won't
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub check: String,
+    pub status: Status,
+    pub message: String,
+    // what to do about it, for warnings and errors
+    pub fix: Option<String>,
+}
+
+impl Diagnostic {
+    fn ok<S: Into<String>>(check: &str, message: S) -> Diagnostic {
+        Diagnostic { check: check.to_owned(), status: Status::Ok, message: message.into(), fix: None }
+    }
+
+    fn problem<S: Into<String>, F: Into<String>>(check: &str, status: Status, message: S, fix: F) -> Diagnostic {
+        Diagnostic { check: check.to_owned(), status, message: message.into(), fix: Some(fix.into()) }
+    }
+}
+
+fn check_home_dir() -> Diagnostic {
+    match get_home_dir() {
+        Ok(home) => Diagnostic::ok("home dir", home),
+        Err(_) => Diagnostic::problem("home dir", Status::Error,
+            format!("{} is not set", get_home_dir_env_var()),
+            format!("set {} to your home directory", get_home_dir_env_var())),
+    }
+}
+
+fn check_global_config(explicit_config: Option<&Path>) -> Diagnostic {
+    match get_global_config_path(explicit_config) {
+        Ok(path) if path.exists() => Diagnostic::ok("global config", path.to_string_lossy()),
+        Ok(path) => Diagnostic::problem("global config", Status::Warning,
+            format!("\"{}\" doesn't exist", path.to_string_lossy()),
+            "run any dep command to create the default config"),
+        Err(e) => Diagnostic::problem("global config", Status::Error, e.to_string(),
+            "set XDG_CONFIG_HOME or pass --config"),
+    }
+}
+
+fn check_key(check: &str, path: &Path, is_private: bool) -> Diagnostic {
+    let path = normalize(&path);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => return Diagnostic::problem(check, Status::Error,
+            format!("\"{}\" can't be read: {}", path.to_string_lossy(), e),
+            "create a key with ssh-keygen, or set ssh.private and ssh.public in the global config"),
+    };
+    let valid = if is_private {
+        content.trim_start().starts_with("-----BEGIN ") && content.contains("PRIVATE KEY-----")
+    } else {
+        content.split_whitespace().next().map(|kind| kind.starts_with("ssh-") || kind.starts_with("ecdsa-")).unwrap_or(false)
+    };
+    if valid {
+        Diagnostic::ok(check, path.to_string_lossy())
+    } else {
+        Diagnostic::problem(check, Status::Error,
+            format!("\"{}\" is not a{} ssh key", path.to_string_lossy(), if is_private { " private" } else { " public" }),
+            "point ssh.private and ssh.public of the global config to the right files")
+    }
+}
+
+fn check_ssh_keys(opts: &GlobalOptions) -> Vec<Diagnostic> {
+    match &opts.ssh {
+        Some(ssh) => vec![check_key("ssh private key", &ssh.private, true), check_key("ssh public key", &ssh.public, false)],
+        None => match default_ssh_keys().first() {
+            Some(ssh) => vec![Diagnostic::ok("ssh keys", format!("no [ssh] section, the default keys of ~/.ssh are tried, starting with \"{}\"",
+                ssh.private.to_string_lossy()))],
+            None => vec![Diagnostic::problem("ssh keys", Status::Warning, "no [ssh] section in the global config and no key in ~/.ssh",
+                "create a key with ssh-keygen, or add [ssh] with private and public")],
+        },
+    }
+}
+
+/// Host and port dep connects to for urls of the server.
+fn server_address(server: &str) -> Option<(String, u16)> {
+    let (default_port, rest) = match server.split_once("://") {
+        Some(("https", rest)) => (443, rest),
+        Some(("http", rest)) => (80, rest),
+        Some(("git", rest)) => (9418, rest),
+        Some((_, rest)) => (22, rest),
+        // scp like syntax, user@host
+        None => return Some((server.rsplit('@').next()?.split(':').next()?.to_owned(), 22)),
+    };
+    let authority = rest.split('/').next()?.rsplit('@').next()?;
+    match authority.split_once(':') {
+        Some((host, port)) => Some((host.to_owned(), port.parse().ok()?)),
+        None => Some((authority.to_owned(), default_port)),
+    }
+}
+
+fn check_server(server: &str) -> Diagnostic {
+    let check = format!("server {}", server);
+    let (host, port) = match server_address(server) {
+        Some(address) => address,
+        None => return Diagnostic::problem(&check, Status::Error, "not a valid server", "use a host, user@host or an url like ssh://host"),
+    };
+    let addresses = match (host.as_str(), port).to_socket_addrs() {
+        Ok(addresses) => addresses.collect::<Vec<_>>(),
+        Err(e) => return Diagnostic::problem(&check, Status::Error, format!("{} can't be resolved: {}", host, e),
+            "check the spelling of the server and your dns settings"),
+    };
+    let reachable = addresses.iter().any(|address| TcpStream::connect_timeout(address, Duration::from_secs(5)).is_ok());
+    if reachable {
+        Diagnostic::ok(&check, format!("{}:{} is reachable", host, port))
+    } else {
+        Diagnostic::problem(&check, Status::Error, format!("{}:{} is not reachable", host, port),
+            "check your network connection, vpn or proxy, or use --offline")
+    }
+}
+
+fn check_servers(opts: &GlobalOptions, man: Option<&TomlManifest>, offline: bool) -> Vec<Diagnostic> {
+    let mut servers = opts.servers.iter().flatten().map(|(_, server)| server.clone()).collect::<Vec<_>>();
+    if let Some(server) = man.and_then(|man| man.project.git_server.as_ref()) {
+        servers.push(resolve_server(opts, server));
+    }
+    servers.sort();
+    servers.dedup();
+    if offline {
+        return servers.iter().map(|server| Diagnostic::ok(&format!("server {}", server), "not checked in offline mode")).collect();
+    }
+    servers.iter().map(|server| check_server(server)).collect()
+}
+
+fn check_symlinks() -> Diagnostic {
+    let dir = std::env::temp_dir().join(format!("dep-doctor-{}", std::process::id()));
+    let link = dir.join("link");
+    let result = std::fs::create_dir_all(dir.join("target"))
+        .and_then(|_| make_symlink(dir.join("target"), &link))
+        .and_then(|_| remove_link(&link));
+    let _ = remove_dir_all::remove_dir_all(&dir);
+    match result {
+        Ok(_) => Diagnostic::ok("symlinks", "directory symlinks can be created"),
+        Err(e) => Diagnostic::problem("symlinks", Status::Warning, format!("directory symlinks can't be created: {}", e),
+            if cfg!(windows) {
+                "enable the developer mode of windows or run as administrator, path dependencies need symlinks"
+            } else {
+                "path dependencies need symlinks, check the permissions of the lib dir"
+            }),
+    }
+}
+
+fn check_cache(opts: &GlobalOptions) -> Diagnostic {
+    let cache = match opts.cache_dir() {
+        Ok(cache) => cache,
+        Err(e) => return Diagnostic::problem("cache", Status::Error, e.to_string(), "set XDG_CACHE_HOME or DEP_CACHE"),
+    };
+    let probe = cache.join(format!(".dep-doctor-{}", std::process::id()));
+    let result = std::fs::create_dir_all(&cache)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(_) => Diagnostic::ok("cache", cache.to_string_lossy()),
+        Err(e) => Diagnostic::problem("cache", Status::Error, format!("\"{}\" is not writable: {}", cache.to_string_lossy(), e),
+            "fix the permissions of the cache dir, or set DEP_CACHE to a writable directory"),
+    }
+}
+
+/// Runs every check of the environment. The manifest is only used for the git server of the project,
+/// it gets validated separately.
+pub fn diagnose(opts: &GlobalOptions, explicit_config: Option<&Path>, man: Option<&TomlManifest>, offline: bool) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![check_home_dir(), check_global_config(explicit_config)];
+    diagnostics.extend(check_ssh_keys(opts));
+    diagnostics.extend(check_servers(opts, man, offline));
+    diagnostics.push(check_symlinks());
+    diagnostics.push(check_cache(opts));
+    diagnostics
+}
@@ -0,0 +1,424 @@
+//! Files for build systems with the paths of the vendored dependencies, written by `dep emit`.
+//!
+//! Files written to their default path are regenerated by every `dep update`, as long as they have the generated header.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::exec::{dependency_env, dir_variable};
+use crate::history::vendored_commit;
+use crate::manifest::{dependency_dir, get_lib_dir, TomlManifest};
+use crate::paths::{absolute_path, relative_path};
+use crate::report::Reporter;
+use crate::update::git_url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitFormat {
+    // shell variables, for `source` and `.` in scripts
+    Env,
+    // java style properties, name=path
+    Props,
+    // cmake variables and add_subdirectory calls, for include()
+    Cmake,
+    // wrap files in the subprojects dir
+    Meson,
+    // a macro with a local repository for every dependency, for WORKSPACE files
+    Bazel,
+    // make variables and a deps target, for include
+    Make,
+    // a property sheet for visual studio projects
+    Msbuild,
+    // [patch] sections for the crates of vendored git dependencies, for cargo --config
+    CargoPatch,
+    // a block of replace directives in an existing go.mod
+    GoReplace,
+}
+
+impl EmitFormat {
+    pub const ALL: [EmitFormat; 9] = [
+        EmitFormat::Env, EmitFormat::Props, EmitFormat::Cmake, EmitFormat::Meson, EmitFormat::Bazel, EmitFormat::Make, EmitFormat::Msbuild,
+        EmitFormat::CargoPatch, EmitFormat::GoReplace,
+    ];
+
+    /// Where the file (or directory, for meson) goes, relative to the project.
+    pub fn default_path(self) -> &'static str {
+        match self {
+            EmitFormat::Env => ".deps.env",
+            EmitFormat::Props => "deps.properties",
+            EmitFormat::Cmake => "deps.cmake",
+            EmitFormat::Meson => "subprojects",
+            EmitFormat::Bazel => "deps.bzl",
+            EmitFormat::Make => "deps.mk",
+            EmitFormat::Msbuild => "Deps.props",
+            EmitFormat::CargoPatch => "deps-patch.toml",
+            EmitFormat::GoReplace => "go.mod",
+        }
+    }
+}
+
+/// Every dependency with its absolute directory.
+fn dependency_dirs(man: &TomlManifest, opts: &GlobalOptions) -> Result<Vec<(String, PathBuf)>> {
+    let libdir = get_lib_dir(man, opts);
+    let mut dirs = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        dirs.push((name.clone(), absolute_path(dependency_dir(&libdir, name, dep))?));
+    }
+    Ok(dirs)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn properties_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(':', "\\:").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+/// Cmake takes forward slashes on every platform.
+fn cmake_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "/").replace('"', "\\\"").replace('$', "\\$"))
+}
+
+fn cmake(man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let mut content = String::new();
+    for (name, path) in dependency_env(man, opts)? {
+        content += &format!("set({} {})\n", name, cmake_quote(&path.to_string_lossy()));
+    }
+    content += "\noption(DEP_ADD_SUBDIRECTORIES \"Adds every dependency with a CMakeLists.txt\" OFF)\n";
+    content += "if(DEP_ADD_SUBDIRECTORIES)\n";
+    for (name, dir) in dependency_dirs(man, opts)? {
+        // checked when the file is written, "dep update" writes it again after new checkouts
+        if dir.join("CMakeLists.txt").exists() {
+            let binary_dir = name.replace(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'), "_");
+            content += &format!("  add_subdirectory(\"${{{}}}\" \"${{CMAKE_BINARY_DIR}}/deps/{}\")\n", dir_variable(&name), binary_dir);
+        }
+    }
+    content += "endif()\n";
+    Ok(content)
+}
+
+fn starlark_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Repositories with a WORKSPACE or MODULE.bazel become local repositories,
+/// the others get a build file exposing all their files as `:all`.
+fn bazel(man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let mut content = String::from("\ndef dep_repositories():\n");
+    let dirs = dependency_dirs(man, opts)?;
+    if dirs.is_empty() {
+        content += "    pass\n";
+    }
+    for (name, dir) in dirs {
+        let name = name.replace(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'), "_");
+        let is_bazel = ["WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"].iter().any(|file| dir.join(file).exists());
+        if is_bazel {
+            content += "    native.local_repository(\n";
+        } else {
+            content += "    native.new_local_repository(\n";
+        }
+        content += &format!("        name = {},\n", starlark_quote(&name));
+        content += &format!("        path = {},\n", starlark_quote(&dir.to_string_lossy()));
+        if !is_bazel {
+            content += "        build_file_content = 'filegroup(name = \"all\", srcs = glob([\"**\"]), visibility = [\"//visibility:public\"])',\n";
+        }
+        content += "    )\n";
+    }
+    Ok(content)
+}
+
+fn make_escape(value: &str) -> String {
+    value.replace('$', "$$").replace('#', "\\#")
+}
+
+fn make(man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let mut content = String::new();
+    for (name, path) in dependency_env(man, opts)? {
+        content += &format!("{} := {}\n", name, make_escape(&path.to_string_lossy()));
+    }
+    content += "\n.PHONY: deps\ndeps:\n\tdep update\n";
+    Ok(content)
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Name of the msbuild property with the directory of a dependency, like `DepSomeLibDir` for `some-lib`.
+fn msbuild_property(name: &str) -> String {
+    let words = name.split(|c: char| !c.is_ascii_alphanumeric()).filter(|word| !word.is_empty());
+    let name = words.map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|first| first.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+    }).collect::<String>();
+    format!("Dep{}Dir", name)
+}
+
+/// Properties with the directories, and their include dir (or the directory itself) as include paths.
+fn msbuild(man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let dirs = dependency_dirs(man, opts)?;
+    let mut content = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    content += MSBUILD_HEADER;
+    content += "<Project xmlns=\"http://schemas.microsoft.com/developer/msbuild/2003\">\n";
+    content += "  <PropertyGroup>\n";
+    content += &format!("    <DepLibDir>{}</DepLibDir>\n", xml_escape(&absolute_path(get_lib_dir(man, opts))?.to_string_lossy()));
+    for (name, dir) in &dirs {
+        let property = msbuild_property(name);
+        content += &format!("    <{}>{}</{}>\n", property, xml_escape(&dir.to_string_lossy()), property);
+    }
+    content += "  </PropertyGroup>\n";
+    let includes = dirs.iter().map(|(name, dir)| {
+        let property = msbuild_property(name);
+        if dir.join("include").is_dir() { format!("$({})\\include", property) } else { format!("$({})", property) }
+    }).collect::<Vec<_>>();
+    content += "  <ItemDefinitionGroup>\n";
+    content += "    <ClCompile>\n";
+    content += &format!("      <AdditionalIncludeDirectories>{}%(AdditionalIncludeDirectories)</AdditionalIncludeDirectories>\n",
+        includes.iter().map(|include| format!("{};", include)).collect::<String>());
+    content += "    </ClCompile>\n";
+    content += "  </ItemDefinitionGroup>\n";
+    content += "</Project>\n";
+    Ok(content)
+}
+
+fn toml_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The crates of a checkout with their directories, the members of a workspace included.
+/// Members are only expanded for globs at the end, like `crates/*`.
+fn crates(dir: &Path) -> Vec<(String, PathBuf)> {
+    let manifest = match std::fs::read_to_string(dir.join("Cargo.toml")).ok().and_then(|content| toml::from_str::<toml::Value>(&content).ok()) {
+        Some(manifest) => manifest,
+        None => return Vec::new(),
+    };
+    let mut crates = Vec::new();
+    if let Some(name) = manifest.get("package").and_then(|package| package.get("name")).and_then(|name| name.as_str()) {
+        crates.push((name.to_owned(), dir.to_path_buf()));
+    }
+    let members = manifest.get("workspace").and_then(|workspace| workspace.get("members")).and_then(|members| members.as_array());
+    for member in members.into_iter().flatten().filter_map(|member| member.as_str()) {
+        let dirs = match member.strip_suffix("/*") {
+            Some(parent) => {
+                let mut dirs = std::fs::read_dir(dir.join(parent)).map(|entries| entries.flatten().map(|entry| entry.path()).collect::<Vec<_>>()).unwrap_or_default();
+                dirs.sort();
+                dirs
+            }
+            None => vec![dir.join(member)],
+        };
+        for member in dirs.into_iter().filter(|member| member != dir) {
+            crates.extend(self::crates(&member).into_iter().filter(|(_, path)| path == &member));
+        }
+    }
+    crates
+}
+
+/// A [patch] section for the url of every vendored git dependency with crates, pointing them to the checkout.
+fn cargo_patch(man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let libdir = get_lib_dir(man, opts);
+    let mut content = String::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        if dep.path.is_some() || dep.url.is_some() || dep.release.is_some() {
+            continue;
+        }
+        let url = match git_url(opts, man.project.git_server.as_ref(), dep) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+        let crates = crates(&absolute_path(dependency_dir(&libdir, name, dep))?);
+        if crates.is_empty() {
+            continue;
+        }
+        content += &format!("\n[patch.{}]\n", toml_quote(&url));
+        for (name, dir) in crates {
+            content += &format!("{} = {{ path = {} }}\n", name, toml_quote(&dir.to_string_lossy()));
+        }
+    }
+    Ok(content)
+}
+
+/// The module path of the go.mod in `dir`.
+fn go_module(dir: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(dir.join("go.mod")).ok()?;
+    content.lines()
+        .find_map(|line| line.trim().strip_prefix("module "))
+        .map(|module| module.split("//").next().unwrap_or_default().trim().trim_matches('"').to_owned())
+}
+
+/// Where the replace directives start and end in go.mod.
+const GO_REPLACE_START: &str = "// generated by dep, changes get overwritten by \"dep update\"";
+const GO_REPLACE_END: &str = "// end of dep";
+
+/// Replace directives for every vendored go module, with paths relative to the directory of the go.mod.
+fn go_replace(man: &TomlManifest, opts: &GlobalOptions, go_mod: &Path) -> Result<String> {
+    let base = absolute_path(go_mod)?.parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+    let mut content = format!("{}\nreplace (\n", GO_REPLACE_START);
+    for (_, dir) in dependency_dirs(man, opts)? {
+        if let Some(module) = go_module(&dir) {
+            // go only takes relative paths starting with ./ or ../
+            let path = relative_path(&base, &dir).to_string_lossy().replace('\\', "/");
+            let path = if path.starts_with("../") { path } else { format!("./{}", path) };
+            content += &format!("\t{} => {}\n", module, path);
+        }
+    }
+    content += &format!(")\n{}\n", GO_REPLACE_END);
+    Ok(content)
+}
+
+/// The go.mod at `path` with the block of replace directives swapped out, or appended if it has none.
+fn go_mod_with(path: &Path, block: &str) -> Result<String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return Err(Error::msg(format!("\"{}\" doesn't exist, \"-o -\" prints the replace directives instead", path.to_string_lossy()))),
+    };
+    let start = content.find(GO_REPLACE_START);
+    let end = content.find(GO_REPLACE_END).map(|end| end + GO_REPLACE_END.len());
+    Ok(match (start, end) {
+        (Some(start), Some(end)) if start < end => {
+            let rest = content[end..].strip_prefix('\n').unwrap_or(&content[end..]);
+            format!("{}{}{}", &content[..start], block, rest)
+        }
+        _ => {
+            let separator = if content.ends_with('\n') { "\n" } else { "\n\n" };
+            format!("{}{}{}", content, separator, block)
+        }
+    })
+}
+
+/// Marks generated files, so they aren't mistaken for hand written ones.
+const HEADER: &str = "# generated by dep, changes get overwritten by \"dep update\"\n";
+/// The same for msbuild, whose header comes after the xml declaration.
+const MSBUILD_HEADER: &str = "<!-- generated by dep, changes get overwritten by \"dep update\" -->\n";
+
+/// One wrap-git file per git checkout, cloned from the vendored checkout at its current commit.
+fn meson_wraps(man: &TomlManifest, opts: &GlobalOptions, dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut wraps = Vec::new();
+    for (name, path) in dependency_dirs(man, opts)? {
+        // archives and plain directories can't be cloned
+        let commit = match vendored_commit(&path) {
+            Some(commit) => commit,
+            None => continue,
+        };
+        let content = format!("{}[wrap-git]\nurl = {}\nrevision = {}\ndepth = 1\n", HEADER, path.to_string_lossy(), commit);
+        wraps.push((dir.join(format!("{}.wrap", name)), content));
+    }
+    Ok(wraps)
+}
+
+/// The files of `format` with their content, `path` is a directory for formats with more than one file.
+fn files(format: EmitFormat, man: &TomlManifest, opts: &GlobalOptions, path: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut content = String::from(HEADER);
+    match format {
+        EmitFormat::Env => {
+            for (name, path) in dependency_env(man, opts)? {
+                content += &format!("export {}={}\n", name, shell_quote(&path.to_string_lossy()));
+            }
+        }
+        EmitFormat::Props => {
+            for (name, dir) in dependency_dirs(man, opts)? {
+                content += &format!("{}={}\n", properties_escape(&name), properties_escape(&dir.to_string_lossy()));
+            }
+        }
+        EmitFormat::Cmake => content += &cmake(man, opts)?,
+        EmitFormat::Meson => return meson_wraps(man, opts, path),
+        // xml has no # comments
+        EmitFormat::Msbuild => return Ok(vec![(path.to_path_buf(), msbuild(man, opts)?)]),
+        EmitFormat::Bazel => content += &bazel(man, opts)?,
+        EmitFormat::Make => content += &make(man, opts)?,
+        EmitFormat::CargoPatch => content += &cargo_patch(man, opts)?,
+        // goes into go.mod, which has a header of its own
+        EmitFormat::GoReplace if is_stdout(path) => return Ok(vec![(path.to_path_buf(), go_replace(man, opts, Path::new(format.default_path()))?)]),
+        EmitFormat::GoReplace => return Ok(vec![(path.to_path_buf(), go_mod_with(path, &go_replace(man, opts, path)?)?)]),
+    }
+    Ok(vec![(path.to_path_buf(), content)])
+}
+
+/// `-` prints the file instead of writing it.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Writes the file for `format` to `path`, or the files into the directory `path` for meson.
+/// With `-` nothing is written, the content is returned for the caller to print.
+pub fn emit(format: EmitFormat, man: &TomlManifest, opts: &GlobalOptions, path: &Path) -> Result<Option<String>> {
+    let files = files(format, man, opts, path)?;
+    if is_stdout(path) {
+        if format == EmitFormat::Meson {
+            return Err(Error::msg("Meson wraps are one file per dependency, they can't be printed"));
+        }
+        return Ok(Some(files.into_iter().map(|(_, content)| content).collect()));
+    }
+    if format == EmitFormat::Meson {
+        std::fs::create_dir_all(path)?;
+        // wraps of dependencies which are gone
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?.path();
+            if is_generated_wrap(&entry) && !files.iter().any(|(file, _)| file == &entry) {
+                std::fs::remove_file(&entry)?;
+            }
+        }
+    }
+    for (path, content) in files {
+        std::fs::write(path, content)?;
+    }
+    Ok(None)
+}
+
+fn is_generated_wrap(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "wrap").unwrap_or(false)
+        && std::fs::read_to_string(path).map(|content| content.starts_with(HEADER)).unwrap_or(false)
+}
+
+/// Whether dep wrote `format` to `path` before, so hand written files at the default path are left alone.
+/// The subprojects dir of meson only counts, if it has generated wraps, and go.mod only if it has the replace directives.
+fn is_emitted(format: EmitFormat, path: &Path) -> bool {
+    if format == EmitFormat::Meson {
+        return std::fs::read_dir(path).map(|entries| entries.flatten().any(|entry| is_generated_wrap(&entry.path()))).unwrap_or(false);
+    }
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return false,
+    };
+    match format {
+        EmitFormat::GoReplace => content.contains(GO_REPLACE_START),
+        EmitFormat::Msbuild => content.contains(MSBUILD_HEADER),
+        _ => content.starts_with(HEADER),
+    }
+}
+
+/// Regenerates the files which dep wrote to their default path, after the dependencies changed.
+pub fn refresh(man: &TomlManifest, opts: &GlobalOptions, reporter: &dyn Reporter) -> Result<()> {
+    for format in EmitFormat::ALL.iter().copied() {
+        let path = Path::new(format.default_path());
+        if is_emitted(format, path) {
+            emit(format, man, opts, path)?;
+            reporter.info(&format!("Regenerated \"{}\"", path.to_string_lossy()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_generated_files_count_as_emitted() {
+        let dir = std::env::temp_dir().join(format!("dep-emit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let make = dir.join("deps.mk");
+        assert!(!is_emitted(EmitFormat::Make, &make));
+        std::fs::write(&make, "MY_HANDWRITTEN := 1\n").unwrap();
+        assert!(!is_emitted(EmitFormat::Make, &make));
+        std::fs::write(&make, format!("{}DEPS :=\n", HEADER)).unwrap();
+        assert!(is_emitted(EmitFormat::Make, &make));
+
+        let props = dir.join("Deps.props");
+        std::fs::write(&props, "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<Project />\n").unwrap();
+        assert!(!is_emitted(EmitFormat::Msbuild, &props));
+        std::fs::write(&props, format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n{}<Project />\n", MSBUILD_HEADER)).unwrap();
+        assert!(is_emitted(EmitFormat::Msbuild, &props));
+        remove_dir_all::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Git(git2::Error),
+    Http(Box<ureq::Error>),
+    // an environment variable, like HOME, isn't set
+    Env(std::env::VarError),
+    // (de)serialization of toml, json or yaml
+    Format(String),
+    Archive(zip::result::ZipError),
+    // the manifest can't be read, the message already contains a "did you mean" hint if possible
+    Manifest(String),
+    Config(String),
+    // an error in, or thrown by, the dep.rhai script
+    Script(String),
+    // cancelled with ctrl-c, see [`crate::Credentials::with_cancel`]
+    Interrupted,
+    // a passphrase or login is needed, but asking for it isn't allowed, see [`crate::Credentials::with_interactive`]
+    CredentialsRequired(String),
+    // a confirmation or answer is needed, but asking for it isn't allowed
+    InputRequired(String),
+    Message(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn msg<S: Into<String>>(message: S) -> Error {
+        Error::Message(message.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Git(e) => write!(f, "{}", e.message()),
+            Error::Http(e) => write!(f, "{}", e),
+            Error::Env(e) => write!(f, "{}", e),
+            Error::Format(message) => write!(f, "{}", message),
+            Error::Archive(e) => write!(f, "{}", e),
+            Error::Manifest(message) => write!(f, "{}", message),
+            Error::Config(message) => write!(f, "Invalid configuration: {}", message),
+            Error::Script(message) => write!(f, "Hook failed: {}", message),
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::CredentialsRequired(message) => write!(f, "{}", message),
+            Error::InputRequired(message) => write!(f, "{}", message),
+            Error::Message(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Error {
+        // the credentials callback can only fail with git errors
+        if e.message().starts_with(crate::git::LOGIN_REQUIRED) {
+            return Error::CredentialsRequired(e.message().to_owned());
+        }
+        Error::Git(e)
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Error {
+        Error::Http(Box::new(e))
+    }
+}
+
+impl From<std::env::VarError> for Error {
+    fn from(e: std::env::VarError) -> Error {
+        Error::Env(e)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(e: zip::result::ZipError) -> Error {
+        Error::Archive(e)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<toml_edit::TomlError> for Error {
+    fn from(e: toml_edit::TomlError) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Format(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Error {
+        Error::Format(e.to_string())
+    }
+}
@@ -0,0 +1,87 @@
+//! Running commands in, or with the paths of, the vendored dependencies, used by `dep foreach` and `dep exec`.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::manifest::{dependency_dir, dependency_order, get_lib_dir, TomlDependency, TomlManifest};
+use crate::paths::absolute_path;
+use crate::report::Reporter;
+use crate::update::git_url;
+
+/// Where a dependency is fetched from: its archive url, or its git url. Path dependencies and
+/// unresolved registry dependencies have none.
+fn source_url(man: &TomlManifest, opts: &GlobalOptions, dep: &TomlDependency) -> Option<String> {
+    if dep.path.is_some() {
+        return None;
+    }
+    match &dep.url {
+        Some(url) => Some(url.clone()),
+        None => git_url(opts, man.project.git_server.as_ref(), dep).ok(),
+    }
+}
+
+/// Runs `command` in the directory of every vendored dependency, with `DEP_NAME`, `DEP_PATH` and `DEP_URL` set.
+/// Dependencies come after the ones they need. Stops at the first dependency the command fails in,
+/// dependencies which aren't vendored are skipped.
+pub fn foreach(man: &TomlManifest, opts: &GlobalOptions, command: &[String], reporter: &dyn Reporter) -> Result<()> {
+    let (program, args) = match command.split_first() {
+        Some(command) => command,
+        None => return Err(Error::msg("No command given")),
+    };
+    let libdir = get_lib_dir(man, opts);
+    let deps = match &man.dependencies {
+        Some(deps) => deps,
+        None => return Ok(()),
+    };
+    for name in &dependency_order(deps)? {
+        let dep = &deps[name];
+        let dst = dependency_dir(&libdir, name, dep);
+        if !dst.exists() {
+            reporter.info(&format!("Skipping \"{}\", it's not vendored", name));
+            continue;
+        }
+        reporter.info(&format!("Entering \"{}\"", name));
+        let dst = absolute_path(&dst)?;
+        let status = Command::new(program).args(args).current_dir(&dst)
+            .env("DEP_NAME", name)
+            .env("DEP_PATH", &dst)
+            .env("DEP_URL", source_url(man, opts, dep).unwrap_or_default())
+            .status()
+            .map_err(|e| Error::msg(format!("\"{}\" could not be started: {}", program, e)))?;
+        if !status.success() {
+            return Err(Error::msg(format!("\"{}\" failed in \"{}\" with {}", program, name, status)));
+        }
+    }
+    Ok(())
+}
+
+/// Name of the environment variable with the directory of a dependency, like `DEP_SOME_LIB_DIR` for `some-lib`.
+pub fn dir_variable(name: &str) -> String {
+    let name = name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect::<String>();
+    format!("DEP_{}_DIR", name)
+}
+
+/// The lib dir as `DEP_LIB_DIR` and the absolute directory of every dependency as [`dir_variable`].
+pub fn dependency_env(man: &TomlManifest, opts: &GlobalOptions) -> Result<Vec<(String, PathBuf)>> {
+    let libdir = get_lib_dir(man, opts);
+    let mut env = vec![("DEP_LIB_DIR".to_owned(), absolute_path(&libdir)?)];
+    for (name, dep) in man.dependencies.iter().flatten() {
+        env.push((dir_variable(name), absolute_path(dependency_dir(&libdir, name, dep))?));
+    }
+    Ok(env)
+}
+
+/// Runs `command` with [`dependency_env`] set and returns its exit code.
+pub fn exec(man: &TomlManifest, opts: &GlobalOptions, command: &[String]) -> Result<i32> {
+    let (program, args) = match command.split_first() {
+        Some(command) => command,
+        None => return Err(Error::msg("No command given")),
+    };
+    let status = Command::new(program).args(args)
+        .envs(dependency_env(man, opts)?)
+        .status()
+        .map_err(|e| Error::msg(format!("\"{}\" could not be started: {}", program, e)))?;
+    Ok(status.code().unwrap_or(1))
+}
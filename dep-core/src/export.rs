@@ -0,0 +1,163 @@
+//! Manifests of other tools generated from deps.toml, written by `dep export`.
+//!
+//! Exported files are regenerated by every `dep update`, as long as they still have the generated header,
+//! and so are the revisions an export staged.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::history::vendored_commit;
+use crate::manifest::{dependency_dir, get_lib_dir, TomlManifest};
+use crate::paths::absolute_path;
+use crate::report::Reporter;
+use crate::update::git_url;
+
+pub const GITMODULES: &str = ".gitmodules";
+
+const HEADER: &str = "# generated by dep, changes get overwritten by \"dep update\"\n";
+
+/// A git dependency as a submodule.
+struct Submodule {
+    name: String,
+    // relative to the project, with forward slashes
+    path: String,
+    url: String,
+    branch: Option<String>,
+    // the pinned revision, or the vendored commit
+    rev: Option<git2::Oid>,
+}
+
+/// Abbreviated revisions can only be expanded with the checkout.
+fn pinned_commit(dst: &Path, rev: &str) -> Result<git2::Oid> {
+    let expanded = git2::Repository::open(dst).ok()
+        .and_then(|repo| repo.revparse_single(rev).ok().map(|object| object.id()));
+    match expanded {
+        Some(oid) => Ok(oid),
+        None if rev.len() == 40 => Ok(git2::Oid::from_str(rev)?),
+        None => Err(Error::msg(format!("Revision \"{}\" can't be expanded, run \"dep update\" first", rev))),
+    }
+}
+
+fn submodules(man: &TomlManifest, opts: &GlobalOptions, reporter: &dyn Reporter) -> Result<Vec<Submodule>> {
+    let libdir = get_lib_dir(man, opts);
+    let mut submodules = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        if dep.path.is_some() || dep.url.is_some() || dep.release.is_some() {
+            continue;
+        }
+        let url = match git_url(opts, man.project.git_server.as_ref(), dep) {
+            Ok(url) => url,
+            Err(_) => {
+                reporter.info(&format!("Skipping \"{}\", it has no git url", name));
+                continue;
+            }
+        };
+        let dst = dependency_dir(&libdir, name, dep);
+        let rev = match &dep.rev {
+            Some(rev) => Some(pinned_commit(&dst, rev)?),
+            None => vendored_commit(&dst),
+        };
+        submodules.push(Submodule {
+            name: name.clone(),
+            path: dst.to_string_lossy().replace('\\', "/").trim_start_matches("./").to_owned(),
+            url,
+            branch: dep.branch.clone(),
+            rev,
+        });
+    }
+    Ok(submodules)
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Writes the git dependencies as `.gitmodules`. With `stage`, their revisions are also recorded
+/// in the index of the project's repository, like `git submodule add` does.
+pub fn export_submodules(man: &TomlManifest, opts: &GlobalOptions, path: &Path, stage: bool, reporter: &dyn Reporter) -> Result<()> {
+    write_submodules(man, opts, path, if stage { Some(false) } else { None }, reporter)
+}
+
+/// `stage` is none to leave the index alone, or whether to only update existing gitlinks.
+fn write_submodules(man: &TomlManifest, opts: &GlobalOptions, path: &Path, stage: Option<bool>, reporter: &dyn Reporter) -> Result<()> {
+    let submodules = submodules(man, opts, reporter)?;
+    let mut content = String::from(HEADER);
+    for submodule in &submodules {
+        content += &format!("[submodule {}]\n", quote(&submodule.name));
+        content += &format!("\tpath = {}\n", quote(&submodule.path));
+        content += &format!("\turl = {}\n", quote(&submodule.url));
+        if let Some(branch) = &submodule.branch {
+            content += &format!("\tbranch = {}\n", quote(branch));
+        }
+    }
+    std::fs::write(path, content)?;
+
+    if let Some(only_existing) = stage {
+        stage_gitlinks(&submodules, only_existing, reporter)?;
+    }
+    Ok(())
+}
+
+/// Records the revision of every submodule as a gitlink in the index of the project.
+fn stage_gitlinks(submodules: &[Submodule], only_existing: bool, reporter: &dyn Reporter) -> Result<()> {
+    let repo = git2::Repository::discover(".").map_err(|_| Error::msg("The project is not a git repository, nothing to stage"))?;
+    let workdir = match repo.workdir() {
+        Some(workdir) => absolute_path(workdir)?,
+        None => return Err(Error::msg("The repository of the project has no working tree")),
+    };
+    let mut index = repo.index()?;
+    let mut staged = 0;
+    for submodule in submodules {
+        let path = absolute_path(PathBuf::from(&submodule.path))?;
+        let relative = match path.strip_prefix(&workdir) {
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => return Err(Error::msg(format!("\"{}\" is outside of the repository of the project", submodule.path))),
+        };
+        let is_gitlink = index.get_path(Path::new(&relative), 0).map(|entry| entry.mode == 0o160000).unwrap_or(false);
+        if only_existing && !is_gitlink {
+            continue;
+        }
+        let rev = match submodule.rev {
+            Some(rev) => rev,
+            None => {
+                reporter.info(&format!("Not staging \"{}\", it's neither pinned nor vendored", submodule.name));
+                continue;
+            }
+        };
+        index.add(&git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            // gitlink
+            mode: 0o160000,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: rev,
+            flags: 0,
+            flags_extended: 0,
+            path: relative.into_bytes(),
+        })?;
+        staged += 1;
+    }
+    index.write()?;
+    if staged > 0 {
+        reporter.info(&format!("Staged the revisions of {} submodule{}", staged, if staged == 1 { "" } else { "s" }));
+    }
+    Ok(())
+}
+
+/// Regenerates the exported files which dep wrote before, after the dependencies changed.
+pub fn refresh(man: &TomlManifest, opts: &GlobalOptions, reporter: &dyn Reporter) -> Result<()> {
+    let path = Path::new(GITMODULES);
+    let generated = std::fs::read_to_string(path).map(|content| content.starts_with(HEADER)).unwrap_or(false);
+    if generated {
+        // gitlinks staged by an earlier export move along, if the project is a repository
+        let stage = if git2::Repository::discover(".").is_ok() { Some(true) } else { None };
+        write_submodules(man, opts, path, stage, reporter)?;
+        reporter.info(&format!("Regenerated \"{}\"", path.to_string_lossy()));
+    }
+    Ok(())
+}
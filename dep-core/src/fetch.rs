@@ -0,0 +1,278 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::config::{host_credentials, server_kind, GlobalOptions, ServerKind};
+use crate::error::{Error, Result};
+use crate::manifest::TomlDependency;
+use crate::paths::replace_dir;
+use crate::report::Reporter;
+use crate::url::GitUrl;
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unpack_archive(url: &str, data: &[u8], into: &Path) -> Result<()> {
+    let file_name = url.rsplit('/').next().unwrap_or(url).to_lowercase();
+    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        tar::Archive::new(GzDecoder::new(data)).unpack(into)?;
+    } else if file_name.ends_with(".tar") {
+        tar::Archive::new(data).unpack(into)?;
+    } else if file_name.ends_with(".zip") {
+        zip::ZipArchive::new(Cursor::new(data))?.extract(into)?;
+    } else {
+        return Err(Error::msg(format!("Unsupported archive type: \"{}\"", file_name)));
+    }
+    Ok(())
+}
+
+fn download(request: ureq::Request) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    request.set("User-Agent", "dep").call()?.into_reader().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The file `.dep-archive` in a downloaded dependency records what was installed,
+/// so unchanged downloads can be skipped.
+pub fn is_installed(dst: &Path, key: &str) -> bool {
+    match std::fs::read_to_string(dst.join(".dep-archive")) {
+        Ok(content) => content.trim() == key,
+        Err(_) => false,
+    }
+}
+
+/// Verifies a downloaded file and installs it as `libdir/name`.
+///
+/// Archives get unpacked, where a single top level directory gets stripped,
+/// as most release archives contain one. Other files are copied into the directory.
+fn install_download(file_name: &str, data: &[u8], sha256: &str, key: &str, libdir: &Path, name: &str) -> Result<()> {
+    let actual = sha256_hex(data);
+    if actual != sha256.to_lowercase() {
+        return Err(Error::msg(format!("Checksum mismatch for \"{}\": expected {}, got {}", file_name, sha256, actual)));
+    }
+
+    let dst = libdir.join(name);
+    let staging = libdir.join(format!(".{}.tmp", name));
+    if staging.exists() {
+        remove_dir_all::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+
+    let lower = file_name.to_lowercase();
+    let is_archive = [".tar.gz", ".tgz", ".tar", ".zip"].iter().any(|ext| lower.ends_with(ext));
+    if is_archive {
+        unpack_archive(file_name, data, &staging)?;
+    } else {
+        let mut file = File::create(staging.join(file_name))?;
+        file.write_all(data)?;
+        file.flush()?;
+    }
+
+    let entries = std::fs::read_dir(&staging)?.collect::<std::io::Result<Vec<_>>>()?;
+    let root = match entries.as_slice() {
+        [entry] if is_archive && entry.path().is_dir() => entry.path(),
+        _ => staging.clone(),
+    };
+
+    // recorded before the swap, an interrupted install doesn't look installed
+    let mut file = File::create(root.join(".dep-archive"))?;
+    file.write_all(key.as_bytes())?;
+    file.flush()?;
+
+    replace_dir(&root, &dst)?;
+    if staging.exists() {
+        remove_dir_all::remove_dir_all(&staging)?;
+    }
+    Ok(())
+}
+
+/// Downloads, verifies and unpacks an archive into `libdir/name`.
+pub fn fetch_archive(url: &str, sha256: &str, libdir: &Path, name: &str, reporter: &dyn Reporter) -> Result<()> {
+    if is_installed(&libdir.join(name), &sha256.to_lowercase()) {
+        return Ok(());
+    }
+
+    reporter.info(&format!("Downloading archive \"{}\" into \"{}\" as \"{}\"", url, libdir.to_string_lossy(), name));
+    let data = download(ureq::get(url))?;
+    let file_name = url.rsplit('/').next().unwrap_or(url);
+    install_download(file_name, &data, sha256, &sha256_hex(&data), libdir, name)
+}
+
+/// Splits a git url (`https://host/path`, `ssh://user@host:port/path` or `user@host:path`)
+/// into host and repository path, without the `.git` suffix.
+pub fn split_git_url(url: &str) -> Option<(String, String)> {
+    let url = GitUrl::parse(url)?;
+    let path = url.path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    Some((url.host.clone(), path.to_owned()))
+}
+
+/// The url of the source archive (a `.tar.gz`) of a tag or revision of a GitHub or GitLab repository.
+/// Which one a server is comes from [`server_kind`], other servers have no archives.
+pub fn source_archive_url(opts: &GlobalOptions, url: &str, tag: Option<&str>, rev: Option<&str>) -> Option<String> {
+    let (host, path) = split_git_url(url)?;
+    match server_kind(opts, &host)? {
+        ServerKind::Github if host == "github.com" => match (tag, rev) {
+            (Some(tag), _) => Some(format!("https://codeload.github.com/{}/tar.gz/refs/tags/{}", path, tag)),
+            (None, Some(rev)) => Some(format!("https://codeload.github.com/{}/tar.gz/{}", path, rev)),
+            _ => None,
+        },
+        // GitHub Enterprise serves them from the server itself
+        ServerKind::Github => match (tag, rev) {
+            (Some(tag), _) => Some(format!("https://{}/{}/archive/refs/tags/{}.tar.gz", host, path, tag)),
+            (None, Some(rev)) => Some(format!("https://{}/{}/archive/{}.tar.gz", host, path, rev)),
+            _ => None,
+        },
+        ServerKind::Gitlab => {
+            let reference = tag.or(rev)?;
+            let repo = path.rsplit('/').next().unwrap_or(&path);
+            Some(format!("https://{}/{}/-/archive/{}/{}-{}.tar.gz", host, path, reference, repo, reference.replace('/', "-")))
+        }
+    }
+}
+
+/// Downloads the source archive of the tag or revision of a public GitHub or GitLab repository, verifies and
+/// unpacks it into `libdir/name`, which is a lot faster than cloning large repositories. Returns false if
+/// there is no archive for the url, no checksum to verify it, or it couldn't be downloaded (like of private repositories).
+pub fn fetch_source_archive(opts: &GlobalOptions, url: &str, dep: &TomlDependency, libdir: &Path, name: &str, reporter: &dyn Reporter) -> Result<bool> {
+    let (tag, rev) = (dep.tag.as_deref(), dep.rev.as_deref());
+    let archive = match source_archive_url(opts, url, tag, rev) {
+        Some(archive) => archive,
+        None => {
+            reporter.info(&format!("\"{}\" isn't on a known GitHub or GitLab server (set kind in its [credentials]), cloning instead", name));
+            return Ok(false);
+        }
+    };
+    let sha256 = match &dep.sha256 {
+        Some(sha256) => sha256,
+        None => {
+            reporter.info(&format!("\"{}\" has no sha256 to verify its archive with, cloning instead", name));
+            return Ok(false);
+        }
+    };
+    if is_installed(&libdir.join(name), &archive) {
+        return Ok(true);
+    }
+
+    reporter.info(&format!("Downloading the archive of \"{}\" from \"{}\" into \"{}\" as \"{}\"", tag.or(rev).unwrap_or_default(), url, libdir.to_string_lossy(), name));
+    let data = match download(ureq::get(&archive)) {
+        Ok(data) => data,
+        Err(e) => {
+            reporter.info(&format!("Could not download \"{}\" ({}), cloning instead", archive, e));
+            return Ok(false);
+        }
+    };
+    let file_name = format!("{}-{}.tar.gz", name, tag.or(rev).unwrap_or_default().replace('/', "-"));
+    install_download(&file_name, &data, sha256, &archive, libdir, name)?;
+    Ok(true)
+}
+
+fn get_token(opts: &GlobalOptions, host: &str, env_var: &str) -> Option<String> {
+    opts.tokens.as_ref()
+        .and_then(|tokens| tokens.get(host))
+        .or_else(|| opts.credentials.as_ref().and_then(|credentials| host_credentials(credentials, host)?.token.as_ref()))
+        .cloned()
+        .or_else(|| std::env::var(env_var).ok())
+}
+
+/// Downloads the asset of the GitHub or GitLab release of `dep`, verifies and installs it into `libdir/name`.
+pub fn fetch_release_asset(opts: &GlobalOptions, url: &str, dep: &TomlDependency, libdir: &Path, name: &str, reporter: &dyn Reporter) -> Result<()> {
+    let (release, asset, sha256) = match (&dep.release, &dep.asset, &dep.sha256) {
+        (Some(release), Some(asset), Some(sha256)) => (release.as_str(), asset.as_str(), sha256.as_str()),
+        (None, _, _) => return Err(Error::msg(format!("\"{}\" isn't a release dependency", name))),
+        (_, None, _) => return Err(Error::msg(format!("Release dependency \"{}\" needs an asset", name))),
+        (_, _, None) => return Err(Error::msg(format!("Release dependency \"{}\" needs the sha256 checksum of its asset", name))),
+    };
+    let key = format!("{}/{}", release, asset);
+    if is_installed(&libdir.join(name), &key) {
+        return Ok(());
+    }
+
+    let (host, path) = match split_git_url(url) {
+        Some(parts) => parts,
+        None => return Err(Error::msg(format!("Could not parse git url \"{}\"", url))),
+    };
+
+    reporter.info(&format!("Downloading asset \"{}\" of release \"{}\" from \"{}\" into \"{}\" as \"{}\"", asset, release, url, libdir.to_string_lossy(), name));
+    let kind = server_kind(opts, &host);
+    let data = if kind == Some(ServerKind::Github) {
+        let token = get_token(opts, &host, "GITHUB_TOKEN");
+        let with_auth = |request: ureq::Request| match &token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        };
+
+        // GitHub Enterprise has the api on the server itself
+        let api = match host.as_str() {
+            "github.com" => format!("https://api.github.com/repos/{}/releases/tags/{}", path, release),
+            _ => format!("https://{}/api/v3/repos/{}/releases/tags/{}", host, path, release),
+        };
+        let response: serde_json::Value = serde_json::from_slice(&download(with_auth(ureq::get(&api)))?)?;
+        let asset_url = response["assets"].as_array()
+            .and_then(|assets| assets.iter().find(|a| a["name"] == asset))
+            .and_then(|a| a["url"].as_str());
+        match asset_url {
+            Some(asset_url) => download(with_auth(ureq::get(asset_url)).set("Accept", "application/octet-stream"))?,
+            None => return Err(Error::msg(format!("Release \"{}\" has no asset \"{}\"", release, asset))),
+        }
+    } else if kind == Some(ServerKind::Gitlab) {
+        let token = get_token(opts, &host, "GITLAB_TOKEN");
+        let with_auth = |request: ureq::Request| match &token {
+            Some(token) => request.set("PRIVATE-TOKEN", token),
+            None => request,
+        };
+
+        let api = format!("https://{}/api/v4/projects/{}/releases/{}", host, path.replace('/', "%2F"), release);
+        let response: serde_json::Value = serde_json::from_slice(&download(with_auth(ureq::get(&api)))?)?;
+        let asset_url = response["assets"]["links"].as_array()
+            .and_then(|links| links.iter().find(|l| l["name"] == asset))
+            .and_then(|l| l["direct_asset_url"].as_str().or_else(|| l["url"].as_str()));
+        match asset_url {
+            Some(asset_url) => download(with_auth(ureq::get(asset_url)))?,
+            None => return Err(Error::msg(format!("Release \"{}\" has no asset \"{}\"", release, asset))),
+        }
+    } else {
+        return Err(Error::msg(format!(
+            "Releases are only supported for GitHub and GitLab, set kind = \"github\" or \"gitlab\" in [credentials.\"{}\"] for self hosted ones", host)));
+    };
+
+    install_download(asset, &data, sha256, &key, libdir, name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostCredentials;
+
+    #[test]
+    fn source_archives_depend_on_the_kind_of_the_server() {
+        let mut opts = GlobalOptions::default();
+        let kind = |kind| HostCredentials { kind: Some(kind), ..Default::default() };
+        opts.credentials = Some(std::collections::BTreeMap::from([
+            ("code.corp.com".to_owned(), kind(ServerKind::Gitlab)),
+            ("ghe.corp.com".to_owned(), kind(ServerKind::Github)),
+        ]));
+        let cases = [
+            ("https://github.com/org/repo", Some("https://codeload.github.com/org/repo/tar.gz/refs/tags/v1.0")),
+            ("https://gitlab.com/group/sub/repo.git", Some("https://gitlab.com/group/sub/repo/-/archive/v1.0/repo-v1.0.tar.gz")),
+            ("git@code.corp.com:group/repo", Some("https://code.corp.com/group/repo/-/archive/v1.0/repo-v1.0.tar.gz")),
+            ("https://ghe.corp.com/org/repo", Some("https://ghe.corp.com/org/repo/archive/refs/tags/v1.0.tar.gz")),
+            // only the kind of the server counts, not its name
+            ("https://gitlab.other.com/group/repo", None),
+        ];
+        for (url, archive) in cases {
+            assert_eq!(source_archive_url(&opts, url, Some("v1.0"), None).as_deref(), archive, "{}", url);
+        }
+        assert_eq!(source_archive_url(&opts, "https://github.com/org/repo", None, Some("3a5f2c1")).as_deref(),
+            Some("https://codeload.github.com/org/repo/tar.gz/3a5f2c1"));
+        assert_eq!(source_archive_url(&opts, "https://github.com/org/repo", None, None), None);
+    }
+}
@@ -0,0 +1,38 @@
+//! Advisory locks, so concurrent dep runs don't update the same lib dir or cache at once.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::report::Reporter;
+
+/// Name of the lock file in a locked directory.
+pub const LOCK_FILE: &str = ".dep-lock";
+
+/// Released when dropped, or when the process ends.
+#[derive(Debug)]
+pub struct FileLock {
+    // only kept for the lock, which belongs to the open file
+    _file: File,
+    pub path: PathBuf,
+}
+
+/// Locks `dir` against other dep processes. If it's locked already, this waits for the other process
+/// to finish, or fails right away if `wait` is false.
+pub fn lock_dir(dir: &Path, wait: bool, reporter: &dyn Reporter) -> Result<FileLock> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(LOCK_FILE);
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) if wait => {
+            reporter.info(&format!("Waiting for another dep process to finish with \"{}\"", dir.to_string_lossy()));
+            file.lock()?;
+        }
+        Err(TryLockError::WouldBlock) => return Err(Error::msg(format!(
+            "\"{}\" is used by another dep process, try again once it's finished", dir.to_string_lossy()
+        ))),
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+    Ok(FileLock { _file: file, path })
+}
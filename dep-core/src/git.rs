@@ -0,0 +1,1363 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use git2::build::CheckoutBuilder;
+use git2::{FetchOptions, ProxyOptions};
+use git2::RemoteCallbacks;
+
+use crate::config::{host_credentials, Backend, HostCredentials, HostKeyChecking, SshOptions};
+use crate::error::{Error, Result};
+use crate::filelock::lock_dir;
+use crate::gitconfig::GitConfig;
+use crate::keychain;
+use crate::known_hosts::check_host_key;
+use crate::manifest::TomlDependency;
+use crate::paths::{absolute_path, get_home_dir, normalize, replace_dir};
+use crate::policy::url_origin;
+use crate::report::{Reporter, Silent};
+use crate::ssh_config;
+use crate::url::GitUrl;
+
+/// Returns true for `file://` urls and paths to local repositories.
+pub fn is_local_url(url: &str) -> bool {
+    if url.starts_with("file://") {
+        return true;
+    }
+    if url.contains("://") {
+        return false;
+    }
+    let bytes = url.as_bytes();
+    let has_drive_letter = bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'/' || bytes[2] == b'\\');
+    url.starts_with('.') || url.starts_with('/') || url.starts_with('\\') || has_drive_letter || Path::new(url).exists()
+}
+
+/// Makes local repository paths absolute, so they don't depend on the working directory
+/// when used as remote of the checkout.
+pub fn local_url(url: &str) -> std::io::Result<String> {
+    if is_local_url(url) && !url.starts_with("file://") {
+        Ok(absolute_path(url)?.to_string_lossy().to_string())
+    } else {
+        Ok(url.to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckoutOptions {
+    // only local repositories are fetched
+    pub offline: bool,
+    // updates checkouts with uncommitted changes or local commits anyway, which drops them
+    pub discard_local: bool,
+    // stashes uncommitted changes before the update and re-applies them afterwards
+    pub preserve_local: bool,
+    // a bare repository with the objects of the remote, like one in the cache, which new clones borrow from
+    pub reference: Option<PathBuf>,
+    // partial clone filter of new clones without a reference, like blob:none
+    pub filter: Option<String>,
+    // new clones without a reference only get the history since this date
+    pub shallow_since: Option<String>,
+}
+
+/// Asks for the passphrase of the given private key.
+type Prompt = dyn Fn(&Path) -> std::io::Result<String> + Send + Sync;
+
+/// Asks for the username (unless the url has one) and password of an url.
+type LoginPrompt = dyn Fn(&str, Option<&str>) -> std::io::Result<(String, String)> + Send + Sync;
+
+/// Start of the error of logins which are needed, but can't be asked for.
+pub const LOGIN_REQUIRED: &str = "A login is required";
+
+/// Authentication for git remotes. The passphrase of a protected ssh key is asked for
+/// with `prompt`, once it's needed.
+///
+/// The remote callbacks also cancel running transfers, once the flag given with
+/// [`Credentials::with_cancel`] is set.
+pub struct Credentials {
+    ssh: Option<SshOptions>,
+    // the ssh key is the `ssh-key` of a dependency, not the one of the global config
+    dependency_key: bool,
+    // IdentityFile of the ~/.ssh/config alias the url was resolved from
+    identity: Option<PathBuf>,
+    // [credentials] of the global config, by host
+    hosts: Arc<BTreeMap<String, HostCredentials>>,
+    // passphrases by private key, shared with the credentials of single dependencies
+    passphrases: Arc<Mutex<BTreeMap<PathBuf, String>>>,
+    prompt: Arc<Prompt>,
+    login_prompt: Option<Arc<LoginPrompt>>,
+    // logins entered during this run, by host
+    logins: Arc<Mutex<BTreeMap<String, (String, String)>>>,
+    cancel: Arc<AtomicBool>,
+    interactive: bool,
+    host_key_checking: HostKeyChecking,
+    // insteadOf, proxies and credential helpers of the git config of the user
+    git_config: Arc<GitConfig>,
+    // with the cli backend, git fetches (with its own credentials) and checks out instead of libgit2
+    backend: Backend,
+    // gets the progress of the clones, fetches and checkouts, and of everything else done with these credentials
+    reporter: Arc<dyn Reporter>,
+}
+
+impl Credentials {
+    pub fn new<F>(ssh: Option<SshOptions>, prompt: F) -> Credentials
+        where F: Fn(&Path) -> std::io::Result<String> + Send + Sync + 'static
+    {
+        Credentials {
+            ssh,
+            dependency_key: false,
+            identity: None,
+            hosts: Arc::default(),
+            passphrases: Arc::default(),
+            prompt: Arc::new(prompt),
+            login_prompt: None,
+            logins: Arc::default(),
+            cancel: Arc::default(),
+            interactive: true,
+            host_key_checking: HostKeyChecking::AcceptNew,
+            git_config: Arc::default(),
+            backend: Backend::Libgit2,
+            reporter: Arc::new(Silent),
+        }
+    }
+
+    /// Uses the credentials of the host of an url, instead of the ssh key, when there are some.
+    pub fn with_hosts(mut self, hosts: Option<BTreeMap<String, HostCredentials>>) -> Credentials {
+        self.hosts = Arc::new(hosts.unwrap_or_default());
+        self
+    }
+
+    /// The credentials for `dep`: its own `ssh-key` takes precedence over the key of the global config.
+    /// Its public key is expected at `<key>.pub`, and whether it's protected is read from the key.
+    pub fn for_dependency(&self, dep: &TomlDependency) -> Credentials {
+        let mut credentials = self.derive();
+        if let Some(key) = &dep.ssh_key {
+            credentials.ssh = Some(key_options(key));
+            credentials.dependency_key = true;
+        }
+        credentials
+    }
+
+    /// The url git would connect to for `url`: the `insteadOf` rewrites of the git config are applied, then
+    /// host aliases of `~/.ssh/config` are resolved (see [`ssh_config::resolve_url`]). Returns it with the
+    /// credentials for it, which use the `IdentityFile` of the alias.
+    pub fn resolve_url(&self, url: &str) -> (String, Credentials) {
+        let (resolved, identity) = ssh_config::resolve_url(&self.git_config.rewrite(url));
+        let mut credentials = self.derive();
+        credentials.identity = identity.or_else(|| self.identity.clone());
+        (resolved, credentials)
+    }
+
+    fn derive(&self) -> Credentials {
+        Credentials {
+            ssh: self.ssh.clone(),
+            dependency_key: self.dependency_key,
+            identity: self.identity.clone(),
+            hosts: self.hosts.clone(),
+            passphrases: self.passphrases.clone(),
+            prompt: self.prompt.clone(),
+            login_prompt: self.login_prompt.clone(),
+            logins: self.logins.clone(),
+            cancel: self.cancel.clone(),
+            interactive: self.interactive,
+            host_key_checking: self.host_key_checking,
+            git_config: self.git_config.clone(),
+            backend: self.backend,
+            reporter: self.reporter.clone(),
+        }
+    }
+
+    /// Asks for the login of https urls without [credentials], instead of failing. Entered logins
+    /// are used for the other urls of their host too, until they get rejected.
+    pub fn with_login_prompt<F>(mut self, prompt: F) -> Credentials
+        where F: Fn(&str, Option<&str>) -> std::io::Result<(String, String)> + Send + Sync + 'static
+    {
+        self.login_prompt = Some(Arc::new(prompt));
+        self
+    }
+
+    /// Applies the settings of the git config of the user, like the git cli would.
+    pub fn with_git_config(mut self, git_config: GitConfig) -> Credentials {
+        self.git_config = Arc::new(git_config);
+        self
+    }
+
+    /// Clones, fetches and checks out with the git command line instead of libgit2, with `Backend::Cli`.
+    pub fn with_backend(mut self, backend: Backend) -> Credentials {
+        self.backend = backend;
+        self
+    }
+
+    /// Reports the progress to `reporter`, which drops it by default.
+    pub fn with_reporter<R: Reporter + 'static>(mut self, reporter: R) -> Credentials {
+        self.reporter = Arc::new(reporter);
+        self
+    }
+
+    pub fn reporter(&self) -> &dyn Reporter {
+        &*self.reporter
+    }
+
+    /// Whether git does the work for `repo`: with the cli backend, or for partial and shallow clones.
+    fn uses_cli(&self, repo: &git2::Repository) -> bool {
+        self.backend == Backend::Cli || is_incomplete(repo)
+    }
+
+    /// How the host keys of ssh servers are checked against known_hosts.
+    pub fn with_host_key_checking(mut self, mode: HostKeyChecking) -> Credentials {
+        self.host_key_checking = mode;
+        self
+    }
+
+    /// Shares the flag which cancels running transfers, e.g. set from a ctrl-c handler.
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Credentials {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Uses a passphrase given up front, like from the environment, instead of the keychain or the prompt.
+    /// It's the one of the key of the global config, or of the first default key without one.
+    pub fn with_passphrase(self, passphrase: Option<String>) -> Credentials {
+        let ssh = self.ssh.clone().or_else(|| default_ssh_keys().into_iter().next());
+        if let (Some(ssh), Some(passphrase)) = (&ssh, passphrase) {
+            self.passphrases.lock().unwrap().insert(ssh.private.clone(), passphrase);
+        }
+        self
+    }
+
+    /// With false, a missing passphrase is an error instead of calling the prompt, like in pipelines.
+    pub fn with_interactive(mut self, interactive: bool) -> Credentials {
+        self.interactive = interactive;
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+
+    /// The login entered for the host of `url` before, or the one the login prompt asks for. None if there
+    /// is no prompt or there is nobody to ask.
+    fn ask_login(&self, url: &str, user_from_url: Option<&str>, rejected: bool) -> Option<std::io::Result<(String, String)>> {
+        let host = url_origin(url).1.unwrap_or_default();
+        // held while asking, so parallel fetches ask one after another
+        let mut logins = self.logins.lock().unwrap();
+        if rejected {
+            logins.remove(&host);
+        }
+        if let Some(login) = logins.get(&host) {
+            return Some(Ok(login.clone()));
+        }
+        let prompt = self.login_prompt.as_ref().filter(|_| self.interactive)?;
+        Some(prompt(url, user_from_url).inspect(|login| {
+            logins.insert(host, login.clone());
+        }))
+    }
+
+    /// The host credentials of `url`, if the global config has some.
+    fn host(&self, url: &str) -> Option<&HostCredentials> {
+        url_origin(url).1.and_then(|host| host_credentials(&self.hosts, &host))
+    }
+
+    /// The ssh keys for `url`, in the order they are tried: the `ssh-key` of the dependency or the one of its
+    /// host (which are the only one then), otherwise the `IdentityFile` of `~/.ssh/config` or the key of the
+    /// global config, followed by the default keys of `~/.ssh`.
+    fn ssh_keys(&self, url: &str) -> Vec<SshOptions> {
+        if self.dependency_key {
+            return self.ssh.clone().into_iter().collect();
+        }
+        if let Some(key) = self.host(url).and_then(|host| host.ssh_key.as_deref()) {
+            return vec![key_options(key)];
+        }
+        let identity = self.identity.clone().or_else(|| url_origin(url).1.and_then(|host| ssh_config::lookup(&host).identity_file));
+        let configured = identity.map(|identity| key_options(&identity)).or_else(|| self.ssh.clone());
+        let mut keys = configured.iter().filter(|ssh| normalize(&ssh.private).exists()).cloned().collect::<Vec<_>>();
+        for key in default_ssh_keys() {
+            if !keys.iter().any(|ssh| normalize(&ssh.private) == key.private) {
+                keys.push(key);
+            }
+        }
+        // a missing key at least shows up in the error
+        if keys.is_empty() {
+            keys.extend(configured);
+        }
+        keys
+    }
+
+    /// The ssh key which is tried first for `url`.
+    fn ssh_for(&self, url: &str) -> Option<SshOptions> {
+        self.ssh_keys(url).into_iter().next()
+    }
+
+    /// Prompts for the passphrase of a protected ssh key, if it wasn't entered yet and isn't in the keychain.
+    pub fn ask_passphrase(&self) -> Result<()> {
+        match self.ssh.clone() {
+            Some(ssh) => self.ask_key_passphrase(&ssh),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`Credentials::ask_passphrase`], for the key which is used for `url`.
+    pub fn ask_passphrase_for(&self, url: &str) -> Result<()> {
+        if url_origin(url).0 != "ssh" {
+            return Ok(());
+        }
+        match self.ssh_for(url) {
+            Some(ssh) => self.ask_key_passphrase(&ssh),
+            None => Ok(()),
+        }
+    }
+
+    fn ask_key_passphrase(&self, ssh: &SshOptions) -> Result<()> {
+        let is_global_key = !self.dependency_key && self.ssh.as_ref().map(|global| global.private == ssh.private).unwrap_or(false);
+        let mut passphrases = self.passphrases.lock().unwrap();
+        if ssh.protected && !passphrases.contains_key(&ssh.private) {
+            let passphrase = match keychain::passphrase(ssh) {
+                Some(stored) => stored,
+                None if self.interactive => (self.prompt)(&ssh.private)?,
+                None if !is_global_key => return Err(Error::CredentialsRequired(format!(
+                    "The ssh key \"{}\" is protected and dep can't ask for its passphrase here, \
+                    store it with \"dep auth set --key {}\"", ssh.private.to_string_lossy(), ssh.private.to_string_lossy()))),
+                None => return Err(Error::CredentialsRequired(format!(
+                    "The ssh key \"{}\" is protected and dep can't ask for its passphrase here, pass it with DEP_SSH_PASSPHRASE, \
+                    --passphrase-file or --passphrase-stdin, or store it with \"dep auth set\"", ssh.private.to_string_lossy()))),
+            };
+            passphrases.insert(ssh.private.clone(), passphrase);
+        }
+        Ok(())
+    }
+
+    /// The callbacks for fetching from `url`.
+    pub fn callbacks(&self, url: &str) -> RemoteCallbacks<'_> {
+        let mut cb = RemoteCallbacks::new();
+        // the check replaces the one of libgit2, which would accept invalid https certificates
+        if url_origin(url).0 == "ssh" && self.host_key_checking != HostKeyChecking::Off {
+            let port = GitUrl::parse(url).and_then(|url| url.port);
+            cb.certificate_check(move |cert, host| {
+                let md5 = cert.as_hostkey().and_then(|key| key.hash_md5());
+                match check_host_key(host, port, md5, self.host_key_checking, self.reporter()) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        // libgit2 only reports that the check failed
+                        self.reporter().info(&e.to_string());
+                        false
+                    }
+                }
+            });
+        }
+        // libgit2 asks again after rejected credentials, which would go on forever with the same ones
+        let mut attempts = Attempts::default();
+        cb.credentials(move |url, user_from_url, allowed| self.credentials(url, user_from_url, allowed, &mut attempts));
+        // returning false aborts the transfer
+        cb.transfer_progress(move |_progress| !self.is_cancelled());
+        cb
+    }
+
+    pub fn fetch_options(&self, url: &str) -> FetchOptions<'_> {
+        let mut fo = FetchOptions::new();
+        fo.remote_callbacks(self.callbacks(url));
+        let mut proxy = ProxyOptions::new();
+        match self.git_config.proxy(url) {
+            Some(url) => proxy.url(url),
+            // the proxy environment variables
+            None => proxy.auto(),
+        };
+        fo.proxy_options(proxy);
+        fo
+    }
+
+    fn credentials(&self, url: &str, user_from_url: Option<&str>, allowed: git2::CredentialType, attempts: &mut Attempts)
+        -> std::result::Result<git2::Cred, git2::Error>
+    {
+        if allowed.is_ssh_key() {
+            let user = match user_from_url {
+                Some(user) => user,
+                None => return Err(git2::Error::from_str("Url does not contain username")),
+            };
+            let keys = self.ssh_keys(url);
+            // a rejected key is followed by the next one, protected keys whose passphrase can't be asked for are skipped
+            while let Some(ssh) = keys.get(attempts.keys) {
+                attempts.keys += 1;
+                if self.ask_key_passphrase(ssh).is_err() {
+                    continue;
+                }
+                let passphrase = self.passphrases.lock().unwrap().get(&ssh.private).cloned().unwrap_or_default();
+                return git2::Cred::ssh_key(user, Some(&normalize(&ssh.public)), &normalize(&ssh.private), Some(passphrase.as_str()));
+            }
+            // servers which allow passwords as well
+            if !allowed.is_user_pass_plaintext() {
+                return Err(git2::Error::from_str(&match keys.is_empty() {
+                    true => format!("There is no ssh key for \"{}\", add one to [ssh] of the global config", url),
+                    false => format!("None of the ssh keys ({}) was accepted for \"{}\"",
+                        keys.iter().map(|ssh| ssh.private.to_string_lossy()).collect::<Vec<_>>().join(", "), url),
+                }));
+            }
+        }
+        if allowed.is_user_pass_plaintext() {
+            attempts.logins += 1;
+            if attempts.logins > 3 {
+                return Err(git2::Error::from_str(&format!("Authentication for \"{}\" failed", url)));
+            }
+            // the server didn't accept the login of the last call, a rejected login of the config gets asked for instead
+            let rejected = attempts.logins > 1;
+            let configured = match rejected {
+                true => None,
+                false => self.host(url).and_then(|host| login(host, url, user_from_url))
+                    // then the credential helpers of the git config
+                    .or_else(|| match self.git_config.has_credential_helper() {
+                        true => credential_fill(None, url, user_from_url),
+                        false => None,
+                    }),
+            };
+            return match configured.map(Ok).or_else(|| self.ask_login(url, user_from_url, rejected)) {
+                Some(Ok((username, password))) => git2::Cred::userpass_plaintext(&username, &password),
+                Some(Err(e)) => Err(git2::Error::from_str(&format!("Could not ask for the login of \"{}\": {}", url, e))),
+                None if rejected => Err(git2::Error::from_str(&format!("The login for \"{}\" was rejected", url))),
+                None => Err(git2::Error::from_str(&format!("{} for \"{}\", add it to [credentials] of the global config", LOGIN_REQUIRED, url))),
+            };
+        }
+        Err(git2::Error::from_str(&format!("\"{}\" asks for credentials dep doesn't support", url)))
+    }
+}
+
+/// How often the credentials of a transfer were asked for.
+#[derive(Default)]
+struct Attempts {
+    // logins, of https urls
+    logins: usize,
+    // ssh keys tried, the index of the next one
+    keys: usize,
+}
+
+/// The keys ssh uses when none is configured, in the order it tries them.
+const DEFAULT_SSH_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// The default keys which exist in `~/.ssh`, in the order they are tried.
+pub fn default_ssh_keys() -> Vec<SshOptions> {
+    let base = match get_home_dir() {
+        Ok(home) => PathBuf::from(home).join(".ssh"),
+        Err(_) => return Vec::new(),
+    };
+    DEFAULT_SSH_KEYS.iter().map(|name| base.join(name)).filter(|path| path.exists()).map(|path| key_options(&path)).collect()
+}
+
+/// A private key, with the public key at `<key>.pub`.
+fn key_options(key: &Path) -> SshOptions {
+    let private = normalize(&key);
+    let public = PathBuf::from(format!("{}.pub", private.to_string_lossy()));
+    SshOptions { protected: is_protected_key(&private), private, public }
+}
+
+/// Username and password for `url`: the token, the configured username and password,
+/// or what the credential helper has.
+fn login(host: &HostCredentials, url: &str, user_from_url: Option<&str>) -> Option<(String, String)> {
+    let username = host.username.as_deref().or(user_from_url);
+    if let Some(token) = &host.token {
+        // the username doesn't matter to most servers, as long as there is one
+        return Some((username.unwrap_or("git").to_owned(), token.clone()));
+    }
+    if let (Some(username), Some(password)) = (username, &host.password) {
+        return Some((username.to_owned(), password.clone()));
+    }
+    host.helper.as_deref().and_then(|helper| credential_fill(Some(helper), url, username))
+}
+
+/// Asks a git credential helper for the login of `url`, with `git credential fill`. Without `helper`,
+/// the helpers of the git config are asked.
+fn credential_fill(helper: Option<&str>, url: &str, username: Option<&str>) -> Option<(String, String)> {
+    let mut input = format!("url={}\n", url);
+    if let Some(username) = username {
+        input += &format!("username={}\n", username);
+    }
+    let args = match helper {
+        // only the helper of the global config, not those of git
+        Some(helper) => vec!["-c".to_owned(), "credential.helper=".to_owned(), "-c".to_owned(), format!("credential.helper={}", helper)],
+        None => Vec::new(),
+    };
+    let mut child = Command::new("git")
+        .args(args)
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(format!("{}\n", input).as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let value = |key: &str| output.lines().find_map(|line| line.strip_prefix(&format!("{}=", key)[..]).map(|value| value.to_owned()));
+    Some((value("username")?, value("password")?))
+}
+
+/// Whether the private key needs a passphrase: pem keys say so in their header, openssh keys
+/// name their cipher right after the magic at the start, "none" for unprotected ones.
+fn is_protected_key(private: &Path) -> bool {
+    let content = match std::fs::read_to_string(private) {
+        Ok(content) => content,
+        Err(_) => return false,
+    };
+    if content.contains("ENCRYPTED") {
+        return true;
+    }
+    // base64 of "openssh-key-v1\0", the length 4 and "non"
+    const UNENCRYPTED: &str = "b3BlbnNzaC1rZXktdjEAAAAABG5vbm";
+    content.contains("BEGIN OPENSSH PRIVATE KEY") && !content.lines().nth(1).unwrap_or_default().starts_with(UNENCRYPTED)
+}
+
+/// Initializes and updates all submodules of a repository recursively.
+pub fn update_submodules(credentials: &Credentials, repo: &git2::Repository, offline: bool) -> Result<()> {
+    if let (Some(workdir), Backend::Cli) = (repo.workdir(), credentials.backend) {
+        if repo.submodules()?.is_empty() {
+            return Ok(());
+        }
+        credentials.reporter().info(&format!("Updating submodules of \"{}\"", workdir.to_string_lossy()));
+        let mut args = vec!["submodule", "update", "--init", "--recursive", "--quiet"];
+        if offline {
+            args.push("--no-fetch");
+        }
+        return run_git(workdir, &args);
+    }
+    for mut submodule in repo.submodules()? {
+        credentials.reporter().info(&format!("Updating submodule \"{}\"", submodule.path().to_string_lossy()));
+
+        let mut opts = git2::SubmoduleUpdateOptions::new();
+        opts.fetch(credentials.fetch_options(submodule.url().unwrap_or_default()));
+        opts.allow_fetch(!offline);
+
+        submodule.update(true, Some(&mut opts))?;
+        update_submodules(credentials, &submodule.open()?, offline)?;
+    }
+    Ok(())
+}
+
+/// Runs git with `args` in `dir`, for what git2 can't do.
+pub fn run_git(dir: &Path, args: &[&str]) -> Result<()> {
+    git_output(dir, args).map(|_| ())
+}
+
+/// Like [`run_git`], returns what git printed.
+fn git_output(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = match Command::new("git").args(args).current_dir(dir).output() {
+        Ok(output) => output,
+        Err(e) => return Err(Error::msg(format!("Could not run git, is it installed? ({})", e))),
+    };
+    if !output.status.success() {
+        return Err(Error::msg(format!("git {} failed in \"{}\": {}", args[0], dir.to_string_lossy(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The refs of the remote at `url` with their commits, and the ref its HEAD points to, listed by git.
+fn ls_remote(url: &str) -> Result<(BTreeMap<String, git2::Oid>, Option<String>)> {
+    let output = git_output(&std::env::current_dir()?, &["ls-remote", "--symref", url])?;
+    let mut refs = BTreeMap::new();
+    let mut head = None;
+    for line in output.lines() {
+        // "ref: refs/heads/main\tHEAD" for the symref, "<oid>\t<ref>" for the others
+        let (value, name) = match line.split_once('\t') {
+            Some(parts) => parts,
+            None => continue,
+        };
+        match value.strip_prefix("ref: ") {
+            Some(target) if name == "HEAD" => head = Some(target.to_owned()),
+            Some(_) => {}
+            None => {
+                if let Ok(oid) = git2::Oid::from_str(value) {
+                    refs.insert(name.to_owned(), oid);
+                }
+            }
+        }
+    }
+    Ok((refs, head))
+}
+
+/// Replaces the lfs pointer files of a checkout with their content, using `git lfs pull`.
+pub fn pull_lfs_objects(dst: &Path, reporter: &dyn Reporter) -> Result<()> {
+    reporter.info(&format!("Fetching lfs objects for \"{}\"", dst.to_string_lossy()));
+    let status = match Command::new("git").args(["lfs", "pull"]).current_dir(dst).status() {
+        Ok(status) => status,
+        Err(e) => return Err(Error::msg(format!("Could not run git lfs, is it installed? ({})", e))),
+    };
+    if !status.success() {
+        return Err(Error::msg(format!("git lfs pull failed in \"{}\"", dst.to_string_lossy())));
+    }
+    Ok(())
+}
+
+/// Verifies the signature of the tag the dependency asks for, or of the checked out commit,
+/// with `git verify-tag` or `git verify-commit` (and the keys of the user).
+pub fn verify_signature(dst: &Path, dep: &TomlDependency) -> Result<()> {
+    let args = match &dep.tag {
+        Some(tag) => vec!["verify-tag", tag.as_str()],
+        None => vec!["verify-commit", "HEAD"],
+    };
+    let output = match Command::new("git").args(&args).current_dir(dst).output() {
+        Ok(output) => output,
+        Err(e) => return Err(Error::msg(format!("Could not run git to verify signatures, is it installed? ({})", e))),
+    };
+    if !output.status.success() {
+        let what = dep.tag.as_ref().map(|tag| format!("tag \"{}\"", tag)).unwrap_or_else(|| "the checked out commit".to_owned());
+        // git prints nothing for objects without a signature
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = if stderr.trim().is_empty() { "it isn't signed".to_owned() } else { stderr.trim().to_owned() };
+        return Err(Error::msg(format!(
+            "The signature of {} in \"{}\" couldn't be verified, but the policy requires it ({})",
+            what, dst.to_string_lossy(), reason
+        )));
+    }
+    Ok(())
+}
+
+/// Clones or updates the git dependency `name` in `libdir`, checking out the branch, tag or revision
+/// it asks for (or the default branch of the remote), including submodules and lfs objects.
+///
+/// New clones are made in `libdir/.<name>.tmp` and only moved to `libdir/name` once they are complete,
+/// so a failed or interrupted clone never leaves a half written checkout behind.
+///
+/// Existing checkouts with uncommitted changes or local commits are refused, unless `discard_local` is set.
+pub fn checkout(credentials: &Credentials, url: &str, dep: &TomlDependency, libdir: &Path, name: &str, options: &CheckoutOptions) -> Result<()> {
+    let dst = libdir.join(name);
+    if dst.exists() {
+        if let Ok(repo) = git2::Repository::open(&dst) {
+            // the url changes with --mirror or rewrites, the checkout fetches from where it's told to
+            if repo.find_remote("origin").ok().and_then(|remote| remote.url().map(|current| current != url)).unwrap_or(false) {
+                repo.remote_set_url("origin", url)?;
+            }
+            if is_up_to_date(credentials, &repo, url, dep, options.offline)? {
+                credentials.reporter().info(&format!("\"{}\" is up to date", name));
+                return Ok(());
+            }
+        }
+
+        let mut stashed = false;
+        if let Ok(mut repo) = git2::Repository::open(&dst) {
+            let changed = uncommitted_changes(&repo, dep)?;
+            if changed > 0 && options.preserve_local && !options.discard_local {
+                credentials.reporter().info(&format!("Stashing local changes of \"{}\"", name));
+                stash_changes(&mut repo)?;
+                stashed = true;
+            }
+
+            let mut changes = Vec::new();
+            if changed > 0 && !stashed {
+                changes.push(format!("uncommitted changes in {} file{}", changed, if changed == 1 { "" } else { "s" }));
+            }
+            if has_local_commits(&repo)? {
+                changes.push("local commits".to_owned());
+            }
+            if !changes.is_empty() {
+                let changes = changes.join(" and ");
+                if !options.discard_local {
+                    if stashed {
+                        restore_stash(&mut repo, name, credentials.reporter());
+                    }
+                    return Err(Error::msg(format!(
+                        "\"{}\" has {}, commit and push them or use --discard-local to drop them", name, changes
+                    )));
+                }
+                credentials.reporter().info(&format!("Discarding {} of \"{}\"", changes, name));
+                discard_changes(&repo, &dst)?;
+            }
+        }
+
+        // an existing checkout goes back to where it was, if the update fails halfway
+        let previous = git2::Repository::open(&dst).ok().and_then(|repo| head_state(&repo));
+        let result = checkout_into(credentials, url, dep, libdir, name, &dst, options);
+        // the tree is written before HEAD moves, so it's restored even if HEAD is where it was
+        if let (Err(_), Some((head, oid))) = (&result, &previous) {
+            match restore_head(&dst, head.as_deref(), *oid) {
+                Ok(_) => credentials.reporter().info(&format!("Update of \"{}\" failed, restored it to \"{}\"", name, oid)),
+                Err(e) => credentials.reporter().warn(&format!("Update of \"{}\" failed and it could not be restored: {}", name, e)),
+            }
+        }
+        if result.is_ok() {
+            mark_checkout(&git2::Repository::open(&dst)?)?;
+        }
+        if stashed {
+            restore_stash(&mut git2::Repository::open(&dst)?, name, credentials.reporter());
+        }
+        return result;
+    }
+
+    let staging = libdir.join(format!(".{}.tmp", name));
+    // left over from an interrupted run
+    if staging.exists() {
+        remove_dir_all::remove_dir_all(&staging)?;
+    }
+    if let Err(e) = checkout_into(credentials, url, dep, libdir, name, &staging, options) {
+        if staging.exists() {
+            remove_dir_all::remove_dir_all(&staging)?;
+        }
+        return Err(e);
+    }
+    mark_checkout(&git2::Repository::open(&staging)?)?;
+    replace_dir(&staging, &dst)?;
+    Ok(())
+}
+
+/// Whether the checkout `repo` is at what `dep` asks for already, so there's nothing to fetch or check out.
+/// Pinned revisions and tags are compared with HEAD, branches with their tip on the remote (a cheap ls-remote
+/// of `url`), and HEAD has to be on them. Offline, branches are never up to date, the fetch from a local
+/// repository tells.
+fn is_up_to_date(credentials: &Credentials, repo: &git2::Repository, url: &str, dep: &TomlDependency, offline: bool) -> Result<bool> {
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(_) => return Ok(false),
+    };
+    let commit = match head.peel_to_commit() {
+        Ok(commit) => commit.id(),
+        Err(_) => return Ok(false),
+    };
+    if let Some(rev) = &dep.rev {
+        return Ok(find_revision(repo, rev).map(|revision| revision.id()) == Some(commit));
+    }
+    if let Some(tag) = &dep.tag {
+        let tagged = repo.find_reference(&format!("refs/tags/{}", tag)).and_then(|tag| tag.peel_to_commit());
+        return Ok(tagged.map(|tagged| tagged.id() == commit).unwrap_or(false));
+    }
+    if !head.is_branch() || offline {
+        return Ok(false);
+    }
+    if credentials.backend == Backend::Cli {
+        let (refs, remote_head) = ls_remote(url)?;
+        let branch = match (&dep.branch, remote_head) {
+            (Some(branch), _) => format!("refs/heads/{}", branch),
+            (None, Some(remote_head)) => remote_head,
+            (None, None) => return Ok(false),
+        };
+        return Ok(head.name() == Some(branch.as_str()) && refs.get(&branch) == Some(&commit));
+    }
+    let mut remote = repo.remote_anonymous(url)?;
+    remote.connect_auth(git2::Direction::Fetch, Some(credentials.callbacks(url)), None)?;
+    let heads = remote.list()?;
+    let branch = match &dep.branch {
+        Some(branch) => format!("refs/heads/{}", branch),
+        None => match heads.iter().find(|remote_head| remote_head.name() == "HEAD").and_then(|remote_head| remote_head.symref_target()) {
+            Some(target) => target.to_owned(),
+            None => return Ok(false),
+        },
+    };
+    let tip = heads.iter().find(|remote_head| remote_head.name() == branch).map(|remote_head| remote_head.oid());
+    Ok(head.name() == Some(branch.as_str()) && tip == Some(commit))
+}
+
+/// Remembers the commit dep checked out, so later commits can be told apart from the ones of the remote.
+const CHECKOUT_REF: &str = "refs/dep/checkout";
+
+fn mark_checkout(repo: &git2::Repository) -> Result<()> {
+    let oid = repo.head()?.peel_to_commit()?.id();
+    repo.reference(CHECKOUT_REF, oid, true, "dep: checkout")?;
+    Ok(())
+}
+
+/// Resets the checkout to HEAD and removes untracked files.
+fn discard_changes(repo: &git2::Repository, dst: &Path) -> Result<()> {
+    let head = repo.head()?.peel_to_commit()?;
+    repo.reset(head.as_object(), git2::ResetType::Hard, Some(CheckoutBuilder::new().force()))?;
+
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        if let (true, Some(path)) = (entry.status().contains(git2::Status::WT_NEW), entry.path()) {
+            let path = dst.join(path);
+            if path.is_dir() {
+                remove_dir_all::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Number of files with uncommitted changes, including untracked files.
+fn uncommitted_changes(repo: &git2::Repository, dep: &TomlDependency) -> Result<usize> {
+    // git lfs files look modified to libgit2, which doesn't know the lfs filters
+    if dep.lfs.unwrap_or(false) {
+        return Ok(0);
+    }
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).include_ignored(false).exclude_submodules(true);
+    let changed = repo.statuses(Some(&mut options))?.iter()
+        .filter(|entry| entry.status() != git2::Status::CURRENT)
+        .count();
+    Ok(changed)
+}
+
+/// Commits count as local if they aren't reachable from the commit dep checked out, a remote tracking branch
+/// or a tag. Checkouts of older versions of dep, which didn't record their commit, never have local commits.
+fn has_local_commits(repo: &git2::Repository) -> Result<bool> {
+    if repo.find_reference(CHECKOUT_REF).is_err() {
+        return Ok(false);
+    }
+    let head = match repo.head().ok().and_then(|head| head.target()) {
+        Some(head) => head,
+        None => return Ok(false),
+    };
+    for reference in repo.references()? {
+        let reference = reference?;
+        let known = reference.name().map(|name| name == CHECKOUT_REF || name.starts_with("refs/remotes/") || name.starts_with("refs/tags/")).unwrap_or(false);
+        if !known {
+            continue;
+        }
+        if let Ok(commit) = reference.peel_to_commit() {
+            if commit.id() == head || repo.graph_descendant_of(commit.id(), head)? {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Stashes uncommitted changes and untracked files, for `preserve-local-changes`.
+fn stash_changes(repo: &mut git2::Repository) -> Result<()> {
+    let signature = repo.signature().or_else(|_| git2::Signature::now("dep", "dep@localhost"))?;
+    repo.stash_save(&signature, "dep: local changes before update", Some(git2::StashFlags::INCLUDE_UNTRACKED))?;
+    Ok(())
+}
+
+/// Re-applies the stash of [`stash_changes`]. If that fails or conflicts with the update,
+/// the stash is kept, so nothing gets lost.
+fn restore_stash(repo: &mut git2::Repository, name: &str, reporter: &dyn Reporter) {
+    if let Err(e) = repo.stash_apply(0, None) {
+        reporter.warn(&format!(
+            "Local changes of \"{}\" could not be re-applied ({}), they are kept in \"git stash list\" of the checkout",
+            name, e.message()
+        ));
+        return;
+    }
+    // conflicting entries have a stage other than 0, in bits 12 and 13 of the flags
+    let conflicts = repo.index().map(|index| {
+        let mut conflicts = index.iter()
+            .filter(|entry| (entry.flags >> 12) & 0x3 != 0)
+            .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+            .collect::<Vec<_>>();
+        conflicts.dedup();
+        conflicts
+    });
+    match conflicts {
+        Ok(conflicts) if conflicts.is_empty() => {
+            reporter.info(&format!("Re-applied local changes of \"{}\"", name));
+            let _ = repo.stash_drop(0);
+        }
+        Ok(conflicts) => reporter.warn(&format!(
+            "Local changes of \"{}\" conflict with the update in {}, resolve them in the checkout (they are also kept in \"git stash list\")",
+            name, conflicts.join(", ")
+        )),
+        Err(e) => reporter.warn(&format!("Could not check \"{}\" for conflicts: {}", name, e.message())),
+    }
+}
+
+/// The branch HEAD points to (if it isn't detached) and the current commit.
+fn head_state(repo: &git2::Repository) -> Option<(Option<String>, git2::Oid)> {
+    let head = repo.head().ok()?;
+    let oid = head.peel_to_commit().ok()?.id();
+    let branch = if head.is_branch() { head.name().map(|name| name.to_owned()) } else { None };
+    Some((branch, oid))
+}
+
+/// Resets the checkout, and the branch it was on, to the state recorded by [`head_state`].
+fn restore_head(dst: &Path, branch: Option<&str>, oid: git2::Oid) -> Result<()> {
+    let repo = git2::Repository::open(dst)?;
+    let commit = repo.find_commit(oid)?;
+    match branch {
+        Some(branch) => {
+            repo.reference(branch, oid, true, "dep: restore after failed update")?;
+            repo.set_head(branch)?;
+        }
+        None => repo.set_head_detached(oid)?,
+    }
+    repo.reset(commit.as_object(), git2::ResetType::Hard, Some(CheckoutBuilder::new().force()))?;
+    repo.cleanup_state()?;
+    Ok(())
+}
+
+/// Asks the remote which branch its HEAD points to.
+fn default_branch(credentials: &Credentials, remote: &mut git2::Remote, url: &str) -> Result<String> {
+    if credentials.backend == Backend::Cli {
+        return ls_remote(url)?.1.as_deref().and_then(|target| target.strip_prefix("refs/heads/")).map(|branch| branch.to_owned())
+            .ok_or_else(|| Error::msg(format!("Could not detect the default branch of \"{}\"", url)));
+    }
+    remote.connect_auth(git2::Direction::Fetch, Some(credentials.callbacks(url)), None)?;
+    let default_branch = remote.list()?.iter()
+        .find(|head| head.name() == "HEAD")
+        .and_then(|head| head.symref_target())
+        .and_then(|target| target.strip_prefix("refs/heads/"))
+        .map(|branch| branch.to_owned());
+    remote.disconnect();
+    default_branch.ok_or_else(|| Error::msg(format!("Could not detect the default branch of \"{}\"", url)))
+}
+
+/// Creates or updates a bare mirror of `url` at `path`, with all branches and tags,
+/// and HEAD on the default branch, so clones from it check out the same branch as clones from `url`.
+pub fn mirror_repository(credentials: &Credentials, url: &str, path: &Path) -> Result<()> {
+    let (url, credentials) = credentials.resolve_url(url);
+    let (url, credentials) = (url.as_str(), &credentials);
+    let repo = match git2::Repository::open_bare(path) {
+        Ok(repo) => repo,
+        Err(_) => {
+            std::fs::create_dir_all(path)?;
+            let repo = git2::Repository::init_bare(path)?;
+            repo.remote("origin", url)?;
+            repo
+        }
+    };
+    repo.remote_set_url("origin", url)?;
+    let mut remote = repo.find_remote("origin")?;
+    let branch = default_branch(credentials, &mut remote, url)?;
+    let specs = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+    if credentials.backend == Backend::Cli {
+        run_git(path, &["fetch", "--quiet", "--prune", "origin", specs[0], specs[1]])?;
+    } else {
+        let mut fo = credentials.fetch_options(url);
+        // branches and tags which are gone upstream are removed from the mirror as well
+        fo.prune(git2::FetchPrune::On);
+        remote.fetch(&specs, Some(&mut fo), None)?;
+    }
+    repo.set_head(&format!("refs/heads/{}", branch))?;
+    Ok(())
+}
+
+/// The commit of the pinned revision `rev` in `repo`, short revisions as well, None if it isn't fetched (yet).
+fn find_revision<'r>(repo: &'r git2::Repository, rev: &str) -> Option<git2::Commit<'r>> {
+    // Oid::from_str pads short revisions
+    repo.revparse_single(rev).and_then(|object| object.peel_to_commit()).ok()
+}
+
+/// The commit of `dep` in `repo`, which has the branches and tags of the remote as its own, like a mirror.
+fn mirrored_commit(repo: &git2::Repository, dep: &TomlDependency, url: &str) -> Result<git2::Oid> {
+    if let Some(rev) = &dep.rev {
+        return find_revision(repo, rev).map(|commit| commit.id())
+            .ok_or_else(|| Error::msg(format!("\"{}\" has no revision \"{}\"", url, rev)));
+    }
+    let (reference, what) = match (&dep.tag, &dep.branch) {
+        (Some(tag), _) => (format!("refs/tags/{}", tag), format!("tag \"{}\"", tag)),
+        (None, Some(branch)) => (format!("refs/heads/{}", branch), format!("branch \"{}\"", branch)),
+        (None, None) => ("HEAD".to_owned(), "default branch".to_owned()),
+    };
+    match repo.find_reference(&reference).and_then(|reference| reference.peel_to_commit()) {
+        Ok(commit) => Ok(commit.id()),
+        Err(_) => Err(Error::msg(format!("\"{}\" has no {}", url, what))),
+    }
+}
+
+/// Whether `commit` is on a branch or tag of `repo`.
+fn is_published(repo: &git2::Repository, commit: git2::Oid) -> Result<bool> {
+    for reference in repo.references()? {
+        let reference = reference?;
+        let is_public = reference.name().map(|name| name.starts_with("refs/heads/") || name.starts_with("refs/tags/")).unwrap_or(false);
+        if !is_public {
+            continue;
+        }
+        if let Ok(tip) = reference.peel_to_commit() {
+            if tip.id() == commit || repo.graph_descendant_of(tip.id(), commit)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Checks out the git dependency `name` in `libdir` as a worktree of `shared`, a bare repository of `url`
+/// laid out like a mirror (see [`mirror_repository`]), which gets fetched first. All worktrees of an url
+/// share its objects. They are always detached, so checkouts of the same branch don't get in each other's way.
+///
+/// Existing worktrees with uncommitted changes or local commits are refused, unless `discard_local` is set.
+/// `preserve_local` doesn't apply, the stash would be shared by all worktrees.
+pub fn checkout_worktree(credentials: &Credentials, url: &str, dep: &TomlDependency, libdir: &Path, name: &str, shared: &Path, options: &CheckoutOptions) -> Result<()> {
+    let dst = libdir.join(name);
+    // projects updating at once would fetch into the same repository
+    let _lock = lock_dir(shared, true, credentials.reporter())?;
+    if !options.offline || is_local_url(url) {
+        credentials.reporter().info(&format!("Fetching \"{}\" into \"{}\"", url, shared.to_string_lossy()));
+        mirror_repository(credentials, url, shared)?;
+    } else if git2::Repository::open_bare(shared).is_err() {
+        return Err(Error::msg(format!("\"{}\" was never fetched, can't add a worktree of it in offline mode", url)));
+    }
+    let commit = mirrored_commit(&git2::Repository::open_bare(shared)?, dep, url)?;
+
+    if !dst.exists() {
+        credentials.reporter().info(&format!("Adding a worktree of \"{}\" at {} into \"{}\" as \"{}\"", url, &commit.to_string()[..10], libdir.to_string_lossy(), name));
+        std::fs::create_dir_all(libdir)?;
+        let path = absolute_path(&dst)?;
+        if let Err(e) = run_git(shared, &["worktree", "add", "--detach", &path.to_string_lossy(), &commit.to_string()]) {
+            if dst.exists() {
+                remove_dir_all::remove_dir_all(&dst)?;
+            }
+            let _ = run_git(shared, &["worktree", "prune"]);
+            return Err(e);
+        }
+    } else {
+        let repo = git2::Repository::open(&dst)?;
+        let mut changes = Vec::new();
+        let changed = uncommitted_changes(&repo, dep)?;
+        if changed > 0 {
+            changes.push(format!("uncommitted changes in {} file{}", changed, if changed == 1 { "" } else { "s" }));
+        }
+        let head = repo.head().ok().and_then(|head| head.target());
+        if let Some(head) = head.filter(|head| *head != commit) {
+            if !is_published(&repo, head)? {
+                changes.push("local commits".to_owned());
+            }
+        }
+        if !changes.is_empty() {
+            let changes = changes.join(" and ");
+            if !options.discard_local {
+                return Err(Error::msg(format!("\"{}\" has {}, commit and push them or use --discard-local to drop them", name, changes)));
+            }
+            credentials.reporter().info(&format!("Discarding {} of \"{}\"", changes, name));
+            discard_changes(&repo, &dst)?;
+        }
+        if head != Some(commit) {
+            credentials.reporter().info(&format!("Updating the worktree \"{}\" in \"{}\" to {}", name, libdir.to_string_lossy(), &commit.to_string()[..10]));
+            let target = repo.find_commit(commit)?;
+            checkout_commit(credentials, &repo, &target, &mut CheckoutBuilder::new())?;
+            repo.set_head_detached(commit)?;
+        }
+    }
+
+    if dep.submodules.unwrap_or(true) {
+        update_submodules(credentials, &git2::Repository::open(&dst)?, options.offline)?;
+    }
+    if dep.lfs.unwrap_or(false) {
+        pull_lfs_objects(&dst, credentials.reporter())?;
+    }
+    Ok(())
+}
+
+/// Fetches `specs` from `remote` of `repo`. Partial and shallow clones are fetched by git (like everything
+/// with the cli backend), git2 can't resolve the deltas of the pack against the objects they lack, nor
+/// negotiate the shallow history.
+fn fetch_remote(credentials: &Credentials, repo: &git2::Repository, remote: &mut git2::Remote, specs: &[&str]) -> Result<()> {
+    match repo.workdir() {
+        Some(workdir) if credentials.uses_cli(repo) => {
+            let mut args = vec!["fetch", "--quiet", remote.name().unwrap_or("origin")];
+            args.extend(specs);
+            run_git(workdir, &args)
+        }
+        _ => {
+            let url = remote.url().unwrap_or_default().to_owned();
+            Ok(remote.fetch(specs, Some(&mut credentials.fetch_options(&url)), None)?)
+        }
+    }
+}
+
+/// The commit `dep update` would check out in `repo`: the pinned revision, or the tip of the tag or branch
+/// on the remote (the default branch, if none is given). Offline, the last fetched state of the remote is used.
+pub fn target_commit(credentials: &Credentials, repo: &git2::Repository, dep: &TomlDependency, offline: bool) -> Result<git2::Oid> {
+    let mut remote = repo.find_remote("origin")?;
+    let url = remote.url().unwrap_or_default().to_owned();
+    if let Some(rev) = &dep.rev {
+        if find_revision(repo, rev).is_none() && !offline {
+            if let Some(branch) = &dep.branch {
+                let spec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch, branch);
+                fetch_remote(credentials, repo, &mut remote, &[&spec])?;
+            }
+        }
+        return find_revision(repo, rev).map(|commit| commit.id())
+            .ok_or_else(|| Error::msg(format!("\"{}\" has no revision \"{}\"", url, rev)));
+    }
+    if let Some(tag) = &dep.tag {
+        let full_tag = format!("refs/tags/{}", tag);
+        if !offline {
+            let spec = format!("+{}:{}", full_tag, full_tag);
+            fetch_remote(credentials, repo, &mut remote, &[&spec])?;
+        }
+        return Ok(repo.find_reference(&full_tag)?.peel_to_commit()?.id());
+    }
+    let branch = match (&dep.branch, offline) {
+        (Some(branch), _) => branch.clone(),
+        (None, false) => default_branch(credentials, &mut remote, &url)?,
+        // the branch dep checked out last time
+        (None, true) => match repo.head()?.shorthand() {
+            Some(branch) if repo.head()?.is_branch() => branch.to_owned(),
+            _ => return Err(Error::msg(format!("Could not detect the default branch of \"{}\" in offline mode", url))),
+        },
+    };
+    if !offline {
+        let spec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch, branch);
+        fetch_remote(credentials, repo, &mut remote, &[&spec])?;
+    }
+    Ok(repo.find_reference(&format!("refs/remotes/origin/{}", branch))?.peel_to_commit()?.id())
+}
+
+/// Sets up a new clone of `url` in `dst`, with the origin remote but nothing fetched yet, the checkout fetches
+/// only the branch, tag or revision it needs.
+///
+/// With a `reference`, a bare repository which has (most of) the objects already, the clone borrows its objects
+/// with git alternates instead of copying them, and only fetches what the reference is missing. Its branches
+/// and tags are taken over as those of the remote first, so the fetch knows what doesn't need to be sent.
+///
+/// Partial and shallow clones are made by git right away (like all clones with the cli backend), of only the
+/// branch or tag `wanted`, if it's known.
+fn init_clone(credentials: &Credentials, url: &str, dst: &Path, wanted: Option<&str>, options: &CheckoutOptions) -> Result<git2::Repository> {
+    let reference = match &options.reference {
+        Some(reference) => Some(reference),
+        // git2 can't make partial or shallow clones, nor fetch into them later. Without a wanted branch or tag,
+        // the cli backend fetches into the new repository as well, so a revision can be fetched by itself
+        None if options.filter.is_some() || options.shallow_since.is_some() || (credentials.backend == Backend::Cli && wanted.is_some()) => {
+            let mut args = vec!["clone".to_owned(), "--quiet".to_owned(), "--no-checkout".to_owned()];
+            if let Some(filter) = &options.filter {
+                credentials.reporter().info(&format!("Cloning with the filter \"{}\"", filter));
+                args.push(format!("--filter={}", filter));
+            }
+            if let Some(date) = &options.shallow_since {
+                credentials.reporter().info(&format!("Cloning the history since {}", date));
+                args.push(format!("--shallow-since={}", date));
+            }
+            if let Some(wanted) = wanted {
+                args.extend(["--single-branch".to_owned(), "--branch".to_owned(), wanted.to_owned()]);
+            }
+            args.extend([url.to_owned(), absolute_path(dst)?.to_string_lossy().to_string()]);
+            let parent = dst.parent().unwrap_or(dst);
+            run_git(parent, &args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>())?;
+            // git2 only opens version 0 repositories, git still honors the partial clone extension in them
+            run_git(dst, &["config", "core.repositoryformatversion", "0"])?;
+            return Ok(git2::Repository::open(dst)?);
+        }
+        None => None,
+    };
+    let repo = git2::Repository::init(dst)?;
+    if let Some(reference) = reference {
+        credentials.reporter().info(&format!("Borrowing the objects of \"{}\"", reference.to_string_lossy()));
+        let objects = absolute_path(reference.join("objects"))?;
+        std::fs::write(repo.path().join("objects").join("info").join("alternates"), format!("{}\n", objects.to_string_lossy()))?;
+    }
+    // opened again, so the object database sees the alternates
+    let repo = git2::Repository::open(dst)?;
+    if let Some(reference) = reference {
+        for cached in git2::Repository::open_bare(reference)?.references()? {
+            let cached = cached?;
+            let (name, target) = match (cached.name(), cached.target()) {
+                (Some(name), Some(target)) => (name, target),
+                _ => continue,
+            };
+            let name = match name.strip_prefix("refs/heads/") {
+                Some(branch) => format!("refs/remotes/origin/{}", branch),
+                None if name.starts_with("refs/tags/") => name.to_owned(),
+                None => continue,
+            };
+            repo.reference(&name, target, true, "dep: borrowed from the reference")?;
+        }
+    }
+    repo.remote("origin", url)?;
+    Ok(repo)
+}
+
+/// Moves the local `branch` to `commit` and checks it out, tracking the branch of the same name on origin.
+fn checkout_branch(credentials: &Credentials, repo: &git2::Repository, branch: &str, commit: &git2::Commit, co: &mut CheckoutBuilder) -> Result<()> {
+    // the checkout compares with HEAD, so it has to happen before the branch is moved,
+    // otherwise changed files look like local modifications and get skipped
+    checkout_commit(credentials, repo, commit, co)?;
+    let local_branch_name = format!("refs/heads/{}", branch);
+    repo.reference(&local_branch_name, commit.id(), true, "dep: update branch")?;
+    repo.find_branch(branch, git2::BranchType::Local)?.set_upstream(Some(&format!("origin/{}", branch)))?;
+    repo.set_head(&local_branch_name)?;
+    repo.cleanup_state()?;
+    Ok(())
+}
+
+/// Whether `repo` is a partial clone, which lacks objects git has to fetch on demand, or a shallow one,
+/// which lacks the older history. git2 can't fetch into either of them.
+fn is_incomplete(repo: &git2::Repository) -> bool {
+    if repo.path().join("shallow").exists() {
+        return true;
+    }
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(_) => return false,
+    };
+    // older versions of git only set the extension, newer ones mark the remote
+    config.get_string("extensions.partialclone").is_ok() || config.get_bool("remote.origin.promisor").unwrap_or(false)
+}
+
+/// Checks out the tree of `commit`, without moving HEAD. Partial and shallow clones are checked out by git
+/// (like everything with the cli backend), which fetches missing blobs, and end up with a detached HEAD.
+fn checkout_commit(credentials: &Credentials, repo: &git2::Repository, commit: &git2::Commit, co: &mut CheckoutBuilder) -> Result<()> {
+    match repo.workdir() {
+        Some(workdir) if credentials.uses_cli(repo) => run_git(workdir, &["checkout", "--quiet", "--force", "--detach", &commit.id().to_string()]),
+        _ => Ok(repo.checkout_tree(commit.as_object(), Some(co))?),
+    }
+}
+
+/// Does the actual work of [`checkout`] in `dst`, `libdir` and `name` are only used for messages.
+/// The reference, filter and shallow date of the options are only used for new clones.
+fn checkout_into(credentials: &Credentials, url: &str, dep: &TomlDependency, libdir: &Path, name: &str, dst: &Path, options: &CheckoutOptions) -> Result<()> {
+    let open = |wanted: Option<&str>| -> Result<git2::Repository> {
+        if dst.exists() {
+            return Ok(git2::Repository::open(dst)?);
+        }
+        std::fs::create_dir_all(dst)?;
+        init_clone(credentials, url, dst, wanted, options)
+    };
+    match (&dep.branch, &dep.tag, &dep.rev) {
+        (Some(branch_name), None, None) => {
+            credentials.reporter().info(&format!("Cloning branch \"{}\" from \"{}\" into \"{}\" as \"{}\"", branch_name, url, libdir.to_string_lossy(), name));
+            let repo = open(Some(branch_name))?;
+            let mut remote = repo.find_remote("origin")?;
+
+            let mut co = CheckoutBuilder::new();
+            co.refresh(true);
+            co.recreate_missing(true);
+            co.update_index(true);
+            co.allow_conflicts(false);
+            co.remove_untracked(true);
+
+            let spec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch_name, branch_name);
+            fetch_remote(credentials, &repo, &mut remote, &[&spec])?;
+
+            let commit = repo.find_reference(&format!("refs/remotes/origin/{}", branch_name))?.peel_to_commit()?;
+            checkout_branch(credentials, &repo, branch_name, &commit, &mut co)?;
+        }
+        (None, Some(tag), None) => {
+            credentials.reporter().info(&format!("Cloning tag \"{}\" from \"{}\" into \"{}\" as \"{}\"", tag, url, libdir.to_string_lossy(), name));
+            let repo = open(Some(tag))?;
+            let mut remote = repo.find_remote("origin")?;
+
+            let full_tag = format!("refs/tags/{}", tag);
+
+            let mut co = CheckoutBuilder::new();
+
+            fetch_remote(credentials, &repo, &mut remote, &[&format!("+{}:{}", full_tag, full_tag)])?;
+
+            // peeling to the commit works for annotated and lightweight tags
+            let commit = repo.find_reference(&full_tag)?.peel_to_commit()?;
+
+            checkout_commit(credentials, &repo, &commit, &mut co)?;
+
+            repo.set_head_detached(commit.id())?;
+        }
+        (branch_name, None, Some(rev)) => {
+            match branch_name {
+                Some(branch_name) => credentials.reporter().info(&format!("Cloning revision \"{}\" of branch \"{}\" from \"{}\" into \"{}\" as \"{}\"", rev, branch_name, url, libdir.to_string_lossy(), name)),
+                None => credentials.reporter().info(&format!("Cloning revision \"{}\" from \"{}\" into \"{}\" as \"{}\"", rev, url, libdir.to_string_lossy(), name)),
+            }
+            let repo = open(branch_name.as_deref())?;
+
+            // revisions don't change, they are only fetched if they're missing. Fetching the branch makes
+            // the revision reachable, even on servers that reject fetching arbitrary commits
+            if find_revision(&repo, rev).is_none() {
+                let mut remote = repo.find_remote("origin")?;
+                match branch_name {
+                    Some(branch_name) => {
+                        let spec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch_name, branch_name);
+                        fetch_remote(credentials, &repo, &mut remote, &[&spec])?;
+                    }
+                    None => {
+                        // most servers let full commit ids be fetched (uploadpack.allowReachableSHA1InWant),
+                        // otherwise all branches are fetched, like a clone would
+                        let fetched = rev.len() == 40 && fetch_remote(credentials, &repo, &mut remote, &[rev]).is_ok()
+                            && find_revision(&repo, rev).is_some();
+                        if !fetched {
+                            fetch_remote(credentials, &repo, &mut remote, &["+refs/heads/*:refs/remotes/origin/*"])?;
+                        }
+                    }
+                }
+            }
+
+            let mut co = CheckoutBuilder::new();
+
+            let commit = &find_revision(&repo, rev).ok_or_else(|| Error::msg(format!("\"{}\" has no revision \"{}\"", url, rev)))?;
+
+            checkout_commit(credentials, &repo, commit, &mut co)?;
+
+            repo.set_head_detached(commit.id())?;
+        }
+        _ => {
+            credentials.reporter().info(&format!("Cloning repository from \"{}\" into \"{}\" as \"{}\"", url, libdir.to_string_lossy(), name));
+            let repo = open(None)?;
+            let mut remote = repo.find_remote("origin")?;
+
+            let mut co = CheckoutBuilder::new();
+
+            // the default branch may have changed since the repo was cloned,
+            // so ask the remote where its HEAD points to
+            let branch_name = default_branch(credentials, &mut remote, url)?;
+            credentials.reporter().info(&format!("Updating default branch \"{}\"", branch_name));
+
+            let spec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch_name, branch_name);
+            fetch_remote(credentials, &repo, &mut remote, &[&spec])?;
+
+            let commit = repo.find_reference(&format!("refs/remotes/origin/{}", branch_name))?.peel_to_commit()?;
+
+            checkout_branch(credentials, &repo, &branch_name, &commit, &mut co)?;
+        }
+    };
+
+    if dep.submodules.unwrap_or(true) {
+        update_submodules(credentials, &git2::Repository::open(dst)?, options.offline)?;
+    }
+
+    if dep.lfs.unwrap_or(false) {
+        pull_lfs_objects(dst, credentials.reporter())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dep-git-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            remove_dir_all::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn commit(repo: &git2::Repository, files: &[(&str, &str)]) -> git2::Oid {
+        let mut tree = repo.treebuilder(None).unwrap();
+        for (name, content) in files {
+            tree.insert(name, repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let signature = git2::Signature::now("dep", "dep@localhost").unwrap();
+        let parent = repo.find_reference("refs/heads/main").and_then(|branch| branch.peel_to_commit()).ok();
+        repo.commit(Some("refs/heads/main"), &signature, &signature, "commit", &tree, parent.iter().collect::<Vec<_>>().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn short_revisions_are_cloned_and_up_to_date() {
+        let dir = temp_dir("short");
+        let remote = git2::Repository::init_bare(dir.join("remote.git")).unwrap();
+        remote.set_head("refs/heads/main").unwrap();
+        let first = commit(&remote, &[("a", "one")]);
+        commit(&remote, &[("a", "two")]);
+        let url = dir.join("remote.git").to_string_lossy().to_string();
+        let dep = TomlDependency { git: Some(url.clone()), rev: Some(first.to_string()[..10].to_owned()), ..Default::default() };
+        let credentials = Credentials::new(None, |_| Ok(String::new()));
+        checkout(&credentials, &url, &dep, &dir, "dep", &CheckoutOptions::default()).unwrap();
+
+        let repo = git2::Repository::open(dir.join("dep")).unwrap();
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), first);
+        assert!(is_up_to_date(&credentials, &repo, &url, &dep, false).unwrap());
+        remove_dir_all::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_checkouts_which_fail_partway() {
+        let dir = temp_dir("restore");
+        let remote = git2::Repository::init_bare(dir.join("remote.git")).unwrap();
+        remote.set_head("refs/heads/main").unwrap();
+        let first = commit(&remote, &[("a", "one")]);
+        let url = dir.join("remote.git").to_string_lossy().to_string();
+        let dep = TomlDependency { git: Some(url.clone()), branch: Some("main".to_owned()), ..Default::default() };
+        let credentials = Credentials::new(None, |_| Ok(String::new()));
+        checkout(&credentials, &url, &dep, &dir, "dep", &CheckoutOptions::default()).unwrap();
+
+        // "a" gets written before the name which is too long for the file system fails
+        commit(&remote, &[("a", "two"), (&"b".repeat(300), "")]);
+        assert!(checkout(&credentials, &url, &dep, &dir, "dep", &CheckoutOptions::default()).is_err());
+        let repo = git2::Repository::open(dir.join("dep")).unwrap();
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), first);
+        assert_eq!(std::fs::read_to_string(dir.join("dep").join("a")).unwrap(), "one");
+        remove_dir_all::remove_dir_all(&dir).unwrap();
+    }
+}
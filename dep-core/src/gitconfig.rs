@@ -0,0 +1,151 @@
+//! The settings of the git config of the user which dep applies like the git cli does: the
+//! `url.<base>.insteadOf` rewrites, `http.proxy` (and `http.<url>.proxy`) and the credential helpers.
+//!
+//! They are read from the system, global and xdg config, and `.git/config` of the repository dep runs in.
+
+use git2::{Config, Repository};
+
+/// What dep takes from the git config.
+#[derive(Debug, Default, Clone)]
+pub struct GitConfig {
+    // (prefix, base) of url.<base>.insteadOf
+    instead_of: Vec<(String, String)>,
+    // http.proxy
+    proxy: Option<String>,
+    // (url prefix, proxy) of http.<url>.proxy
+    url_proxies: Vec<(String, String)>,
+    // credential.helper or credential.<url>.helper is set
+    credential_helper: bool,
+}
+
+/// The values of the entries matching `pattern` (a regex), with the part of their names between `prefix` and `suffix`.
+fn subsection_values(config: &Config, pattern: &str, prefix: &str, suffix: &str) -> Vec<(String, String)> {
+    let entries = match config.entries(Some(pattern)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut values = Vec::new();
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        // only the section and key are lowercased, not the url between them
+        let name = entry.name().and_then(|name| name.get(prefix.len()..name.len().checked_sub(suffix.len())?));
+        if let (Some(name), Some(value)) = (name, entry.value()) {
+            values.push((name.to_owned(), value.to_owned()));
+        }
+    }
+    values
+}
+
+impl GitConfig {
+    /// Reads the git config, with `.git/config` of the repository of the working directory.
+    /// Missing or unreadable configs apply nothing.
+    pub fn load() -> GitConfig {
+        let config = Repository::discover(".").and_then(|repo| repo.config()).or_else(|_| Config::open_default());
+        let mut config = match config {
+            Ok(config) => config,
+            Err(_) => return GitConfig::default(),
+        };
+        match config.snapshot() {
+            Ok(snapshot) => GitConfig::from_config(&snapshot),
+            Err(_) => GitConfig::default(),
+        }
+    }
+
+    /// What dep takes from `config`, which has to be a snapshot for its strings to be read.
+    fn from_config(config: &Config) -> GitConfig {
+        let instead_of = subsection_values(config, r"^url\..*\.insteadof$", "url.", ".insteadof")
+            .into_iter().map(|(base, prefix)| (prefix, base)).collect();
+        let url_proxies = subsection_values(config, r"^http\..*\.proxy$", "http.", ".proxy");
+        let credential_helper = subsection_values(config, r"^credential\..*helper$", "credential", "helper")
+            .iter().any(|(_, helper)| !helper.is_empty());
+        GitConfig {
+            instead_of,
+            proxy: config.get_string("http.proxy").ok().filter(|proxy| !proxy.is_empty()),
+            url_proxies,
+            credential_helper,
+        }
+    }
+
+    /// `url` with the longest matching `insteadOf` prefix replaced by its base.
+    pub fn rewrite(&self, url: &str) -> String {
+        let rule = self.instead_of.iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match rule {
+            Some((prefix, base)) => format!("{}{}", base, &url[prefix.len()..]),
+            None => url.to_owned(),
+        }
+    }
+
+    /// The proxy for an http(s) url: the one of the longest matching `http.<url>.proxy`, otherwise `http.proxy`.
+    pub fn proxy(&self, url: &str) -> Option<&str> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return None;
+        }
+        let proxy = self.url_proxies.iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, proxy)| proxy.as_str());
+        // an empty value turns the proxy off for the url
+        proxy.or(self.proxy.as_deref()).filter(|proxy| !proxy.is_empty())
+    }
+
+    /// Whether git has credential helpers to ask for logins.
+    pub fn has_credential_helper(&self) -> bool {
+        self.credential_helper
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git_config(content: &str) -> GitConfig {
+        let path = std::env::temp_dir().join(format!("dep-gitconfig-{}-{}", std::process::id(), content.len()));
+        std::fs::write(&path, content).unwrap();
+        let config = GitConfig::from_config(&Config::open(&path).unwrap().snapshot().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn the_longest_instead_of_prefix_wins() {
+        let config = git_config(r#"
+[url "ssh://git@github.com/"]
+    insteadOf = https://github.com/
+[url "ssh://git@git.corp.com/Mirror/"]
+    insteadOf = https://github.com/org/
+    insteadOf = gh:
+"#);
+        let cases = [
+            ("https://github.com/other/repo", "ssh://git@github.com/other/repo"),
+            ("https://github.com/org/repo", "ssh://git@git.corp.com/Mirror/repo"),
+            ("gh:repo", "ssh://git@git.corp.com/Mirror/repo"),
+            ("https://gitlab.com/org/repo", "https://gitlab.com/org/repo"),
+            ("git@github.com:org/repo", "git@github.com:org/repo"),
+        ];
+        for (url, rewritten) in cases {
+            assert_eq!(config.rewrite(url), rewritten, "{}", url);
+        }
+    }
+
+    #[test]
+    fn url_proxies_win_over_the_default_one() {
+        let config = git_config(r#"
+[http]
+    proxy = http://proxy:3128
+[http "https://git.corp.com/"]
+    proxy = ""
+[http "https://github.com/Org/"]
+    proxy = http://org-proxy:3128
+"#);
+        assert_eq!(config.proxy("https://github.com/other/repo"), Some("http://proxy:3128"));
+        assert_eq!(config.proxy("https://github.com/Org/repo"), Some("http://org-proxy:3128"));
+        assert_eq!(config.proxy("https://git.corp.com/repo"), None);
+        assert_eq!(config.proxy("ssh://git@github.com/repo"), None);
+        assert!(!config.has_credential_helper());
+    }
+}
@@ -0,0 +1,325 @@
+//! Comparing vendored checkouts with the revisions of the manifest and the remote, used by `dep diff`, `dep log`,
+//! `dep changelog` and the report of `dep update`.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::git::{is_local_url, target_commit, Credentials};
+use crate::manifest::{dependency_dir, get_lib_dir, is_manifest_v2, parse, ManifestFormat, TomlDependency, TomlManifest, TomlManifestV2};
+use crate::registry::{is_registry_dependency, read_registry_index, resolve_registry_dependency};
+use crate::report::Reporter;
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub id: String,
+    // first line of the message
+    pub summary: String,
+    pub author: String,
+}
+
+/// The dependency `name`, with registry versions resolved, and the repository of its checkout.
+fn open_checkout(man: &TomlManifest, opts: &GlobalOptions, credentials: &Credentials, name: &str, offline: bool) -> Result<(TomlDependency, git2::Repository, Credentials)> {
+    let dep = match man.dependencies.as_ref().and_then(|deps| deps.get(name)) {
+        Some(dep) => dep.clone(),
+        None => return Err(Error::msg(format!("Unknown dependency \"{}\"", name))),
+    };
+    if dep.path.is_some() || dep.url.is_some() || dep.release.is_some() {
+        return Err(Error::msg(format!("\"{}\" is not a git dependency", name)));
+    }
+    let dep = if is_registry_dependency(&dep) {
+        resolve_registry_dependency(&read_registry_index(opts, credentials, offline)?, name, &dep)?
+    } else {
+        dep
+    };
+    let dst = dependency_dir(&get_lib_dir(man, opts), name, &dep);
+    let repo = match git2::Repository::open(&dst) {
+        Ok(repo) => repo,
+        Err(_) => return Err(Error::msg(format!("\"{}\" is not checked out, run \"dep update\" first", name))),
+    };
+    let url = repo.find_remote("origin")?.url().unwrap_or_default().to_owned();
+    let credentials = credentials.for_dependency(&dep);
+    if !offline && !url.is_empty() && !is_local_url(&url) {
+        credentials.ask_passphrase_for(&url)?;
+    }
+    Ok((dep, repo, credentials))
+}
+
+/// Patch from the revision `dep update` would check out for `name` (the pinned revision, or the tip of
+/// the tag or branch on the remote) to its vendored tree, uncommitted changes included.
+pub fn diff(man: &TomlManifest, opts: &GlobalOptions, credentials: &Credentials, name: &str, offline: bool) -> Result<String> {
+    let (dep, repo, credentials) = open_checkout(man, opts, credentials, name, offline)?;
+    let target = repo.find_commit(target_commit(&credentials, &repo, &dep, offline)?)?;
+
+    // no DiffOptions, git2 0.7 can't create them without undefined behaviour on current compilers
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&target.tree()?), None)?;
+
+    let mut patch = String::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if let '+' | '-' | ' ' = line.origin() {
+            patch.push(line.origin());
+        }
+        patch += &String::from_utf8_lossy(line.content());
+        true
+    })?;
+    Ok(patch)
+}
+
+/// Commits reachable from `to` but not from `from`, newest first.
+pub fn commits_between(repo: &git2::Repository, from: git2::Oid, to: git2::Oid) -> Result<Vec<LogEntry>> {
+    // git2 doesn't know where the history of shallow clones ends, and fails at the first missing parent
+    if repo.path().join("shallow").exists() {
+        return shallow_commits_between(repo, from, to);
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME);
+    walk.push(to)?;
+    walk.hide(from)?;
+    let mut entries = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        entries.push(LogEntry {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_owned(),
+            author: commit.author().name().unwrap_or_default().to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+/// [`commits_between`] for shallow clones, with git.
+fn shallow_commits_between(repo: &git2::Repository, from: git2::Oid, to: git2::Oid) -> Result<Vec<LogEntry>> {
+    let output = Command::new("git")
+        .args(["log", "--date-order", "--format=%H%x09%an%x09%s", &format!("{}..{}", from, to)])
+        .current_dir(repo.path())
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => return Err(Error::msg(format!("git log failed: {}", String::from_utf8_lossy(&output.stderr).trim()))),
+        Err(e) => return Err(Error::msg(format!("Could not run git, is it installed? ({})", e))),
+    };
+    let entries = String::from_utf8_lossy(&output.stdout).lines().filter_map(|line| {
+        let mut fields = line.splitn(3, '\t');
+        Some(LogEntry { id: fields.next()?.to_owned(), author: fields.next()?.to_owned(), summary: fields.next().unwrap_or_default().to_owned() })
+    }).collect();
+    Ok(entries)
+}
+
+/// Upstream commits of `name` which aren't vendored yet, up to the tip of its tag or branch on the remote.
+/// With `since_locked`, the log starts at the pinned revision instead of the vendored commit.
+pub fn log(man: &TomlManifest, opts: &GlobalOptions, credentials: &Credentials, name: &str, since_locked: bool, offline: bool) -> Result<Vec<LogEntry>> {
+    let (dep, repo, credentials) = open_checkout(man, opts, credentials, name, offline)?;
+    let from = if since_locked {
+        match &dep.rev {
+            Some(rev) => repo.revparse_single(rev)?.peel_to_commit()?.id(),
+            None => return Err(Error::msg(format!("\"{}\" has no pinned revision, \"dep pin\" adds one", name))),
+        }
+    } else {
+        repo.head()?.peel_to_commit()?.id()
+    };
+    // the tip, even if the dependency is pinned
+    let upstream = TomlDependency { rev: None, ..dep };
+    let to = target_commit(&credentials, &repo, &upstream, offline)?;
+    commits_between(&repo, from, to)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+/// A dependency which moved to another commit during an update.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevisionChange {
+    pub name: String,
+    // none for new checkouts
+    pub old: Option<String>,
+    pub new: String,
+    // commits of the new revision which the old one didn't have
+    pub commits: usize,
+    // what the manifest asks for, like "branch main"
+    #[serde(rename = "ref")]
+    pub reference: String,
+}
+
+/// The commit vendored at `dst`, if it's a git checkout.
+pub fn vendored_commit(dst: &Path) -> Option<git2::Oid> {
+    let repo = git2::Repository::open(dst).ok()?;
+    let oid = repo.head().ok()?.peel_to_commit().ok()?.id();
+    Some(oid)
+}
+
+/// The branch, tag or revision a dependency follows.
+pub fn describe_reference(dep: &TomlDependency) -> String {
+    match (&dep.branch, &dep.tag, &dep.rev) {
+        (_, Some(tag), _) => format!("tag {}", tag),
+        (Some(branch), None, Some(rev)) => format!("rev {} of branch {}", short_id(rev), branch),
+        (None, None, Some(rev)) => format!("rev {}", short_id(rev)),
+        (Some(branch), None, None) => format!("branch {}", branch),
+        (None, None, None) => "default branch".to_owned(),
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    &id[..id.len().min(10)]
+}
+
+/// Records how far the checkout at `dst` moved from `old`, if it moved at all.
+pub fn revision_change(name: &str, dep: &TomlDependency, dst: &Path, old: Option<git2::Oid>) -> Result<Option<RevisionChange>> {
+    let new = match vendored_commit(dst) {
+        Some(new) if Some(new) != old => new,
+        _ => return Ok(None),
+    };
+    let commits = match old {
+        Some(old) => commits_between(&git2::Repository::open(dst)?, old, new).map(|commits| commits.len()).unwrap_or(0),
+        None => 0,
+    };
+    Ok(Some(RevisionChange {
+        name: name.to_owned(),
+        old: old.map(|old| old.to_string()),
+        new: new.to_string(),
+        commits,
+        reference: describe_reference(dep),
+    }))
+}
+
+fn describe_change(change: &RevisionChange) -> String {
+    match &change.old {
+        Some(old) => format!("{} -> {}, {} commit{}, {}", short_id(old), short_id(&change.new),
+            change.commits, if change.commits == 1 { "" } else { "s" }, change.reference),
+        None => format!("new at {}, {}", short_id(&change.new), change.reference),
+    }
+}
+
+/// Reports the changes of an update as a list.
+pub fn report_changes(changes: &[RevisionChange], reporter: &dyn Reporter) {
+    if changes.is_empty() {
+        return;
+    }
+    let width = changes.iter().map(|change| change.name.len()).max().unwrap_or(0);
+    reporter.info("");
+    reporter.info("Updated revisions:");
+    for change in changes {
+        reporter.info(&format!("  {:<width$}  {}", change.name, describe_change(change), width = width));
+    }
+}
+
+/// The changes of an update as a markdown table or json, to paste into pull requests.
+pub fn render_report(changes: &[RevisionChange], format: ReportFormat) -> Result<String> {
+    match format {
+        ReportFormat::Json => Ok(serde_json::to_string_pretty(changes)? + "\n"),
+        ReportFormat::Markdown => {
+            let mut report = String::from("| Dependency | Old | New | Commits | Ref |\n|---|---|---|---|---|\n");
+            for change in changes {
+                let old = change.old.as_deref().map(|old| format!("`{}`", short_id(old))).unwrap_or_else(|| "new".to_owned());
+                report += &format!("| {} | {} | `{}` | {} | {} |\n", change.name, old, short_id(&change.new), change.commits, change.reference);
+            }
+            Ok(report)
+        }
+    }
+}
+
+/// The manifest at `file_path` as it is committed in the repository of the project, if it is.
+fn committed_manifest(file_path: &Path) -> Result<Option<TomlManifest>> {
+    let path = file_path.canonicalize()?;
+    let repo = match git2::Repository::discover(path.parent().unwrap_or(&path)) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(None),
+    };
+    let relative = match repo.workdir().and_then(|workdir| path.strip_prefix(workdir.canonicalize().ok()?).ok()) {
+        Some(relative) => relative.to_path_buf(),
+        None => return Ok(None),
+    };
+    let tree = match repo.head().and_then(|head| head.peel_to_tree()) {
+        Ok(tree) => tree,
+        Err(_) => return Ok(None),
+    };
+    let entry = match tree.get_path(&relative) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(None),
+    };
+    let blob = repo.find_blob(entry.id())?;
+    let content = String::from_utf8_lossy(blob.content()).to_string();
+    let format = ManifestFormat::from_path(file_path);
+    let man = if is_manifest_v2(&content, format) {
+        parse::<TomlManifestV2>(&content, format).and_then(TomlManifestV2::into_v1)
+    } else {
+        parse::<TomlManifest>(&content, format)
+    };
+    man.map(Some).map_err(|e| Error::msg(format!("The committed manifest can't be read: {}", e)))
+}
+
+/// The commit a pinned revision or tag of the committed manifest refers to.
+fn locked_commit(repo: &git2::Repository, dep: &TomlDependency) -> Option<git2::Oid> {
+    if let Some(rev) = &dep.rev {
+        // abbreviated revisions work too
+        return Some(repo.revparse_single(rev).ok()?.peel(git2::ObjectType::Commit).ok()?.id());
+    }
+    let tag = dep.tag.as_ref()?;
+    Some(repo.find_reference(&format!("refs/tags/{}", tag)).ok()?.peel_to_commit().ok()?.id())
+}
+
+/// Lines of the changelog at `new` which aren't in the one at `old`, if both have a CHANGELOG.md.
+fn changelog_lines(repo: &git2::Repository, old: git2::Oid, new: git2::Oid) -> Option<Vec<String>> {
+    let read = |oid: git2::Oid| -> Option<String> {
+        let tree = repo.find_commit(oid).ok()?.tree().ok()?;
+        let entry = tree.get_path(Path::new("CHANGELOG.md")).ok()?;
+        let blob = repo.find_blob(entry.id()).ok()?;
+        Some(String::from_utf8_lossy(blob.content()).to_string())
+    };
+    let (old, new) = (read(old)?, read(new)?);
+    let known = old.lines().collect::<std::collections::HashSet<_>>();
+    let added = new.lines().filter(|line| !known.contains(line)).map(|line| line.to_owned()).collect::<Vec<_>>();
+    if added.iter().all(|line| line.trim().is_empty()) {
+        return None;
+    }
+    Some(added)
+}
+
+/// A markdown changelog of every git dependency which moved away from the revision or tag pinned in the
+/// committed manifest. Dependencies with a CHANGELOG.md contribute its new lines, the others their commit subjects.
+pub fn changelog(file_path: &Path, man: &TomlManifest, opts: &GlobalOptions) -> Result<String> {
+    let committed = match committed_manifest(file_path)? {
+        Some(committed) => committed,
+        None => return Err(Error::msg(format!(
+            "\"{}\" is not committed to a git repository, there is no earlier state to compare with", file_path.to_string_lossy()
+        ))),
+    };
+    let locked = committed.dependencies.unwrap_or_default();
+    let libdir = get_lib_dir(man, opts);
+
+    let mut document = String::from("# Changelog\n");
+    let mut changed = 0;
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let dst = dependency_dir(&libdir, name, dep);
+        let repo = match git2::Repository::open(&dst) {
+            Ok(repo) => repo,
+            Err(_) => continue,
+        };
+        let (old, new) = match (locked.get(name).and_then(|locked| locked_commit(&repo, locked)), vendored_commit(&dst)) {
+            (Some(old), Some(new)) if old != new => (old, new),
+            _ => continue,
+        };
+        changed += 1;
+        document += &format!("\n## {} ({}..{})\n\n", name, short_id(&old.to_string()), short_id(&new.to_string()));
+        match changelog_lines(&repo, old, new) {
+            Some(lines) => {
+                for line in lines {
+                    document += &line;
+                    document += "\n";
+                }
+            }
+            None => {
+                for entry in commits_between(&repo, old, new)? {
+                    document += &format!("- {} ({})\n", entry.summary, short_id(&entry.id));
+                }
+            }
+        }
+    }
+    if changed == 0 {
+        document += "\nNo dependency changed since the committed manifest.\n";
+    }
+    Ok(document)
+}
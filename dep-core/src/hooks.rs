@@ -0,0 +1,106 @@
+//! Hooks defined in a `dep.rhai` script of the project.
+//!
+//! The script registers callbacks by defining functions with these names:
+//!
+//! - `before_fetch(name, dep)`: called before a dependency gets fetched, returning `false` skips it
+//! - `url_rewrite(name, url)`: returns the url to fetch from instead, or nothing to keep it
+//! - `after_checkout(name, dep, dir)`: called once a dependency is vendored into `dir`
+//!
+//! `dep` is the dependency as in the manifest. The constants `PROJECT` and `DEPENDENCIES`
+//! contain the `[project]` section and all dependencies. Throwing an error aborts the update.
+
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::manifest::{TomlDependency, TomlManifest};
+
+#[cfg(feature = "scripting")]
+const HOOKS: [&str; 3] = ["before_fetch", "url_rewrite", "after_checkout"];
+
+#[cfg(feature = "scripting")]
+pub struct Hooks {
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    scope: std::sync::Mutex<rhai::Scope<'static>>,
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct Hooks {}
+
+#[cfg(feature = "scripting")]
+fn script_error<E: std::fmt::Display>(path: &Path) -> impl Fn(E) -> Error + '_ {
+    move |e| Error::Script(format!("{}: {}", path.to_string_lossy(), e))
+}
+
+#[cfg(feature = "scripting")]
+impl Hooks {
+    /// Compiles the script and runs its top level statements.
+    pub fn load(path: &Path, man: &TomlManifest) -> Result<Hooks> {
+        let engine = rhai::Engine::new();
+        let ast = engine.compile_file(path.to_path_buf()).map_err(script_error(path))?;
+
+        let mut scope = rhai::Scope::new();
+        scope.push_constant("PROJECT", rhai::serde::to_dynamic(&man.project).map_err(script_error(path))?);
+        scope.push_constant("DEPENDENCIES", rhai::serde::to_dynamic(&man.dependencies).map_err(script_error(path))?);
+        engine.run_ast_with_scope(&mut scope, &ast).map_err(script_error(path))?;
+
+        Ok(Hooks { engine, ast, scope: std::sync::Mutex::new(scope) })
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> Result<Option<rhai::Dynamic>> {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(None);
+        }
+        let options = rhai::CallFnOptions::new().eval_ast(false);
+        self.engine.call_fn_with_options(options, &mut self.scope.lock().unwrap(), &self.ast, name, args)
+            .map(Some)
+            .map_err(|e| Error::Script(format!("{}: {}", name, e)))
+    }
+
+    fn dependency(dep: &TomlDependency) -> Result<rhai::Dynamic> {
+        rhai::serde::to_dynamic(dep).map_err(|e| Error::Script(e.to_string()))
+    }
+
+    /// The hooks the script defines.
+    pub fn defined(&self) -> Vec<&'static str> {
+        HOOKS.iter().copied().filter(|hook| self.ast.iter_functions().any(|f| f.name == *hook)).collect()
+    }
+
+    pub fn before_fetch(&self, name: &str, dep: &TomlDependency) -> Result<bool> {
+        let result = self.call("before_fetch", (name.to_owned(), Hooks::dependency(dep)?))?;
+        Ok(result.and_then(|r| r.as_bool().ok()).unwrap_or(true))
+    }
+
+    pub fn url_rewrite(&self, name: &str, url: &str) -> Result<String> {
+        let result = self.call("url_rewrite", (name.to_owned(), url.to_owned()))?;
+        Ok(result.and_then(|r| r.into_string().ok()).unwrap_or_else(|| url.to_owned()))
+    }
+
+    pub fn after_checkout(&self, name: &str, dep: &TomlDependency, dir: &Path) -> Result<()> {
+        self.call("after_checkout", (name.to_owned(), Hooks::dependency(dep)?, dir.to_string_lossy().to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+impl Hooks {
+    pub fn load(path: &Path, _man: &TomlManifest) -> Result<Hooks> {
+        Err(Error::Script(format!("{}: dep was built without scripting support", path.to_string_lossy())))
+    }
+
+    pub fn defined(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    pub fn before_fetch(&self, _name: &str, _dep: &TomlDependency) -> Result<bool> {
+        Ok(true)
+    }
+
+    pub fn url_rewrite(&self, _name: &str, url: &str) -> Result<String> {
+        Ok(url.to_owned())
+    }
+
+    pub fn after_checkout(&self, _name: &str, _dep: &TomlDependency, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,530 @@
+//! Dependencies read from the manifests of other tools, used by `dep import`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::manifest::TomlDependency;
+use crate::report::Reporter;
+
+/// Resolves submodule urls like `../other.git` against the url of the superproject.
+fn resolve_relative_url(base: &str, url: &str) -> String {
+    let mut base = base.trim_end_matches('/').to_owned();
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            rest = stripped;
+            match base.rfind(['/', ':']) {
+                Some(index) => base.truncate(index),
+                None => base.clear(),
+            }
+        } else {
+            break;
+        }
+    }
+    let separator = if base.ends_with(':') { "" } else { "/" };
+    format!("{}{}{}", base, separator, rest)
+}
+
+/// A dependency for every submodule of the repository at `dir`, pinned to the commit recorded for it.
+/// Submodules are named after the last component of their path.
+pub fn from_submodules(dir: &Path) -> Result<BTreeMap<String, TomlDependency>> {
+    let repo = git2::Repository::open(dir).map_err(|_| Error::msg(format!(
+        "\"{}\" is not a git repository, submodules can only be imported with their recorded commits", dir.to_string_lossy()
+    )))?;
+    let origin = repo.find_remote("origin").ok().and_then(|remote| remote.url().map(|url| url.to_owned()));
+
+    let mut deps = BTreeMap::new();
+    for submodule in repo.submodules()? {
+        let path = submodule.path();
+        let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let url = match submodule.url() {
+            Some(url) if url.starts_with("./") || url.starts_with("../") => match &origin {
+                Some(origin) => resolve_relative_url(origin, url),
+                None => return Err(Error::msg(format!(
+                    "Submodule \"{}\" has the relative url \"{}\", but the repository has no origin to resolve it against", name, url
+                ))),
+            },
+            Some(url) => url.to_owned(),
+            None => return Err(Error::msg(format!("Submodule \"{}\" has no url", name))),
+        };
+        let rev = submodule.index_id().or_else(|| submodule.head_id()).map(|oid| oid.to_string());
+        if deps.contains_key(&name) {
+            return Err(Error::msg(format!("Two submodules end in \"{}\", rename one of them first", name)));
+        }
+        deps.insert(name, TomlDependency {
+            git: Some(url),
+            // "." follows the branch of the superproject, which has no equivalent
+            branch: submodule.branch().filter(|branch| *branch != ".").map(|branch| branch.to_owned()),
+            rev,
+            ..Default::default()
+        });
+    }
+    Ok(deps)
+}
+
+/// Name of the dependency for a repository, the last component of its url.
+fn repository_name(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit(['/', ':']).next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name).to_owned()
+}
+
+/// Urls are compared without a trailing slash or .git.
+fn normalize_url(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url)
+}
+
+/// Adds `dep` as a dependency named after its repository. Crates of the same repository share one,
+/// unless they ask for different revisions.
+fn insert_repository(deps: &mut BTreeMap<String, TomlDependency>, dep: TomlDependency) -> Result<()> {
+    let url = dep.git.clone().unwrap_or_default();
+    let name = repository_name(&url);
+    match deps.get(&name) {
+        Some(existing) if (&existing.branch, &existing.tag, &existing.rev) != (&dep.branch, &dep.tag, &dep.rev) => Err(different_revisions(&url)),
+        Some(_) => Ok(()),
+        None => {
+            deps.insert(name, dep);
+            Ok(())
+        }
+    }
+}
+
+fn different_revisions(url: &str) -> Error {
+    Error::msg(format!("\"{}\" is used with different revisions, only one of them can be vendored", url))
+}
+
+/// Query values like `a%2Fb`, with their percent escapes decoded.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Git sources of a Cargo.lock (`git+url?branch=name#commit`) by url. A repository locked at two commits
+/// can't be vendored, that's an error.
+fn cargo_lock_sources(lock: &toml::Value) -> Result<BTreeMap<String, TomlDependency>> {
+    let mut sources: BTreeMap<String, TomlDependency> = BTreeMap::new();
+    let packages = lock.get("package").and_then(|packages| packages.as_array()).cloned().unwrap_or_default();
+    for package in packages {
+        let source = match package.get("source").and_then(|source| source.as_str()).and_then(|source| source.strip_prefix("git+")) {
+            Some(source) => source.to_owned(),
+            None => continue,
+        };
+        let (source, commit) = match source.split_once('#') {
+            Some((source, commit)) => (source.to_owned(), Some(commit.to_owned())),
+            None => (source, None),
+        };
+        let (url, query) = match source.split_once('?') {
+            Some((url, query)) => (url.to_owned(), query.to_owned()),
+            None => (source, String::new()),
+        };
+        let mut dep = TomlDependency { git: Some(url.clone()), rev: commit, ..Default::default() };
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            match key {
+                "branch" => dep.branch = Some(percent_decode(value)),
+                "tag" => dep.tag = Some(percent_decode(value)),
+                _ => (),
+            }
+        }
+        // every crate of a repository has an entry
+        match sources.get(normalize_url(&url)) {
+            Some(existing) if existing.rev != dep.rev => return Err(different_revisions(&url)),
+            Some(_) => (),
+            None => {
+                sources.insert(normalize_url(&url).to_owned(), dep);
+            }
+        }
+    }
+    Ok(sources)
+}
+
+const CARGO_DEPENDENCY_KINDS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// The tables of a cargo manifest which list dependencies, target specific and workspace ones included.
+fn cargo_dependency_tables(manifest: &toml::Value) -> Vec<&toml::value::Table> {
+    let mut parents = vec![manifest];
+    parents.extend(manifest.get("workspace"));
+    parents.extend(manifest.get("target").and_then(|targets| targets.as_table()).into_iter().flat_map(|targets| targets.values()));
+    parents.iter()
+        .flat_map(|parent| CARGO_DEPENDENCY_KINDS.iter().filter_map(move |kind| parent.get(*kind).and_then(|table| table.as_table())))
+        .collect()
+}
+
+/// The git dependencies of a Cargo.toml, pinned to the commits of the Cargo.lock next to it.
+/// Git packages which are only in the Cargo.lock, like those of other git dependencies, are added too.
+pub fn from_cargo(manifest_path: &Path) -> Result<BTreeMap<String, TomlDependency>> {
+    let manifest: toml::Value = toml::from_str(&std::fs::read_to_string(manifest_path)?)?;
+    let lock_path = manifest_path.with_file_name("Cargo.lock");
+    let locked = if lock_path.exists() {
+        cargo_lock_sources(&toml::from_str(&std::fs::read_to_string(&lock_path)?)?)?
+    } else {
+        BTreeMap::new()
+    };
+
+    let mut deps = BTreeMap::new();
+    for table in cargo_dependency_tables(&manifest) {
+        for spec in table.values() {
+            let url = match spec.get("git").and_then(|git| git.as_str()) {
+                Some(url) => url.to_owned(),
+                None => continue,
+            };
+            let key = |key: &str| spec.get(key).and_then(|value| value.as_str()).map(|value| value.to_owned());
+            let mut dep = TomlDependency { git: Some(url.clone()), branch: key("branch"), tag: key("tag"), rev: key("rev"), ..Default::default() };
+            if let Some(lock) = locked.get(normalize_url(&url)) {
+                dep.rev = lock.rev.clone();
+            }
+            insert_repository(&mut deps, dep)?;
+        }
+    }
+    // lock entries of repositories from the manifest were used already
+    let imported = deps.values().filter_map(|dep| dep.git.as_deref()).map(|url| normalize_url(url).to_owned()).collect::<Vec<_>>();
+    for (url, dep) in locked {
+        if !imported.contains(&url) {
+            insert_repository(&mut deps, dep)?;
+        }
+    }
+    Ok(deps)
+}
+
+/// The version of a vcpkg port, from the vcpkg.json in its directory.
+fn vcpkg_port_version(port: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(port.join("vcpkg.json")).ok()?;
+    let manifest: serde_json::Value = serde_json::from_str(&content).ok()?;
+    ["version", "version-semver", "version-date", "version-string"].iter()
+        .find_map(|key| manifest.get(*key).and_then(|version| version.as_str()))
+        .map(|version| version.to_owned())
+}
+
+/// The arguments of every call of `function` in a cmake script, split at whitespace.
+fn cmake_calls(script: &str, function: &str) -> Vec<Vec<String>> {
+    let mut calls = Vec::new();
+    let mut rest = script;
+    while let Some(start) = rest.find(&format!("{}(", function)) {
+        rest = &rest[start + function.len() + 1..];
+        let end = rest.find(')').unwrap_or(rest.len());
+        let arguments = rest[..end].lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.split_whitespace())
+            .map(|argument| argument.trim_matches('"').to_owned())
+            .collect();
+        calls.push(arguments);
+        rest = &rest[end..];
+    }
+    calls
+}
+
+/// The upstream repository and ref of a vcpkg port, from the vcpkg_from_* call of its portfile.
+fn vcpkg_port_source(port: &Path) -> Option<TomlDependency> {
+    let portfile = std::fs::read_to_string(port.join("portfile.cmake")).ok()?;
+    let sources = [
+        ("vcpkg_from_github", "https://github.com"),
+        ("vcpkg_from_gitlab", "https://gitlab.com"),
+        ("vcpkg_from_bitbucket", "https://bitbucket.org"),
+        ("vcpkg_from_git", ""),
+    ];
+    for (function, host) in sources.iter() {
+        let arguments = match cmake_calls(&portfile, function).into_iter().next() {
+            Some(arguments) => arguments,
+            None => continue,
+        };
+        let value = |key: &str| arguments.iter().position(|argument| argument == key)
+            .and_then(|index| arguments.get(index + 1)).cloned();
+        let url = match (value("URL"), value("REPO")) {
+            (Some(url), _) => url,
+            (None, Some(repo)) => format!("{}/{}", value("GITLAB_URL").unwrap_or_else(|| host.to_string()).trim_end_matches('/'), repo),
+            (None, None) => continue,
+        };
+        // refs are mostly derived from the version of the port
+        let reference = value("REF").map(|reference| match vcpkg_port_version(port) {
+            Some(version) => reference.replace("${VERSION}", &version),
+            None => reference,
+        }).filter(|reference| !reference.contains("${"));
+        let is_commit = reference.as_ref().map(|reference| reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())).unwrap_or(false);
+        let (tag, rev) = if is_commit { (None, reference) } else { (reference, None) };
+        return Some(TomlDependency { git: Some(url), tag, rev, ..Default::default() });
+    }
+    None
+}
+
+/// Skeleton entries for the dependencies of a vcpkg.json. Ports found in `$VCPKG_ROOT/ports`
+/// (or a vcpkg checkout next to the manifest) get the repository and ref of their portfile,
+/// the others become registry dependencies.
+pub fn from_vcpkg(manifest_path: &Path) -> Result<BTreeMap<String, TomlDependency>> {
+    let manifest: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(manifest_path)?)?;
+    let ports = match std::env::var_os("VCPKG_ROOT") {
+        Some(root) => Path::new(&root).join("ports"),
+        None => manifest_path.with_file_name("vcpkg").join("ports"),
+    };
+
+    let mut deps = BTreeMap::new();
+    for dependency in manifest.get("dependencies").and_then(|deps| deps.as_array()).into_iter().flatten() {
+        let (name, version) = match dependency {
+            serde_json::Value::String(name) => (name.clone(), None),
+            serde_json::Value::Object(dependency) => match dependency.get("name").and_then(|name| name.as_str()) {
+                // the port version after # has no meaning outside of vcpkg
+                Some(name) => (name.to_owned(), dependency.get("version>=").and_then(|version| version.as_str())
+                    .map(|version| version.split('#').next().unwrap_or(version).to_owned())),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let dep = match vcpkg_port_source(&ports.join(&name)) {
+            Some(dep) => dep,
+            None => TomlDependency { version, ..Default::default() },
+        };
+        deps.insert(name, dep);
+    }
+    Ok(deps)
+}
+
+/// Requirements like `zlib/1.2.13@user/channel#revision` as name and version. Version ranges are left out.
+fn conan_requirement(requirement: &str) -> Option<(String, Option<String>)> {
+    let (name, version) = requirement.split_once('/')?;
+    let version = version.split(['@', '#']).next().unwrap_or_default();
+    let is_name = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.+".contains(c));
+    if !is_name || version.is_empty() {
+        return None;
+    }
+    Some((name.to_owned(), if version.starts_with('[') { None } else { Some(version.to_owned()) }))
+}
+
+/// Skeleton entries for the requirements of a conanfile.txt or conanfile.py. Conan recipes don't
+/// know the repositories of their sources, so all of them become registry dependencies.
+pub fn from_conan(recipe_path: &Path) -> Result<BTreeMap<String, TomlDependency>> {
+    let content = std::fs::read_to_string(recipe_path)?;
+    let is_python = recipe_path.extension().map(|extension| extension == "py").unwrap_or(false);
+    let mut requirements = Vec::new();
+    if is_python {
+        // string literals of lines like requires = "zlib/1.2.13", or self.requires("zlib/1.2.13")
+        for line in content.lines().filter(|line| line.contains("requires")) {
+            let literals = line.split(['"', '\'']).skip(1).step_by(2);
+            requirements.extend(literals.filter_map(conan_requirement));
+        }
+    } else {
+        let mut in_requires = false;
+        for line in content.lines().map(|line| line.trim()) {
+            if line.starts_with('[') {
+                in_requires = ["[requires]", "[tool_requires]", "[build_requires]", "[test_requires]"].contains(&line);
+            } else if in_requires && !line.starts_with('#') {
+                requirements.extend(conan_requirement(line));
+            }
+        }
+    }
+
+    Ok(requirements.into_iter()
+        .map(|(name, version)| (name, TomlDependency { version, ..Default::default() }))
+        .collect())
+}
+
+/// Sets `into` and `as` so the dependency ends up at `path`, relative to the project.
+fn place_at(name: &str, dep: &mut TomlDependency, path: &str) -> Result<()> {
+    let path = Path::new(path.trim_end_matches('/'));
+    let dir_name = match path.file_name() {
+        Some(dir_name) => dir_name.to_string_lossy().to_string(),
+        None => return Err(Error::msg(format!("\"{}\" is imported into the project root, which dep can't vendor into", name))),
+    };
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    dep.into = Some(parent.to_path_buf());
+    if dir_name != name {
+        dep.name = Some(dir_name);
+    }
+    Ok(())
+}
+
+/// Versions like `v1.2.0` or `2.1`, which are usually tags.
+fn is_version(reference: &str) -> bool {
+    let version = reference.strip_prefix('v').unwrap_or(reference);
+    !version.is_empty() && version.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Refs of other tools can be branches, tags or commits. Hex strings are taken as commits, versions as tags
+/// and everything else as a branch. Which one was guessed for tags and branches gets reported.
+fn git_ref(name: &str, dep: &mut TomlDependency, reference: &str, reporter: &dyn Reporter) {
+    let is_commit = reference.len() >= 7 && reference.len() <= 40 && reference.chars().all(|c| c.is_ascii_hexdigit());
+    if is_commit {
+        dep.rev = Some(reference.to_owned());
+    } else if is_version(reference) {
+        // a tag can't be combined with a branch
+        dep.branch = None;
+        dep.tag = Some(reference.to_owned());
+        reporter.info(&format!("\"{}\": \"{}\" looks like a version, it's taken as a tag", name, reference));
+    } else {
+        dep.branch = Some(reference.to_owned());
+        reporter.info(&format!("\"{}\": \"{}\" is taken as a branch, change it to a tag if it is one", name, reference));
+    }
+}
+
+fn yaml_str<'a>(value: &'a serde_yaml::Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(|value| value.as_str())
+}
+
+/// The git modules of a peru.yaml, vendored where the `imports` put them. Peru's file rules
+/// (move, copy, pick, export, drop) have no equivalent, the whole repository is vendored and a note is printed.
+pub fn from_peru(config_path: &Path, reporter: &dyn Reporter) -> Result<BTreeMap<String, TomlDependency>> {
+    let config: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(config_path)?)?;
+    let mut imports = BTreeMap::new();
+    if let Some(mapping) = config.get("imports").and_then(|imports| imports.as_mapping()) {
+        for (module, path) in mapping {
+            if let (Some(module), Some(path)) = (module.as_str(), path.as_str()) {
+                // modules can be filtered by named rules, like module|rule
+                let mut parts = module.split('|');
+                let name = parts.next().unwrap_or_default().trim().to_owned();
+                if parts.next().is_some() {
+                    reporter.info(&format!("\"{}\": the rules applied in imports have no equivalent, the whole repository gets vendored", name));
+                }
+                imports.insert(name, path.to_owned());
+            }
+        }
+    }
+
+    let mut deps = BTreeMap::new();
+    for (key, module) in config.as_mapping().into_iter().flatten() {
+        let key = key.as_str().unwrap_or_default();
+        let (kind, name) = match key.split_once(" module ") {
+            Some((kind, name)) => (kind.trim(), name.trim()),
+            None => continue,
+        };
+        if kind != "git" {
+            reporter.info(&format!("Skipping \"{}\", {} modules aren't supported", name, kind));
+            continue;
+        }
+        let url = match yaml_str(module, "url") {
+            Some(url) => url.to_owned(),
+            None => return Err(Error::msg(format!("Module \"{}\" has no url", name))),
+        };
+        let mut dep = TomlDependency { git: Some(url), ..Default::default() };
+        // reup is the branch "peru reup" follows
+        dep.branch = yaml_str(module, "reup").map(|branch| branch.to_owned());
+        if let Some(rev) = yaml_str(module, "rev") {
+            git_ref(name, &mut dep, rev, reporter);
+        }
+        dep.submodules = module.get("submodules").and_then(|submodules| submodules.as_bool());
+        let rules = ["move", "copy", "pick", "export", "drop", "executable"].iter()
+            .filter(|rule| module.get(**rule).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        if !rules.is_empty() {
+            reporter.info(&format!("\"{}\": the {} rules have no equivalent, the whole repository gets vendored", name, rules.join(", ")));
+        }
+        if let Some(path) = imports.get(name) {
+            place_at(name, &mut dep, path)?;
+        }
+        deps.insert(name.to_owned(), dep);
+    }
+    Ok(deps)
+}
+
+/// The git sources of a gitman.yml, pinned to the commits of `sources_locked` if it has them
+/// and vendored where their `link` points.
+pub fn from_gitman(config_path: &Path, reporter: &dyn Reporter) -> Result<BTreeMap<String, TomlDependency>> {
+    let config: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(config_path)?)?;
+    let list = |key: &str| config.get(key).and_then(|sources| sources.as_sequence()).cloned().unwrap_or_default();
+    let locked = list("sources_locked").into_iter()
+        .filter_map(|source| Some((yaml_str(&source, "name")?.to_owned(), yaml_str(&source, "rev")?.to_owned())))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut deps = BTreeMap::new();
+    for source in list("sources") {
+        let url = match yaml_str(&source, "repo") {
+            Some(url) => url.to_owned(),
+            None => continue,
+        };
+        let name = yaml_str(&source, "name").map(|name| name.to_owned()).unwrap_or_else(|| repository_name(&url));
+        if yaml_str(&source, "type").unwrap_or("git") != "git" {
+            reporter.info(&format!("Skipping \"{}\", only git sources are supported", name));
+            continue;
+        }
+        let mut dep = TomlDependency { git: Some(url), ..Default::default() };
+        if let Some(rev) = yaml_str(&source, "rev") {
+            git_ref(&name, &mut dep, rev, reporter);
+        }
+        if let Some(rev) = locked.get(&name) {
+            // the locked commit is the one the tag points to
+            dep.tag = None;
+            dep.rev = Some(rev.clone());
+        }
+        if source.get("sparse_paths").and_then(|paths| paths.as_sequence()).map(|paths| !paths.is_empty()).unwrap_or(false) {
+            reporter.info(&format!("\"{}\": sparse paths have no equivalent, the whole repository gets vendored", name));
+        }
+        if let Some(link) = yaml_str(&source, "link") {
+            place_at(&name, &mut dep, link)?;
+        }
+        deps.insert(name, dep);
+    }
+    Ok(deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Silent;
+
+    #[test]
+    fn guesses_the_kind_of_refs() {
+        let guess = |reference: &str| {
+            let mut dep = TomlDependency { branch: Some("reup".to_owned()), ..Default::default() };
+            git_ref("lib", &mut dep, reference, &Silent);
+            (dep.branch, dep.tag, dep.rev)
+        };
+        assert_eq!(guess("v1.2.0"), (None, Some("v1.2.0".to_owned()), None));
+        assert_eq!(guess("2.1"), (None, Some("2.1".to_owned()), None));
+        assert_eq!(guess("3a5f2c1"), (Some("reup".to_owned()), None, Some("3a5f2c1".to_owned())));
+        assert_eq!(guess("main"), (Some("main".to_owned()), None, None));
+        assert_eq!(guess("v1..2"), (Some("v1..2".to_owned()), None, None));
+        assert_eq!(guess("v"), (Some("v".to_owned()), None, None));
+    }
+
+    #[test]
+    fn decodes_the_queries_of_cargo_lock_sources() {
+        let lock = toml::from_str(r#"
+            [[package]]
+            name = "a"
+            source = "git+https://github.com/org/repo?branch=feature%2Fx#3a5f2c1d"
+            [[package]]
+            name = "b"
+            source = "git+https://github.com/org/repo?branch=feature%2Fx#3a5f2c1d"
+            [[package]]
+            name = "c"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#).unwrap();
+        let sources = cargo_lock_sources(&lock).unwrap();
+        assert_eq!(sources.len(), 1);
+        let dep = &sources["https://github.com/org/repo"];
+        assert_eq!((dep.branch.as_deref(), dep.rev.as_deref()), (Some("feature/x"), Some("3a5f2c1d")));
+        assert_eq!(percent_decode("a%2fb%20c%zz%"), "a/b c%zz%");
+    }
+
+    #[test]
+    fn repositories_locked_at_two_commits_are_errors() {
+        let lock = toml::from_str(r#"
+            [[package]]
+            name = "a"
+            source = "git+https://github.com/org/repo#3a5f2c1d"
+            [[package]]
+            name = "b"
+            source = "git+https://github.com/org/repo.git#9b8c7d6e"
+        "#).unwrap();
+        let error = cargo_lock_sources(&lock).unwrap_err().to_string();
+        assert!(error.contains("is used with different revisions"), "{}", error);
+    }
+}
@@ -0,0 +1,156 @@
+//! The passphrase of a protected ssh key in the keychain of the OS, so it doesn't have to be entered on
+//! every run. `dep auth set` stores it and `dep auth clear` removes it.
+//!
+//! The keychains are used through their command line tools: `secret-tool` of libsecret (the Secret Service),
+//! `security` on macOS (which asks for the passphrase itself) and the password vault of the Windows Credential
+//! Manager through PowerShell.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::SshOptions;
+use crate::error::{Error, Result};
+use crate::paths::normalize;
+
+const SERVICE: &str = "dep";
+
+/// Entries are per key, so switching keys doesn't use the wrong passphrase.
+fn account(ssh: &SshOptions) -> String {
+    format!("ssh:{}", normalize(&ssh.private).to_string_lossy())
+}
+
+/// Runs `command` with `input` on its stdin, returning its stdout if it succeeded.
+fn run(command: &mut Command, input: Option<&str>) -> Result<Option<String>> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    Ok(if output.status.success() { Some(String::from_utf8_lossy(&output.stdout).to_string()) } else { None })
+}
+
+#[cfg(target_os = "macos")]
+mod backend {
+    use std::process::Command;
+
+    use super::{run, SERVICE};
+    use crate::error::{Error, Result};
+
+    pub const TOOL: &str = "security";
+
+    pub fn lookup(account: &str) -> Result<Option<String>> {
+        let secret = run(Command::new(TOOL).args(["find-generic-password", "-s", SERVICE, "-a", account, "-w"]), None)?;
+        Ok(secret.map(|secret| secret.trim_end_matches('\n').to_owned()))
+    }
+
+    // the secret would show up in the arguments where every process can read it, so `security` asks for it
+    pub const ASKS: bool = true;
+
+    /// `-w` without a value as the last argument makes `security` ask for the secret on the terminal.
+    pub fn store(account: &str, secret: Option<&str>) -> Result<bool> {
+        if secret.is_some() {
+            return Err(Error::msg(format!("{} only takes the passphrase from the terminal, run dep auth set in one", TOOL)));
+        }
+        Ok(Command::new(TOOL).args(["add-generic-password", "-U", "-s", SERVICE, "-a", account, "-w"]).status()?.success())
+    }
+
+    pub fn clear(account: &str) -> Result<bool> {
+        Ok(run(Command::new(TOOL).args(["delete-generic-password", "-s", SERVICE, "-a", account]), None)?.is_some())
+    }
+}
+
+#[cfg(windows)]
+mod backend {
+    use std::process::Command;
+
+    use super::{run, SERVICE};
+    use crate::error::{Error, Result};
+
+    pub const TOOL: &str = "powershell";
+
+    const VAULT: &str = "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+        $vault = New-Object Windows.Security.Credentials.PasswordVault;";
+
+    /// The account and the service are passed in the environment, so they don't need quoting.
+    fn powershell(script: &str, account: &str, input: Option<&str>) -> Result<Option<String>> {
+        let mut command = Command::new(TOOL);
+        command.args(["-NoProfile", "-NonInteractive", "-Command", &format!("{} {}", VAULT, script)])
+            .env("DEP_KEYCHAIN_SERVICE", SERVICE)
+            .env("DEP_KEYCHAIN_ACCOUNT", account);
+        run(&mut command, input)
+    }
+
+    pub fn lookup(account: &str) -> Result<Option<String>> {
+        powershell("$credential = $vault.Retrieve($env:DEP_KEYCHAIN_SERVICE, $env:DEP_KEYCHAIN_ACCOUNT); \
+            $credential.RetrievePassword(); [Console]::Out.Write($credential.Password)", account, None)
+    }
+
+    pub const ASKS: bool = false;
+
+    pub fn store(account: &str, secret: Option<&str>) -> Result<bool> {
+        let secret = secret.ok_or_else(|| Error::msg("The passphrase to store is missing"))?;
+        let script = "$vault.Add((New-Object Windows.Security.Credentials.PasswordCredential($env:DEP_KEYCHAIN_SERVICE, \
+            $env:DEP_KEYCHAIN_ACCOUNT, [Console]::In.ReadLine())))";
+        Ok(powershell(script, account, Some(&format!("{}\n", secret)))?.is_some())
+    }
+
+    pub fn clear(account: &str) -> Result<bool> {
+        Ok(powershell("$vault.Remove($vault.Retrieve($env:DEP_KEYCHAIN_SERVICE, $env:DEP_KEYCHAIN_ACCOUNT))", account, None)?.is_some())
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod backend {
+    use std::process::Command;
+
+    use super::{run, SERVICE};
+    use crate::error::{Error, Result};
+
+    pub const TOOL: &str = "secret-tool";
+
+    pub fn lookup(account: &str) -> Result<Option<String>> {
+        run(Command::new(TOOL).args(["lookup", "service", SERVICE, "account", account]), None)
+    }
+
+    pub const ASKS: bool = false;
+
+    pub fn store(account: &str, secret: Option<&str>) -> Result<bool> {
+        let secret = secret.ok_or_else(|| Error::msg("The passphrase to store is missing"))?;
+        let label = format!("dep passphrase of {}", account);
+        Ok(run(Command::new(TOOL).args(["store", "--label", &label, "service", SERVICE, "account", account]), Some(secret))?.is_some())
+    }
+
+    pub fn clear(account: &str) -> Result<bool> {
+        Ok(run(Command::new(TOOL).args(["clear", "service", SERVICE, "account", account]), None)?.is_some())
+    }
+}
+
+/// The stored passphrase of the ssh key, if there is one. A missing keychain counts as none.
+pub fn passphrase(ssh: &SshOptions) -> Option<String> {
+    backend::lookup(&account(ssh)).ok().flatten().filter(|passphrase| !passphrase.is_empty())
+}
+
+/// Whether the keychain tool asks for the passphrase itself instead of taking it from dep.
+pub const ASKS_FOR_PASSPHRASE: bool = backend::ASKS;
+
+/// Stores the passphrase of the ssh key, replacing the one stored before.
+/// Without one the keychain tool asks for it, see `ASKS_FOR_PASSPHRASE`.
+pub fn store_passphrase(ssh: &SshOptions, passphrase: Option<&str>) -> Result<()> {
+    match backend::store(&account(ssh), passphrase) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::msg(format!("{} could not store the passphrase, is the keychain unlocked?", backend::TOOL))),
+        Err(e) => Err(Error::msg(format!("Could not run {}, is it installed? ({})", backend::TOOL, e))),
+    }
+}
+
+/// Removes the stored passphrase of the ssh key. Returns false if there was none.
+pub fn clear_passphrase(ssh: &SshOptions) -> Result<bool> {
+    if passphrase(ssh).is_none() {
+        return Ok(false);
+    }
+    match backend::clear(&account(ssh)) {
+        Ok(true) => Ok(true),
+        Ok(false) => Err(Error::msg(format!("{} could not remove the passphrase", backend::TOOL))),
+        Err(e) => Err(Error::msg(format!("Could not run {}, is it installed? ({})", backend::TOOL, e))),
+    }
+}
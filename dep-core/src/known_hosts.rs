@@ -0,0 +1,154 @@
+//! Checks the host keys of ssh servers against `~/.ssh/known_hosts`, which libgit2 doesn't do by itself.
+//!
+//! libgit2 only hands over the md5 and sha1 hashes of the host key, so the entries of known_hosts are
+//! compared by their fingerprint, which `ssh-keygen` prints (hashed host names included). New hosts are
+//! added with the key `ssh-keyscan` gets, if it's the one the server showed to libgit2.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::config::HostKeyChecking;
+use crate::error::{Error, Result};
+use crate::paths::get_home_dir;
+use crate::report::Reporter;
+
+fn known_hosts_path() -> Result<PathBuf> {
+    Ok(PathBuf::from(get_home_dir()?).join(".ssh").join("known_hosts"))
+}
+
+/// The name of a host in known_hosts, with the port if it isn't the default one.
+fn host_name(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if port != 22 => format!("[{}]:{}", host, port),
+        _ => host.to_owned(),
+    }
+}
+
+/// `MD5:aa:bb:...`, the way `ssh-keygen -E md5` prints fingerprints.
+fn md5_fingerprint(md5: &[u8; 16]) -> String {
+    format!("MD5:{}", md5.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":"))
+}
+
+/// Runs an ssh tool with `input` on its stdin and returns its stdout, or None if it failed.
+fn run(tool: &str, args: &[&str], input: Option<&str>) -> Result<Option<String>> {
+    let mut child = match Command::new(tool).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+        Ok(child) => child,
+        Err(e) => return Err(Error::msg(format!(
+            "Could not run {} to check the host key, install it or set network.host-key-checking = \"off\" ({})", tool, e))),
+    };
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    Ok(if output.status.success() { Some(String::from_utf8_lossy(&output.stdout).to_string()) } else { None })
+}
+
+/// The fingerprints of the keys known_hosts has for `name`, none if it doesn't know the host.
+fn known_fingerprints(name: &str) -> Result<Vec<String>> {
+    let path = known_hosts_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let output = run("ssh-keygen", &["-l", "-E", "md5", "-F", name, "-f", &path.to_string_lossy()], None)?;
+    Ok(listed_fingerprints(&output.unwrap_or_default()))
+}
+
+/// The fingerprints `ssh-keygen -l -F` lists, "<name> <type> <fingerprint> [comment]" after "# Host ... found" lines.
+fn listed_fingerprints(output: &str) -> Vec<String> {
+    output.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().nth(2).map(|fingerprint| fingerprint.to_owned()))
+        .collect()
+}
+
+/// Adds the host key with the `fingerprint` to known_hosts, with the key ssh-keyscan gets from the host.
+fn add_host_key(host: &str, port: Option<u16>, fingerprint: &str, reporter: &dyn Reporter) -> Result<()> {
+    let name = host_name(host, port);
+    let port = port.unwrap_or(22).to_string();
+    let scanned = run("ssh-keyscan", &["-p", &port, host], None)?.unwrap_or_default();
+    for line in scanned.lines().filter(|line| !line.starts_with('#') && !line.trim().is_empty()) {
+        let printed = run("ssh-keygen", &["-l", "-E", "md5", "-f", "-"], Some(line))?.unwrap_or_default();
+        // "<bits> <fingerprint> <name> (<type>)"
+        if printed.split_whitespace().nth(1) != Some(fingerprint) {
+            continue;
+        }
+        let path = known_hosts_path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "{}", line)?;
+        reporter.info(&format!("Added the host key of \"{}\" ({}) to \"{}\"", name, fingerprint, path.to_string_lossy()));
+        return Ok(());
+    }
+    Err(Error::msg(format!("ssh-keyscan didn't get the host key {} from \"{}\", which the server showed to dep", fingerprint, name)))
+}
+
+/// Checks the host key of `host`, given by its md5 hash. Keys which don't match known_hosts are always
+/// refused, hosts it doesn't know only with `strict` (`accept-new` adds them).
+pub fn check_host_key(host: &str, port: Option<u16>, md5: Option<&[u8; 16]>, mode: HostKeyChecking, reporter: &dyn Reporter) -> Result<()> {
+    if mode == HostKeyChecking::Off {
+        return Ok(());
+    }
+    let name = host_name(host, port);
+    let fingerprint = match md5 {
+        Some(md5) => md5_fingerprint(md5),
+        None => return Err(Error::msg(format!("The ssh server \"{}\" didn't show a host key which can be checked", name))),
+    };
+    let known = known_fingerprints(&name)?;
+    if known.contains(&fingerprint) {
+        return Ok(());
+    }
+    if !known.is_empty() {
+        return Err(Error::msg(format!("The host key of \"{}\" ({}) doesn't match the one in known_hosts, somebody could be \
+            intercepting the connection. Remove the old key with \"ssh-keygen -R '{}'\" if it was changed on purpose", name, fingerprint, name)));
+    }
+    match mode {
+        HostKeyChecking::AcceptNew => add_host_key(host, port, &fingerprint, reporter),
+        _ => Err(Error::msg(format!("\"{}\" isn't in known_hosts, add its host key ({}) with \"ssh-keyscan -p {} {} >> ~/.ssh/known_hosts\" \
+            after checking it", name, fingerprint, port.unwrap_or(22), host))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_hosts_like_known_hosts() {
+        let cases = [
+            ("github.com", None, "github.com"),
+            ("github.com", Some(22), "github.com"),
+            ("git.corp.com", Some(7999), "[git.corp.com]:7999"),
+        ];
+        for (host, port, name) in cases {
+            assert_eq!(host_name(host, port), name);
+        }
+    }
+
+    #[test]
+    fn formats_fingerprints_like_ssh_keygen() {
+        let md5 = [0x16, 0x27, 0xac, 0xa5, 0x76, 0x28, 0x2d, 0x36, 0x63, 0x1b, 0x56, 0x4d, 0xeb, 0xdf, 0xa6, 0x48];
+        assert_eq!(md5_fingerprint(&md5), "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48");
+    }
+
+    #[test]
+    fn reads_the_fingerprints_ssh_keygen_lists() {
+        let output = "# Host github.com found: line 1\n\
+            github.com ED25519 MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48\n\
+            # Host github.com found: line 2\n\
+            github.com RSA MD5:d5:2c:63:d9:bc:75:9d:de:b1:4e:36:28:9f:7a:9c:39 comment\n";
+        assert_eq!(listed_fingerprints(output), vec![
+            "MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48",
+            "MD5:d5:2c:63:d9:bc:75:9d:de:b1:4e:36:28:9f:7a:9c:39",
+        ]);
+        assert!(listed_fingerprints("").is_empty());
+    }
+
+    #[test]
+    fn checking_can_be_turned_off() {
+        assert!(check_host_key("github.com", None, None, HostKeyChecking::Off, &crate::report::Silent).is_ok());
+        assert!(check_host_key("github.com", None, None, HostKeyChecking::Strict, &crate::report::Silent).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! The core of dep: the manifest model, resolution of registry dependencies,
+//! fetching of git repositories and archives, and linking of local paths.
+//!
+//! Nothing in here exits the process, keeps global state or prints, so it can be embedded
+//! by other tools. Progress goes to the [`Reporter`] of the caller, see [`report`].
+
+#[macro_use]
+extern crate serde_derive;
+
+pub mod audit;
+pub mod bundle;
+pub mod cache;
+pub mod check;
+pub mod config;
+pub mod doctor;
+pub mod emit;
+mod error;
+pub mod exec;
+pub mod export;
+pub mod fetch;
+pub mod filelock;
+pub mod git;
+pub mod gitconfig;
+pub mod history;
+pub mod hooks;
+pub mod import;
+pub mod keychain;
+pub mod known_hosts;
+pub mod manifest;
+pub mod metadata;
+pub mod mirror;
+pub mod paths;
+pub mod policy;
+pub mod registry;
+pub mod report;
+pub mod requirements;
+pub mod sbom;
+pub mod ssh_config;
+pub mod status;
+pub mod update;
+pub mod url;
+
+pub use crate::config::GlobalOptions;
+pub use crate::error::{Error, Result};
+pub use crate::git::Credentials;
+pub use crate::manifest::{TomlDependency, TomlManifest};
+pub use crate::report::{Level, Reporter};
+pub use crate::update::{update, UpdateOptions};
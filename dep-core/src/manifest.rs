@@ -0,0 +1,1037 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+
+use crate::check::with_suggestion;
+use crate::config::{apply_env_overrides, resolve_server, server_protocol, GlobalOptions, Protocol};
+use crate::error::{Error, Result};
+use crate::report::Reporter;
+use crate::url::make_url;
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TomlDependency {
+    pub path: Option<PathBuf>,
+    pub repo: Option<String>,
+    pub git: Option<String>,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    pub into: Option<PathBuf>,
+    pub server: Option<String>,
+    pub version: Option<String>,
+    // archive download, verified against the sha256 checksum
+    pub url: Option<String>,
+    pub sha256: Option<String>,
+    // asset of a GitHub or GitLab release, the repository is taken from `git` or `repo`
+    pub release: Option<String>,
+    pub asset: Option<String>,
+    // initialize and update submodules recursively, defaults to true
+    pub submodules: Option<bool>,
+    // fetch git lfs objects, needs git-lfs to be installed
+    pub lfs: Option<bool>,
+    // partial clone filter of new clones, like blob:none, needs git to be installed
+    pub filter: Option<String>,
+    // history of new clones only goes back to this date, like 2023-01-01, needs git to be installed
+    pub shallow_since: Option<String>,
+    // private key for this dependency only, like a deploy key, instead of the one of the global config
+    pub ssh_key: Option<PathBuf>,
+    // downloads the source archive of the tag or rev from GitHub or GitLab instead of cloning, verified against sha256
+    pub archive: Option<bool>,
+    // ssh or https, for the url made from server and repo
+    pub protocol: Option<Protocol>,
+    #[serde(rename="as")]
+    pub name: Option<String>,
+    // other dependencies which have to be vendored first, like ones the hooks of this one use
+    pub needs: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TomlProject {
+    pub name: String,
+    pub lib_dir: Option<PathBuf>,
+    pub git_server: Option<String>,
+
+    // package metadata
+    pub authors: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub metadata: Option<toml::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TomlManifest {
+    // other manifests, whose dependencies get merged into this one
+    pub include: Option<Vec<PathBuf>>,
+    pub project: TomlProject,
+    pub dependencies: Option<BTreeMap<String, TomlDependency>>,
+    // overrides of the global config for this project
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub config: Option<toml::Value>,
+}
+
+/// Version 2 of the manifest, where every dependency has exactly one kind of source.
+/// After reading, it gets converted into a [`TomlManifest`].
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TomlManifestV2 {
+    pub version: u32,
+    pub include: Option<Vec<PathBuf>>,
+    pub project: TomlProject,
+    #[serde(default, rename = "dependency")]
+    pub dependencies: Vec<TomlDependencyV2>,
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub config: Option<toml::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TomlDependencyV2 {
+    pub name: String,
+    pub into: Option<PathBuf>,
+    #[serde(rename="as")]
+    pub dir_name: Option<String>,
+    pub needs: Option<Vec<String>>,
+    pub source: TomlSource,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum TomlSource {
+    Git {
+        url: String,
+        branch: Option<String>,
+        tag: Option<String>,
+        rev: Option<String>,
+        submodules: Option<bool>,
+        lfs: Option<bool>,
+        filter: Option<String>,
+        shallow_since: Option<String>,
+        ssh_key: Option<PathBuf>,
+        archive: Option<bool>,
+        sha256: Option<String>,
+    },
+    // repository on a (named) git server
+    Repo {
+        repo: String,
+        server: Option<String>,
+        protocol: Option<Protocol>,
+        branch: Option<String>,
+        tag: Option<String>,
+        rev: Option<String>,
+        submodules: Option<bool>,
+        lfs: Option<bool>,
+        filter: Option<String>,
+        shallow_since: Option<String>,
+        ssh_key: Option<PathBuf>,
+        archive: Option<bool>,
+        sha256: Option<String>,
+    },
+    Path {
+        path: PathBuf,
+    },
+    Archive {
+        url: String,
+        sha256: Option<String>,
+    },
+    Release {
+        // git url of the repository
+        url: String,
+        release: String,
+        asset: String,
+        sha256: Option<String>,
+    },
+    Registry {
+        version: Option<String>,
+        branch: Option<String>,
+        tag: Option<String>,
+        rev: Option<String>,
+    },
+}
+
+impl TomlManifestV2 {
+    pub fn into_v1(self) -> std::result::Result<TomlManifest, String> {
+        let dependencies = TomlManifestV2::dependencies_into_v1(self.dependencies)?;
+        Ok(TomlManifest {
+            include: self.include,
+            project: self.project,
+            dependencies: if dependencies.is_empty() { None } else { Some(dependencies) },
+            config: self.config,
+        })
+    }
+
+    /// Version 1 has one dependency per name, so names used more than once are an error.
+    pub fn dependencies_into_v1(dependencies: Vec<TomlDependencyV2>) -> std::result::Result<BTreeMap<String, TomlDependency>, String> {
+        let mut deps = BTreeMap::new();
+        for dep in dependencies {
+            let mut v1 = TomlDependency { into: dep.into, name: dep.dir_name, needs: dep.needs, ..TomlDependency::default() };
+            match dep.source {
+                TomlSource::Git { url, branch, tag, rev, submodules, lfs, filter, shallow_since, ssh_key, archive, sha256 } => {
+                    v1.git = Some(url);
+                    v1.branch = branch;
+                    v1.tag = tag;
+                    v1.rev = rev;
+                    v1.submodules = submodules;
+                    v1.lfs = lfs;
+                    v1.filter = filter;
+                    v1.shallow_since = shallow_since;
+                    v1.ssh_key = ssh_key;
+                    v1.archive = archive;
+                    v1.sha256 = sha256;
+                }
+                TomlSource::Repo { repo, server, protocol, branch, tag, rev, submodules, lfs, filter, shallow_since, ssh_key, archive, sha256 } => {
+                    v1.repo = Some(repo);
+                    v1.server = server;
+                    v1.protocol = protocol;
+                    v1.branch = branch;
+                    v1.tag = tag;
+                    v1.rev = rev;
+                    v1.submodules = submodules;
+                    v1.lfs = lfs;
+                    v1.filter = filter;
+                    v1.shallow_since = shallow_since;
+                    v1.ssh_key = ssh_key;
+                    v1.archive = archive;
+                    v1.sha256 = sha256;
+                }
+                TomlSource::Path { path } => v1.path = Some(path),
+                TomlSource::Archive { url, sha256 } => {
+                    v1.url = Some(url);
+                    v1.sha256 = sha256;
+                }
+                TomlSource::Release { url, release, asset, sha256 } => {
+                    v1.git = Some(url);
+                    v1.release = Some(release);
+                    v1.asset = Some(asset);
+                    v1.sha256 = sha256;
+                }
+                TomlSource::Registry { version, branch, tag, rev } => {
+                    v1.version = version;
+                    v1.branch = branch;
+                    v1.tag = tag;
+                    v1.rev = rev;
+                }
+            }
+            if deps.contains_key(&dep.name) {
+                return Err(format!("the dependency \"{}\" is declared more than once", dep.name));
+            }
+            deps.insert(dep.name, v1);
+        }
+        Ok(deps)
+    }
+
+    pub fn from_v1(man: TomlManifest, opts: &GlobalOptions) -> TomlManifestV2 {
+        let git_server = man.project.git_server.clone();
+        let dependencies = man.dependencies.unwrap_or_default().into_iter()
+            .map(|(name, dep)| TomlDependencyV2::from_v1(name, dep, git_server.as_ref(), opts))
+            .collect();
+
+        TomlManifestV2 { version: 2, include: man.include, project: man.project, dependencies, config: man.config }
+    }
+}
+
+impl TomlDependencyV2 {
+    pub fn from_v1(name: String, dep: TomlDependency, git_server: Option<&String>, opts: &GlobalOptions) -> TomlDependencyV2 {
+        let source = if let Some(path) = dep.path {
+            TomlSource::Path { path }
+        } else if let Some(url) = dep.url {
+            TomlSource::Archive { url, sha256: dep.sha256 }
+        } else if let (Some(release), Some(asset)) = (dep.release, dep.asset) {
+            let url = match (dep.git, dep.repo, dep.server.or_else(|| git_server.cloned())) {
+                (Some(git), _, _) => git,
+                (None, Some(repo), Some(server)) => {
+                    let server = resolve_server(opts, &server);
+                    make_url(&server, &repo, dep.protocol.or_else(|| server_protocol(opts, &server)))
+                }
+                (None, repo, _) => repo.unwrap_or_default(),
+            };
+            TomlSource::Release { url, release, asset, sha256: dep.sha256 }
+        } else if let Some(url) = dep.git {
+            TomlSource::Git { url, branch: dep.branch, tag: dep.tag, rev: dep.rev, submodules: dep.submodules, lfs: dep.lfs, filter: dep.filter, shallow_since: dep.shallow_since, ssh_key: dep.ssh_key, archive: dep.archive, sha256: dep.sha256 }
+        } else if let Some(repo) = dep.repo {
+            TomlSource::Repo { repo, server: dep.server, protocol: dep.protocol, branch: dep.branch, tag: dep.tag, rev: dep.rev, submodules: dep.submodules, lfs: dep.lfs, filter: dep.filter, shallow_since: dep.shallow_since, ssh_key: dep.ssh_key, archive: dep.archive, sha256: dep.sha256 }
+        } else {
+            TomlSource::Registry { version: dep.version, branch: dep.branch, tag: dep.tag, rev: dep.rev }
+        };
+        TomlDependencyV2 { name, source, into: dep.into, dir_name: dep.name, needs: dep.needs }
+    }
+}
+
+/// A manifest included by another one. It only contains dependencies,
+/// either as `[dependencies]` table or as `[[dependency]]` list.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TomlIncludedManifest {
+    // both layouts are accepted, so the version doesn't matter
+    #[allow(dead_code)]
+    version: Option<u32>,
+    include: Option<Vec<PathBuf>>,
+    dependencies: Option<BTreeMap<String, TomlDependency>>,
+    dependency: Option<Vec<TomlDependencyV2>>,
+}
+
+/// Only used to find out which version of the manifest has to be parsed.
+#[derive(Deserialize)]
+struct ManifestVersion {
+    version: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ManifestFormat {
+    pub fn from_name(name: &str) -> Option<ManifestFormat> {
+        match name.to_lowercase().as_str() {
+            "toml" => Some(ManifestFormat::Toml),
+            "json" => Some(ManifestFormat::Json),
+            "yaml" | "yml" => Some(ManifestFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> ManifestFormat {
+        path.extension()
+            .and_then(|ext| ManifestFormat::from_name(&ext.to_string_lossy()))
+            .unwrap_or(ManifestFormat::Toml)
+    }
+}
+
+/// Finds the manifest of the current project. Without an explicit format,
+/// the first existing of deps.toml, deps.json, deps.yaml and deps.yml is used.
+pub fn find_manifest(format: Option<ManifestFormat>) -> PathBuf {
+    let candidates: &[&str] = match format {
+        None => &["./deps.toml", "./deps.json", "./deps.yaml", "./deps.yml"],
+        Some(ManifestFormat::Toml) => &["./deps.toml"],
+        Some(ManifestFormat::Json) => &["./deps.json"],
+        Some(ManifestFormat::Yaml) => &["./deps.yaml", "./deps.yml"],
+    };
+    candidates.iter()
+        .map(PathBuf::from)
+        .find(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(candidates[0]))
+}
+
+/// The manifest of the project in `dir`, like the one of a vendored dependency.
+pub fn manifest_in(dir: &Path) -> Option<PathBuf> {
+    ["deps.toml", "deps.json", "deps.yaml", "deps.yml"].iter().map(|name| dir.join(name)).find(|path| path.exists())
+}
+
+pub fn parse<T: serde::de::DeserializeOwned>(content: &str, format: ManifestFormat) -> std::result::Result<T, String> {
+    match format {
+        ManifestFormat::Toml => toml::de::from_str(content).map_err(|e| e.to_string()),
+        ManifestFormat::Json => serde_json::from_str(content).map_err(|e| e.to_string()),
+        ManifestFormat::Yaml => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+    }
+}
+
+pub fn is_manifest_v2(content: &str, format: ManifestFormat) -> bool {
+    parse::<ManifestVersion>(content, format).map(|v| v.version == Some(2)).unwrap_or(false)
+}
+
+pub fn read_manifest(path: &Path) -> Result<TomlManifest> {
+    Ok(read_manifest_with_origins(path)?.0)
+}
+
+/// Reads the manifest like [`read_manifest`], with `DEP_LIB_DIR` and `DEP_GIT_SERVER` applied.
+/// Everything that works with the dependencies uses this, only code writing the manifest back reads it as it is.
+pub fn load_manifest(path: &Path) -> Result<TomlManifest> {
+    Ok(load_manifest_with_origins(path)?.0)
+}
+
+/// [`read_manifest_with_origins`] with the environment overrides of [`load_manifest`] applied.
+pub fn load_manifest_with_origins(path: &Path) -> Result<(TomlManifest, BTreeMap<String, PathBuf>)> {
+    let (mut man, origins) = read_manifest_with_origins(path)?;
+    apply_env_overrides(&mut man);
+    Ok((man, origins))
+}
+
+fn manifest_error(message: &str) -> Error {
+    Error::Manifest(with_suggestion(message))
+}
+
+/// Reads the manifest and all manifests it includes. Returns the merged manifest
+/// and the file each dependency is defined in.
+///
+/// Includes are merged in order, so later includes override earlier ones,
+/// and the dependencies of a manifest override those of its includes.
+pub fn read_manifest_with_origins(path: &Path) -> Result<(TomlManifest, BTreeMap<String, PathBuf>)> {
+    let config = std::fs::read_to_string(path)?;
+    let format = ManifestFormat::from_path(path);
+    let result = if is_manifest_v2(&config, format) {
+        parse::<TomlManifestV2>(&config, format).and_then(TomlManifestV2::into_v1)
+    } else {
+        parse::<TomlManifest>(&config, format)
+    };
+    let mut man = result.map_err(|e| manifest_error(&e))?;
+
+    let mut origins = BTreeMap::new();
+    if let Some(includes) = &man.include {
+        let mut deps = BTreeMap::new();
+        let mut stack = vec![path.canonicalize()?];
+        include_dependencies(path, includes, &mut stack, &mut deps, &mut origins)?;
+        for (name, dep) in man.dependencies.take().unwrap_or_default() {
+            origins.insert(name.clone(), path.to_path_buf());
+            deps.insert(name, dep);
+        }
+        man.dependencies = Some(deps);
+    } else {
+        for name in man.dependencies.iter().flat_map(|deps| deps.keys()) {
+            origins.insert(name.clone(), path.to_path_buf());
+        }
+    }
+    Ok((man, origins))
+}
+
+fn include_dependencies(
+    including: &Path,
+    includes: &[PathBuf],
+    stack: &mut Vec<PathBuf>,
+    deps: &mut BTreeMap<String, TomlDependency>,
+    origins: &mut BTreeMap<String, PathBuf>,
+) -> Result<()> {
+    // includes are relative to the including manifest
+    let base = including.parent().unwrap_or_else(|| Path::new("."));
+    for include in includes {
+        let path = base.join(include);
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) => return Err(Error::msg(format!("Could not include \"{}\": {}", path.to_string_lossy(), e))),
+        };
+        if stack.contains(&canonical) {
+            return Err(Error::msg(format!("\"{}\" is included recursively", path.to_string_lossy())));
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        let included = parse::<TomlIncludedManifest>(&content, ManifestFormat::from_path(&path))
+            .map_err(|e| manifest_error(&format!("{}: {}", path.to_string_lossy(), e)))?;
+
+        if let Some(nested) = &included.include {
+            stack.push(canonical);
+            include_dependencies(&path, nested, stack, deps, origins)?;
+            stack.pop();
+        }
+
+        let v2_deps = TomlManifestV2::dependencies_into_v1(included.dependency.unwrap_or_default())
+            .map_err(|e| manifest_error(&format!("{}: {}", path.to_string_lossy(), e)))?;
+        let included_deps = included.dependencies.unwrap_or_default().into_iter().chain(v2_deps);
+        for (name, dep) in included_deps {
+            origins.insert(name.clone(), path.clone());
+            deps.insert(name, dep);
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a version 1 manifest as version 2. The old manifest is kept as `<manifest>.bak`.
+pub fn migrate(path: &Path, opts: &GlobalOptions, reporter: &dyn Reporter) -> Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let format = ManifestFormat::from_path(path);
+    if is_manifest_v2(&content, format) {
+        // still reports problems, like dependencies declared twice
+        read_manifest(path)?;
+        reporter.info(&format!("\"{}\" already is a version 2 manifest", path.to_string_lossy()));
+        return Ok(());
+    }
+
+    let man = read_manifest(path)?;
+    let backup = PathBuf::from(format!("{}.bak", path.to_string_lossy()));
+    std::fs::copy(path, &backup)?;
+    write_serialized(path, &TomlManifestV2::from_v1(man, opts))?;
+    reporter.info(&format!("Migrated \"{}\" to version 2, the old manifest was saved as \"{}\"", path.to_string_lossy(), backup.to_string_lossy()));
+    Ok(())
+}
+
+pub fn write_manifest(path: &Path, man: &TomlManifest) -> Result<()> {
+    write_serialized(path, man)
+}
+
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(remove_nulls),
+        _ => (),
+    }
+}
+
+pub fn write_serialized<T: serde::Serialize>(path: &Path, man: &T) -> Result<()> {
+    let format = ManifestFormat::from_path(path);
+    let content = if format == ManifestFormat::Toml {
+        toml::ser::to_string_pretty(man)?
+    } else {
+        // unset options would be nulls otherwise
+        let mut value = serde_json::to_value(man)?;
+        remove_nulls(&mut value);
+        if format == ManifestFormat::Json {
+            serde_json::to_string_pretty(&value)? + "\n"
+        } else {
+            serde_yaml::to_string(&value)?
+        }
+    };
+
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Sets `key` of the given dependencies in the manifest. For TOML manifests,
+/// formatting and comments are preserved.
+pub fn set_dependency_keys(path: &Path, key: &str, values: &BTreeMap<String, String>) -> Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let format = ManifestFormat::from_path(path);
+    let v2 = is_manifest_v2(&content, format);
+    let content = match format {
+        ManifestFormat::Toml => {
+            let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+            for (name, value) in values {
+                if v2 {
+                    let tables = doc["dependency"].as_array_of_tables_mut().into_iter().flat_map(|tables| tables.iter_mut());
+                    for table in tables.filter(|table| table.get("name").and_then(|n| n.as_str()) == Some(name.as_str())) {
+                        let source = &mut table["source"];
+                        source[key] = toml_edit::value(value.as_str());
+                        if let Some(source) = source.as_inline_table_mut() {
+                            source.fmt();
+                        }
+                    }
+                } else {
+                    let entry = &mut doc["dependencies"][name.as_str()];
+                    entry[key] = toml_edit::value(value.as_str());
+                    if let Some(table) = entry.as_inline_table_mut() {
+                        table.fmt();
+                    }
+                }
+            }
+            doc.to_string()
+        }
+        ManifestFormat::Json => {
+            let mut doc: serde_json::Value = serde_json::from_str(&content)?;
+            for (name, value) in values {
+                if v2 {
+                    for dep in doc["dependency"].as_array_mut().into_iter().flatten().filter(|dep| dep["name"] == name.as_str()) {
+                        dep["source"][key] = serde_json::Value::from(value.as_str());
+                    }
+                } else {
+                    doc["dependencies"][name.as_str()][key] = serde_json::Value::from(value.as_str());
+                }
+            }
+            serde_json::to_string_pretty(&doc)? + "\n"
+        }
+        ManifestFormat::Yaml => {
+            let mut doc: serde_yaml::Value = serde_yaml::from_str(&content)?;
+            for (name, value) in values {
+                if v2 {
+                    for dep in doc["dependency"].as_sequence_mut().into_iter().flatten().filter(|dep| dep["name"] == name.as_str()) {
+                        dep["source"][key] = serde_yaml::Value::from(value.as_str());
+                    }
+                } else {
+                    doc["dependencies"][name.as_str()][key] = serde_yaml::Value::from(value.as_str());
+                }
+            }
+            serde_yaml::to_string(&doc)?
+        }
+    };
+
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Renames the dependency `old` of the manifest (or include) to `new`, or with `dir_name`, only sets its `as`.
+/// For TOML manifests, formatting and comments are preserved.
+pub fn rename_dependency(path: &Path, old: &str, new: &str, dir_name: bool) -> Result<()> {
+    let content = std::fs::read_to_string(path)?;
+    let format = ManifestFormat::from_path(path);
+    let key = if dir_name { "as" } else { "name" };
+    // includes can have dependencies of both versions
+    let content = match format {
+        ManifestFormat::Toml => {
+            let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+            let tables = doc.get_mut("dependency").and_then(|tables| tables.as_array_of_tables_mut());
+            for table in tables.into_iter().flat_map(|tables| tables.iter_mut()) {
+                if table.get("name").and_then(|n| n.as_str()) == Some(old) {
+                    table[key] = toml_edit::value(new);
+                }
+            }
+            let deps = doc.get_mut("dependencies");
+            if dir_name {
+                if let Some(entry) = deps.and_then(|deps| deps.as_table_like_mut()).and_then(|deps| deps.get_mut(old)) {
+                    entry["as"] = toml_edit::value(new);
+                    if let Some(table) = entry.as_inline_table_mut() {
+                        table.fmt();
+                    }
+                }
+            } else if let Some(deps) = deps {
+                if let Some(deps) = deps.as_table_mut() {
+                    let order = deps.iter().map(|(name, _)| if name == old { new } else { name }.to_owned()).collect::<Vec<_>>();
+                    if let Some((name, entry)) = deps.remove_entry(old) {
+                        // the comments of the entry and its place in the table are kept
+                        deps.insert_formatted(&toml_edit::Key::new(new).with_leaf_decor(name.leaf_decor().clone()), entry);
+                        let position = |name: &toml_edit::Key| order.iter().position(|n| n == name.get());
+                        deps.sort_values_by(|a, _, b, _| position(a).cmp(&position(b)));
+                    }
+                } else if let Some(deps) = deps.as_table_like_mut() {
+                    if let Some(entry) = deps.remove(old) {
+                        deps.insert(new, entry);
+                    }
+                }
+            }
+            doc.to_string()
+        }
+        ManifestFormat::Json => {
+            let mut doc: serde_json::Value = serde_json::from_str(&content)?;
+            for dep in doc.get_mut("dependency").and_then(|deps| deps.as_array_mut()).into_iter().flatten().filter(|dep| dep["name"] == old) {
+                dep[key] = serde_json::Value::from(new);
+            }
+            if let Some(deps) = doc.get_mut("dependencies").and_then(|deps| deps.as_object_mut()) {
+                if dir_name {
+                    if let Some(entry) = deps.get_mut(old) {
+                        entry["as"] = serde_json::Value::from(new);
+                    }
+                } else if let Some(entry) = deps.remove(old) {
+                    deps.insert(new.to_owned(), entry);
+                }
+            }
+            serde_json::to_string_pretty(&doc)? + "\n"
+        }
+        ManifestFormat::Yaml => {
+            let mut doc: serde_yaml::Value = serde_yaml::from_str(&content)?;
+            for dep in doc.get_mut("dependency").and_then(|deps| deps.as_sequence_mut()).into_iter().flatten().filter(|dep| dep["name"] == old) {
+                dep[key] = serde_yaml::Value::from(new);
+            }
+            if let Some(deps) = doc.get_mut("dependencies").and_then(|deps| deps.as_mapping_mut()) {
+                if dir_name {
+                    if let Some(entry) = deps.get_mut(old) {
+                        entry["as"] = serde_yaml::Value::from(new);
+                    }
+                } else if let Some(entry) = deps.remove(old) {
+                    deps.insert(serde_yaml::Value::from(new), entry);
+                }
+            }
+            serde_yaml::to_string(&doc)?
+        }
+    };
+
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Toml version of a serialized value, tables become inline tables.
+fn toml_value(value: &serde_json::Value) -> Option<toml_edit::Value> {
+    match value {
+        serde_json::Value::String(s) => Some(s.as_str().into()),
+        serde_json::Value::Bool(b) => Some((*b).into()),
+        serde_json::Value::Number(n) => n.as_i64().map(|n| n.into()).or_else(|| n.as_f64().map(|n| n.into())),
+        serde_json::Value::Array(values) => Some(toml_edit::Value::Array(values.iter().filter_map(toml_value).collect())),
+        serde_json::Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (key, value) in map {
+                if let Some(value) = toml_value(value) {
+                    table.insert(key, value);
+                }
+            }
+            table.fmt();
+            Some(toml_edit::Value::InlineTable(table))
+        }
+        serde_json::Value::Null => None,
+    }
+}
+
+/// Adds dependencies to the manifest, keeping the ones which exist already. For TOML manifests,
+/// formatting and comments are preserved. Returns the names of the added dependencies.
+pub fn add_dependencies(path: &Path, opts: &GlobalOptions, deps: &BTreeMap<String, TomlDependency>, reporter: &dyn Reporter) -> Result<Vec<String>> {
+    let man = read_manifest(path)?;
+    let existing = man.dependencies.unwrap_or_default();
+    let mut added = Vec::new();
+    for name in deps.keys() {
+        if existing.contains_key(name) {
+            reporter.info(&format!("\"{}\" is in the manifest already, keeping it", name));
+        } else {
+            added.push(name.clone());
+        }
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    let format = ManifestFormat::from_path(path);
+    let v2 = is_manifest_v2(&content, format);
+    // serialized like they'd be written, without unset options
+    let mut values = Vec::new();
+    for name in &added {
+        let dep = deps[name].clone();
+        let mut value = if v2 {
+            serde_json::to_value(TomlDependencyV2::from_v1(name.clone(), dep, man.project.git_server.as_ref(), opts))?
+        } else {
+            serde_json::to_value(dep)?
+        };
+        remove_nulls(&mut value);
+        values.push((name, value));
+    }
+
+    let content = match format {
+        ManifestFormat::Toml => {
+            let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+            for (name, value) in values {
+                if v2 {
+                    let mut table = toml_edit::Table::new();
+                    for (key, value) in value.as_object().into_iter().flatten() {
+                        if let Some(value) = toml_value(value) {
+                            table.insert(key, toml_edit::Item::Value(value));
+                        }
+                    }
+                    if doc.get("dependency").is_none() {
+                        doc.insert("dependency", toml_edit::Item::ArrayOfTables(toml_edit::ArrayOfTables::new()));
+                    }
+                    if let Some(tables) = doc["dependency"].as_array_of_tables_mut() {
+                        tables.push(table);
+                    }
+                } else {
+                    if doc.get("dependencies").is_none() {
+                        doc.insert("dependencies", toml_edit::table());
+                    }
+                    if let Some(value) = toml_value(&value) {
+                        doc["dependencies"][name.as_str()] = toml_edit::Item::Value(value);
+                    }
+                }
+            }
+            doc.to_string()
+        }
+        ManifestFormat::Json | ManifestFormat::Yaml => {
+            let mut doc: serde_json::Value = if format == ManifestFormat::Json {
+                serde_json::from_str(&content)?
+            } else {
+                serde_yaml::from_str(&content)?
+            };
+            for (name, value) in values {
+                if v2 {
+                    if !doc["dependency"].is_array() {
+                        doc["dependency"] = serde_json::Value::Array(Vec::new());
+                    }
+                    if let Some(deps) = doc["dependency"].as_array_mut() {
+                        deps.push(value);
+                    }
+                } else {
+                    if !doc["dependencies"].is_object() {
+                        doc["dependencies"] = serde_json::Value::Object(Default::default());
+                    }
+                    doc["dependencies"][name.as_str()] = value;
+                }
+            }
+            if format == ManifestFormat::Json {
+                serde_json::to_string_pretty(&doc)? + "\n"
+            } else {
+                serde_yaml::to_string(&doc)?
+            }
+        }
+    };
+
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(added)
+}
+
+pub fn get_lib_dir(man: &TomlManifest, opts: &GlobalOptions) -> PathBuf {
+    match &man.project.lib_dir {
+        Some(dir) => dir.clone(),
+        None => opts.general.default_lib_dir.clone(),
+    }
+}
+
+/// The directory a dependency gets vendored into.
+pub fn dependency_dir(libdir: &Path, name: &str, dep: &TomlDependency) -> PathBuf {
+    dep.into.clone().unwrap_or_else(|| libdir.to_path_buf()).join(dep.name.as_deref().unwrap_or(name))
+}
+
+/// The names of the dependencies in the order they get vendored: every one after the ones it `needs`,
+/// otherwise by name. Fails for needs which aren't dependencies and for dependencies needing each other.
+pub fn dependency_order(deps: &BTreeMap<String, TomlDependency>) -> Result<Vec<String>> {
+    fn visit<'a>(name: &'a str, deps: &'a BTreeMap<String, TomlDependency>, stack: &mut Vec<&'a str>, order: &mut Vec<String>) -> Result<()> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+        if let Some(start) = stack.iter().position(|other| *other == name) {
+            let cycle = stack[start..].iter().chain(std::iter::once(&name)).cloned().collect::<Vec<_>>().join(" -> ");
+            return Err(Error::msg(format!("Dependencies need each other: {}", cycle)));
+        }
+        stack.push(name);
+        for need in deps[name].needs.iter().flatten() {
+            if !deps.contains_key(need) {
+                return Err(Error::msg(format!("\"{}\" needs \"{}\", which isn't a dependency", name, need)));
+            }
+            visit(need, deps, stack, order)?;
+        }
+        stack.pop();
+        order.push(name.to_owned());
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    for name in deps.keys() {
+        visit(name, deps, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Git dependencies that follow a branch, instead of a tag or revision.
+pub fn is_floating(dep: &TomlDependency) -> bool {
+    dep.path.is_none() && dep.url.is_none() && dep.release.is_none()
+        && dep.tag.is_none() && dep.rev.is_none() && dep.version.is_none()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependencies(toml: &str) -> BTreeMap<String, TomlDependency> {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn v1_dependency(toml: &str) -> TomlDependency {
+        let manifest: TomlManifestV2 = toml::from_str(&format!("version = 2\n[project]\nname = \"test\"\n{}", toml)).unwrap();
+        let mut deps = manifest.into_v1().unwrap().dependencies.unwrap();
+        assert_eq!(deps.len(), 1);
+        deps.remove("dep").unwrap()
+    }
+
+    #[test]
+    fn git_sources_become_git_dependencies() {
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            into = "third_party"
+            as = "lib"
+            source = { kind = "git", url = "https://host/dep.git", branch = "main", submodules = true, lfs = true, filter = "blob:none" }
+        "#);
+        assert_eq!(dep.git.as_deref(), Some("https://host/dep.git"));
+        assert_eq!(dep.branch.as_deref(), Some("main"));
+        assert_eq!((dep.submodules, dep.lfs, dep.filter.as_deref()), (Some(true), Some(true), Some("blob:none")));
+        assert_eq!((dep.into, dep.name.as_deref()), (Some(PathBuf::from("third_party")), Some("lib")));
+        assert_eq!(dep.repo, None);
+    }
+
+    #[test]
+    fn repo_sources_keep_their_server() {
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "repo", repo = "org/dep", server = "corp", protocol = "ssh", tag = "v1.0" }
+        "#);
+        assert_eq!((dep.repo.as_deref(), dep.server.as_deref()), (Some("org/dep"), Some("corp")));
+        assert_eq!(dep.protocol, Some(Protocol::Ssh));
+        assert_eq!(dep.tag.as_deref(), Some("v1.0"));
+        assert_eq!(dep.git, None);
+    }
+
+    #[test]
+    fn path_archive_and_release_sources() {
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "path", path = "../dep" }
+        "#);
+        assert_eq!(dep.path, Some(PathBuf::from("../dep")));
+
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "archive", url = "https://host/dep.tar.gz", sha256 = "abc" }
+        "#);
+        assert_eq!((dep.url.as_deref(), dep.sha256.as_deref()), (Some("https://host/dep.tar.gz"), Some("abc")));
+        assert_eq!(dep.git, None);
+
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "release", url = "https://github.com/org/dep", release = "v2", asset = "dep.zip", sha256 = "abc" }
+        "#);
+        assert_eq!(dep.git.as_deref(), Some("https://github.com/org/dep"));
+        assert_eq!((dep.release.as_deref(), dep.asset.as_deref(), dep.sha256.as_deref()), (Some("v2"), Some("dep.zip"), Some("abc")));
+    }
+
+    #[test]
+    fn registry_sources_only_have_a_version() {
+        let dep = v1_dependency(r#"
+            [[dependency]]
+            name = "dep"
+            needs = ["other"]
+            source = { kind = "registry", version = "1.2" }
+        "#);
+        assert_eq!(dep.version.as_deref(), Some("1.2"));
+        assert_eq!(dep.needs, Some(vec!["other".to_owned()]));
+        assert_eq!((dep.git, dep.repo, dep.path, dep.url), (None, None, None, None));
+    }
+
+    #[test]
+    fn names_declared_twice_are_errors() {
+        let manifest: TomlManifestV2 = toml::from_str(r#"
+            version = 2
+            [project]
+            name = "test"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "git", url = "https://host/dep.git" }
+            [[dependency]]
+            name = "dep"
+            source = { kind = "path", path = "../dep" }
+        "#).unwrap();
+        assert_eq!(manifest.into_v1().unwrap_err(), "the dependency \"dep\" is declared more than once");
+    }
+
+    #[test]
+    fn dependencies_come_after_their_needs() {
+        let deps = dependencies(r#"
+            app = { git = "https://host/app", needs = ["zlib", "boost"] }
+            boost = { git = "https://host/boost", needs = ["zlib"] }
+            zlib = { git = "https://host/zlib" }
+        "#);
+        assert_eq!(dependency_order(&deps).unwrap(), vec!["zlib", "boost", "app"]);
+    }
+
+    #[test]
+    fn dependencies_without_needs_are_ordered_by_name() {
+        let deps = dependencies(r#"
+            c = { git = "https://host/c" }
+            a = { git = "https://host/a" }
+            b = { git = "https://host/b", needs = ["c"] }
+        "#);
+        assert_eq!(dependency_order(&deps).unwrap(), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn cycles_are_errors() {
+        let deps = dependencies(r#"
+            a = { git = "https://host/a", needs = ["b"] }
+            b = { git = "https://host/b", needs = ["c"] }
+            c = { git = "https://host/c", needs = ["a"] }
+        "#);
+        assert_eq!(dependency_order(&deps).unwrap_err().to_string(), "Dependencies need each other: a -> b -> c -> a");
+
+        let deps = dependencies(r#"a = { git = "https://host/a", needs = ["a"] }"#);
+        assert_eq!(dependency_order(&deps).unwrap_err().to_string(), "Dependencies need each other: a -> a");
+    }
+
+    #[test]
+    fn needs_have_to_be_dependencies() {
+        let deps = dependencies(r#"a = { git = "https://host/a", needs = ["b"] }"#);
+        assert_eq!(dependency_order(&deps).unwrap_err().to_string(), "\"a\" needs \"b\", which isn't a dependency");
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dep-manifest-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            remove_dir_all::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, file: &str, content: &str) -> PathBuf {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn later_includes_and_the_manifest_override_earlier_ones() {
+        let dir = temp_dir("override");
+        write(&dir, "first.toml", r#"
+            [dependencies]
+            a = { git = "https://host/a", tag = "first" }
+            b = { git = "https://host/b", tag = "first" }
+            c = { git = "https://host/c", tag = "first" }
+        "#);
+        write(&dir, "second.toml", r#"
+            [dependencies]
+            b = { git = "https://host/b", tag = "second" }
+            c = { git = "https://host/c", tag = "second" }
+        "#);
+        let path = write(&dir, "deps.toml", r#"
+            include = ["first.toml", "second.toml"]
+            [project]
+            name = "test"
+            [dependencies]
+            c = { git = "https://host/c", tag = "project" }
+        "#);
+
+        let (man, origins) = read_manifest_with_origins(&path).unwrap();
+        let deps = man.dependencies.unwrap();
+        let tag = |name: &str| deps[name].tag.clone().unwrap();
+        assert_eq!((tag("a"), tag("b"), tag("c")), ("first".to_owned(), "second".to_owned(), "project".to_owned()));
+        assert_eq!(origins["a"], dir.join("first.toml"));
+        assert_eq!(origins["b"], dir.join("second.toml"));
+        assert_eq!(origins["c"], path);
+    }
+
+    #[test]
+    fn includes_override_their_nested_includes() {
+        let dir = temp_dir("nested");
+        write(&dir, "shared/base.toml", r#"
+            [dependencies]
+            a = { git = "https://host/a", tag = "base" }
+            b = { git = "https://host/b", tag = "base" }
+        "#);
+        // nested includes are relative to the including manifest, and it can be version 2
+        write(&dir, "shared/team.toml", r#"
+            version = 2
+            include = ["base.toml"]
+            [[dependency]]
+            name = "b"
+            source = { kind = "git", url = "https://host/b", tag = "team" }
+        "#);
+        let path = write(&dir, "deps.toml", r#"
+            include = ["shared/team.toml"]
+            [project]
+            name = "test"
+        "#);
+
+        let deps = read_manifest(&path).unwrap().dependencies.unwrap();
+        assert_eq!(deps["a"].tag.as_deref(), Some("base"));
+        assert_eq!(deps["b"].tag.as_deref(), Some("team"));
+    }
+
+    #[test]
+    fn recursive_includes_are_errors() {
+        let dir = temp_dir("recursive");
+        write(&dir, "other.toml", "include = [\"deps.toml\"]\n");
+        let path = write(&dir, "deps.toml", "include = [\"other.toml\"]\n[project]\nname = \"test\"\n");
+        let error = read_manifest(&path).unwrap_err().to_string();
+        assert!(error.contains("is included recursively"), "{}", error);
+    }
+
+    #[test]
+    fn migrate_reports_names_declared_twice() {
+        let dir = temp_dir("twice");
+        let path = write(&dir, "deps.toml", r#"
+            version = 2
+            [project]
+            name = "test"
+            [[dependency]]
+            name = "dep"
+            source = { kind = "path", path = "../a" }
+            [[dependency]]
+            name = "dep"
+            source = { kind = "path", path = "../b" }
+        "#);
+        let error = migrate(&path, &GlobalOptions::default(), &crate::report::Silent).unwrap_err().to_string();
+        assert!(error.contains("\"dep\" is declared more than once"), "{}", error);
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::config::GlobalOptions;
+use crate::error::Result;
+use crate::manifest::{dependency_dir, get_lib_dir, load_manifest_with_origins, TomlDependency, TomlProject};
+
+/// The resolved project, as handed to plugins and other tools.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Metadata {
+    pub manifest: PathBuf,
+    pub lib_dir: PathBuf,
+    pub project: TomlProject,
+    pub dependencies: BTreeMap<String, DependencyMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyMetadata {
+    // where the dependency gets vendored into
+    pub dir: PathBuf,
+    // the manifest that defines the dependency
+    pub origin: PathBuf,
+    #[serde(flatten)]
+    pub dependency: TomlDependency,
+}
+
+/// Reads the manifest (including its includes) and resolves where every dependency ends up.
+pub fn metadata(manifest_path: &Path, opts: &GlobalOptions) -> Result<Metadata> {
+    let (man, origins) = load_manifest_with_origins(manifest_path)?;
+
+    let lib_dir = get_lib_dir(&man, opts);
+    let dependencies = man.dependencies.unwrap_or_default().into_iter().map(|(name, dep)| {
+        let metadata = DependencyMetadata {
+            dir: dependency_dir(&lib_dir, &name, &dep),
+            origin: origins[&name].clone(),
+            dependency: dep,
+        };
+        (name, metadata)
+    }).collect();
+
+    Ok(Metadata { manifest: manifest_path.to_path_buf(), lib_dir, project: man.project, dependencies })
+}
@@ -0,0 +1,59 @@
+//! Bare mirrors of the git dependencies, made by `dep mirror` and used by `dep update --mirror`.
+//!
+//! A mirror directory holds one bare repository per url, named after the url, so it can be carried
+//! into networks without access to the original servers.
+
+use std::path::{Path, PathBuf};
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::git::{mirror_repository, Credentials};
+use crate::manifest::TomlManifest;
+use crate::paths::cache_name;
+use crate::policy::{enforce_url, mirror_url};
+use crate::registry::{is_registry_dependency, read_registry_index, resolve_registry_dependency};
+use crate::update::git_url;
+
+/// Where the mirror of `url` lives in the mirror directory `dir`.
+pub fn mirror_path(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("{}.git", cache_name(url)))
+}
+
+/// The name and url of every git dependency, with those of the registry resolved. Other dependencies are skipped.
+pub fn git_dependency_urls(man: &TomlManifest, opts: &GlobalOptions, credentials: &Credentials, offline: bool) -> Result<Vec<(String, String)>> {
+    let deps = man.dependencies.clone().unwrap_or_default();
+    let index = match deps.values().any(is_registry_dependency) {
+        true => Some(read_registry_index(opts, credentials, offline)?),
+        false => None,
+    };
+    let mut urls = Vec::new();
+    for (name, dep) in deps {
+        let dep = match &index {
+            Some(index) if is_registry_dependency(&dep) => resolve_registry_dependency(index, &name, &dep)?,
+            _ => dep,
+        };
+        if dep.path.is_some() || dep.url.is_some() || dep.release.is_some() {
+            credentials.reporter().info(&format!("Skipping \"{}\", it isn't a git dependency", name));
+            continue;
+        }
+        let url = git_url(opts, man.project.git_server.as_ref(), &dep)?;
+        enforce_url(opts, &name, &url)?;
+        urls.push((name, url));
+    }
+    Ok(urls)
+}
+
+/// Creates or updates the mirror of every git dependency in `dir`, returning how many were mirrored.
+pub fn mirror(man: &TomlManifest, opts: &GlobalOptions, credentials: &Credentials, dir: &Path, offline: bool) -> Result<usize> {
+    if offline {
+        return Err(Error::msg("Mirrors can't be updated in offline mode"));
+    }
+    let urls = git_dependency_urls(man, opts, credentials, offline)?;
+    std::fs::create_dir_all(dir)?;
+    for (name, url) in &urls {
+        let path = mirror_path(dir, url);
+        credentials.reporter().info(&format!("Mirroring \"{}\" from \"{}\" into \"{}\"", name, url, path.to_string_lossy()));
+        mirror_repository(credentials, &mirror_url(opts, url), &path)?;
+    }
+    Ok(urls.len())
+}
@@ -0,0 +1,227 @@
+use std::env::VarError;
+use std::path::Path;
+use std::path::PathBuf;
+
+use path_clean::PathClean;
+
+pub fn absolute_path<P>(path: P) -> std::io::Result<PathBuf>
+    where
+        P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if path.is_absolute() {
+        Ok(path.to_path_buf().clean())
+    } else {
+        Ok(std::env::current_dir()?.join(path).clean())
+    }
+}
+
+#[cfg(windows)]
+mod systools {
+    use std::env::VarError;
+    use std::os::windows::fs::symlink_dir;
+    use std::path::{Path, PathBuf};
+    use super::absolute_path;
+
+    pub fn make_symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), std::io::Error> {
+        symlink_dir(absolute_path(src)?, absolute_path(dst)?)
+    }
+
+    /// Directory symlinks are removed like directories on windows.
+    pub fn remove_link<P: AsRef<Path>>(path: P) -> Result<(), std::io::Error> {
+        std::fs::remove_dir(path)
+    }
+
+    pub fn get_home_dir_env_var() -> &'static str {
+        "USERPROFILE"
+    }
+
+    pub fn get_home_dir() -> Result<String, VarError> {
+        std::env::var(get_home_dir_env_var())
+    }
+
+    pub fn get_config_dir() -> Result<PathBuf, VarError> {
+        Ok(Path::new(&std::env::var("APPDATA")?).to_path_buf())
+    }
+
+    pub fn get_cache_dir() -> Result<PathBuf, VarError> {
+        Ok(Path::new(&std::env::var("LOCALAPPDATA")?).to_path_buf())
+    }
+
+    pub fn get_system_config_dir() -> Result<PathBuf, VarError> {
+        Ok(Path::new(&std::env::var("ProgramData")?).to_path_buf())
+    }
+}
+
+#[cfg(unix)]
+mod systools {
+    use std::env::VarError;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use super::absolute_path;
+
+    pub fn make_symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), std::io::Error> {
+        symlink(absolute_path(src)?, absolute_path(dst)?)
+    }
+
+    pub fn remove_link<P: AsRef<Path>>(path: P) -> Result<(), std::io::Error> {
+        std::fs::remove_file(path)
+    }
+
+    pub fn get_home_dir_env_var() -> &'static str {
+        "HOME"
+    }
+
+    pub fn get_home_dir() -> Result<String, VarError> {
+        std::env::var(get_home_dir_env_var())
+    }
+
+    fn get_xdg_dir(var: &str, default: &str) -> Result<PathBuf, VarError> {
+        match std::env::var(var) {
+            Ok(dir) if !dir.is_empty() => Ok(Path::new(&dir).to_path_buf()),
+            _ => Ok(Path::new(&get_home_dir()?).join(default)),
+        }
+    }
+
+    pub fn get_config_dir() -> Result<PathBuf, VarError> {
+        get_xdg_dir("XDG_CONFIG_HOME", ".config")
+    }
+
+    pub fn get_cache_dir() -> Result<PathBuf, VarError> {
+        get_xdg_dir("XDG_CACHE_HOME", ".cache")
+    }
+
+    pub fn get_system_config_dir() -> Result<PathBuf, VarError> {
+        Ok(PathBuf::from("/etc"))
+    }
+}
+
+pub use systools::{get_home_dir, get_home_dir_env_var, make_symlink, remove_link};
+
+/// Moves the directory `src` to `dst`, replacing an existing `dst`. The old directory is moved aside first,
+/// so `dst` is always either the old or the new directory, and only gets deleted once the new one is in place.
+pub fn replace_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    if dst.symlink_metadata().is_err() {
+        return std::fs::rename(src, dst);
+    }
+    let file_name = dst.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let old = dst.with_file_name(format!(".{}.old", file_name));
+    if old.exists() {
+        remove_dir_all::remove_dir_all(&old)?;
+    }
+    std::fs::rename(dst, &old)?;
+    if let Err(e) = std::fs::rename(src, dst) {
+        std::fs::rename(&old, dst)?;
+        return Err(e);
+    }
+    remove_dir_all::remove_dir_all(&old)
+}
+
+/// Expands `$VAR`, `%VAR%` and `~` path components.
+pub fn normalize<P>(path: &P) -> PathBuf
+    where P: AsRef<Path>
+{
+    let path_string = path.as_ref().to_string_lossy().to_string();
+    let split_char = if path_string.contains("/") {
+        "/"
+    } else {
+        "\\"
+    };
+
+    let parts = path_string.split(split_char);
+
+    let mut result = PathBuf::new();
+
+    for part in parts {
+        if let Some(var) = part.strip_prefix('$') {
+            result.push(std::env::var(var).unwrap_or_default());
+        } else if part.starts_with('%') && part.ends_with('%') {
+            let var = &part[1..part.len() - 1];
+            result.push(std::env::var(var).unwrap_or_default());
+        } else if part == "~" {
+            result.push(get_home_dir().unwrap_or_default());
+        } else if part.is_empty() && result.as_os_str().is_empty() {
+            // the root of absolute paths
+            result.push(split_char);
+        } else {
+            result.push(part);
+        }
+    }
+
+    result
+}
+
+/// `path` relative to the directory `base`, both absolute.
+pub fn relative_path(base: &Path, path: &Path) -> PathBuf {
+    let base = base.components().collect::<Vec<_>>();
+    let path = path.components().collect::<Vec<_>>();
+    let common = base.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let mut result = PathBuf::new();
+    for _ in common..base.len() {
+        result.push("..");
+    }
+    result.extend(&path[common..]);
+    result
+}
+
+/// The configured cache dir (with `~` and variables expanded), or the default one.
+pub fn get_cache_dir(configured: Option<&Path>) -> Result<PathBuf, VarError> {
+    match configured {
+        Some(dir) => Ok(normalize(&dir)),
+        None => Ok(systools::get_cache_dir()?.join("dep")),
+    }
+}
+
+/// Turns an url into something usable as a directory name.
+pub fn cache_name(url: &str) -> String {
+    url.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect()
+}
+
+pub fn get_legacy_global_config_path() -> Result<PathBuf, VarError> {
+    Ok(Path::new(&get_home_dir()?).join(".deprc"))
+}
+
+/// The location of the global config in the config dir of the platform.
+pub fn get_default_global_config_path() -> Result<PathBuf, VarError> {
+    Ok(systools::get_config_dir()?.join("dep").join("config.toml"))
+}
+
+/// Templates for `dep init --template <name>`, each in its own directory.
+pub fn get_templates_dir() -> Result<PathBuf, VarError> {
+    Ok(systools::get_config_dir()?.join("dep").join("templates"))
+}
+
+/// The system wide organization policy, `/etc/dep/policy.toml` (`%ProgramData%\dep\policy.toml` on Windows).
+pub fn get_system_policy_path() -> Result<PathBuf, VarError> {
+    Ok(systools::get_system_config_dir()?.join("dep").join("policy.toml"))
+}
+
+/// An additional policy given with `DEP_POLICY`, which can only lock more settings than the system policy.
+pub fn get_env_policy_path() -> Option<PathBuf> {
+    std::env::var_os("DEP_POLICY").filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+/// The global config given explicitly (`--config`) or by `DEP_CONFIG`, if any.
+pub fn get_explicit_config_path(explicit: Option<&Path>) -> Option<PathBuf> {
+    match explicit {
+        Some(path) => Some(path.to_path_buf()),
+        None => std::env::var_os("DEP_CONFIG").filter(|p| !p.is_empty()).map(PathBuf::from),
+    }
+}
+
+/// The global config lives in the config dir of the platform. A legacy `$HOME/.deprc` is still used,
+/// as long as it wasn't migrated.
+pub fn get_global_config_path(explicit: Option<&Path>) -> Result<PathBuf, VarError> {
+    if let Some(path) = get_explicit_config_path(explicit) {
+        return Ok(path);
+    }
+    let path = get_default_global_config_path()?;
+    if !path.exists() {
+        if let Ok(legacy) = get_legacy_global_config_path() {
+            if legacy.exists() {
+                return Ok(legacy);
+            }
+        }
+    }
+    Ok(path)
+}
@@ -0,0 +1,220 @@
+//! The `[policy]` of the global config, restricting the hosts and protocols dependencies come from.
+//!
+//! Organizations can enforce it with a policy file (see `paths::get_system_policy_path`), whose settings
+//! win over the global config, the project and the environment.
+
+use crate::config::{GlobalOptions, PolicyOptions};
+use crate::error::{Error, Result};
+use crate::git::is_local_url;
+use crate::manifest::TomlManifest;
+use crate::update::git_url;
+
+/// The protocol of an url and its host, local repositories have none.
+pub fn url_origin(url: &str) -> (String, Option<String>) {
+    if is_local_url(url) {
+        return ("file".to_owned(), None);
+    }
+    let (protocol, rest) = match url.split_once("://") {
+        Some((protocol, rest)) => (protocol.to_lowercase(), rest),
+        // scp like urls, [user@]host:path
+        None => ("ssh".to_owned(), url),
+    };
+    // the authority ends at the path, the query or the fragment
+    let rest = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = rest.rsplit('@').next().unwrap_or(rest);
+    // ports and the path of scp like urls
+    let host = host.split(':').next().unwrap_or(host).to_lowercase();
+    // git+ssh and ssh+git are ssh as well
+    let protocol = if protocol.contains("ssh") { "ssh".to_owned() } else { protocol };
+    (protocol, Some(host))
+}
+
+/// `*.corp.com` matches the subdomains of corp.com, everything else only the host itself.
+pub fn matches_host(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let host = host.to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => pattern == host,
+    }
+}
+
+/// `url` with the longest matching prefix of the mirrors replaced.
+fn mirrored(policy: &PolicyOptions, url: &str) -> String {
+    let mirror = policy.mirrors.iter().flatten()
+        .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len());
+    match mirror {
+        Some((prefix, mirror)) => format!("{}{}", mirror, &url[prefix.len()..]),
+        None => url.to_owned(),
+    }
+}
+
+/// The url to fetch `url` from, which is its mirror if the policy has one.
+pub fn mirror_url(opts: &GlobalOptions, url: &str) -> String {
+    match &opts.policy {
+        Some(policy) => mirrored(policy, url),
+        None => url.to_owned(),
+    }
+}
+
+/// Why `url` isn't allowed by the policy, if it isn't. Urls are checked after they got mirrored.
+pub fn check_url(policy: &PolicyOptions, url: &str) -> Option<String> {
+    let url = &mirrored(policy, url);
+    let (protocol, host) = url_origin(url);
+    if protocol == "http" && policy.forbid_http.unwrap_or(false) {
+        return Some(format!("\"{}\" uses plain http, which the policy forbids", url));
+    }
+    if let Some(protocols) = &policy.allowed_protocols {
+        if !protocols.iter().any(|allowed| allowed.to_lowercase() == protocol) {
+            return Some(format!("\"{}\" uses {}, the policy only allows {}", url, protocol, protocols.join(", ")));
+        }
+    }
+    // hosts only restrict remote repositories
+    let host = host?;
+    if policy.denied_hosts.iter().flatten().any(|denied| matches_host(denied, &host)) {
+        return Some(format!("\"{}\" is on a host the policy denies ({})", url, host));
+    }
+    if let Some(hosts) = &policy.allowed_hosts {
+        if !hosts.iter().any(|allowed| matches_host(allowed, &host)) {
+            return Some(format!("\"{}\" is on a host the policy doesn't allow ({})", url, host));
+        }
+    }
+    None
+}
+
+/// Whether the policy asks for signed commits.
+pub fn requires_signatures(opts: &GlobalOptions) -> bool {
+    opts.policy.as_ref().and_then(|policy| policy.require_signatures).unwrap_or(false)
+}
+
+/// Fails if the url `name` gets fetched from isn't allowed, for urls rewritten by hooks.
+pub fn enforce_url(opts: &GlobalOptions, name: &str, url: &str) -> Result<()> {
+    match opts.policy.as_ref().and_then(|policy| check_url(policy, url)) {
+        Some(violation) => Err(Error::msg(format!("\"{}\": {}", name, violation))),
+        None => Ok(()),
+    }
+}
+
+/// Policy violations of every dependency with an url. Dependencies with an url that can't be
+/// determined yet, like those from the registry, are left out.
+pub fn violations(man: &TomlManifest, opts: &GlobalOptions) -> Vec<(String, String)> {
+    let policy = match &opts.policy {
+        Some(policy) => policy,
+        None => return Vec::new(),
+    };
+    let mut violations = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let url = match (&dep.path, &dep.url) {
+            (Some(_), _) => continue,
+            (None, Some(url)) => url.clone(),
+            (None, None) => match git_url(opts, man.project.git_server.as_ref(), dep) {
+                Ok(url) => url,
+                Err(_) => continue,
+            },
+        };
+        if let Some(violation) = check_url(policy, &url) {
+            violations.push((name.clone(), violation));
+        }
+    }
+    violations
+}
+
+/// Fails with all violations of the policy, if there are any.
+pub fn enforce(man: &TomlManifest, opts: &GlobalOptions) -> Result<()> {
+    let violations = violations(man, opts);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let lines = violations.iter().map(|(name, violation)| format!("\n  \"{}\": {}", name, violation)).collect::<String>();
+    Err(Error::msg(format!("The manifest violates the policy:{}", lines)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(toml: &str) -> PolicyOptions {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn finds_the_origin_of_urls() {
+        let cases = [
+            ("https://github.com/org/repo", "https", Some("github.com")),
+            ("ssh://git@git.corp.com:7999/scm/repo.git", "ssh", Some("git.corp.com")),
+            ("git+ssh://git@Git.Corp.com/repo", "ssh", Some("git.corp.com")),
+            ("git@github.com:org/repo", "ssh", Some("github.com")),
+            ("https://corp.com@evil.com/repo", "https", Some("evil.com")),
+            ("https://evil.com#@corp.com/repo", "https", Some("evil.com")),
+            ("https://evil.com?@corp.com/repo", "https", Some("evil.com")),
+            ("HTTP://corp.com/repo", "http", Some("corp.com")),
+            ("../repo", "file", None),
+        ];
+        for (url, protocol, host) in cases {
+            assert_eq!(url_origin(url), (protocol.to_owned(), host.map(|host| host.to_owned())), "{}", url);
+        }
+    }
+
+    #[test]
+    fn matches_hosts_and_their_subdomains() {
+        let cases = [
+            ("corp.com", "corp.com", true),
+            ("Corp.com", "corp.COM", true),
+            ("corp.com", "git.corp.com", false),
+            ("*.corp.com", "git.corp.com", true),
+            ("*.corp.com", "a.b.corp.com", true),
+            ("*.corp.com", "corp.com", false),
+            ("*.corp.com", "evilcorp.com", false),
+            ("*.corp.com", "corp.com.evil.com", false),
+            ("*corp.com", "evilcorp.com", false),
+            ("*", "corp.com", false),
+        ];
+        for (pattern, host, matches) in cases {
+            assert_eq!(matches_host(pattern, host), matches, "{} against {}", pattern, host);
+        }
+    }
+
+    #[test]
+    fn forbids_plain_http_but_not_scp_like_urls() {
+        let policy = policy("forbid-http = true\n");
+        let cases = [
+            ("http://corp.com/repo", false),
+            ("HTTP://corp.com/repo", false),
+            ("https://corp.com/repo", true),
+            ("git@corp.com:repo", true),
+            ("http@corp.com:repo", true),
+            ("git@http:repo", true),
+            ("ssh://git@corp.com/http://repo", true),
+        ];
+        for (url, allowed) in cases {
+            assert_eq!(check_url(&policy, url).is_none(), allowed, "{}", url);
+        }
+    }
+
+    #[test]
+    fn denied_hosts_win_over_allowed_ones() {
+        let policy = policy("allowed-hosts = ['*.corp.com']\ndenied-hosts = ['legacy.corp.com']\nallowed-protocols = ['ssh', 'https']\n");
+        let cases = [
+            ("git@git.corp.com:repo", true),
+            ("https://git.corp.com/repo", true),
+            ("git@legacy.corp.com:repo", false),
+            ("git@github.com:repo", false),
+            ("git://git.corp.com/repo", false),
+            // local repositories have no host, but a protocol
+            ("../repo", false),
+        ];
+        for (url, allowed) in cases {
+            assert_eq!(check_url(&policy, url).is_none(), allowed, "{}", url);
+        }
+    }
+
+    #[test]
+    fn mirrors_are_checked_instead_of_their_urls() {
+        let policy = policy("allowed-hosts = ['git.corp.com']\n[mirrors]\n'https://github.com/' = 'ssh://git@git.corp.com/github/'\n'https://github.com/org/' = 'ssh://git@git.corp.com/org/'\n");
+        assert_eq!(mirrored(&policy, "https://github.com/org/repo"), "ssh://git@git.corp.com/org/repo");
+        assert_eq!(mirrored(&policy, "https://github.com/other/repo"), "ssh://git@git.corp.com/github/other/repo");
+        assert!(check_url(&policy, "https://github.com/other/repo").is_none());
+        assert!(check_url(&policy, "https://gitlab.com/other/repo").is_some());
+    }
+}
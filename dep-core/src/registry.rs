@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use git2::build::RepoBuilder;
+
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::git::{is_local_url, local_url, Credentials};
+use crate::manifest::TomlDependency;
+use crate::paths::cache_name;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryEntry {
+    pub git: String,
+    // default ref, used if the dependency doesn't request a version
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+    pub rev: Option<String>,
+    // version -> tag
+    pub versions: Option<BTreeMap<String, String>>,
+}
+
+/// Dependencies with a version, or without any source, are looked up in the registry.
+pub fn is_registry_dependency(dep: &TomlDependency) -> bool {
+    dep.version.is_some() || (dep.path.is_none() && dep.repo.is_none() && dep.git.is_none() && dep.url.is_none())
+}
+
+fn is_index_file(index: &str) -> bool {
+    (index.starts_with("http://") || index.starts_with("https://")) && index.ends_with(".json")
+}
+
+/// Where the registry index is cached, a clone of it or the downloaded json file.
+pub fn registry_cache_path(opts: &GlobalOptions, index: &str) -> Result<PathBuf> {
+    let cache = opts.cache_dir()?.join("registry").join(cache_name(index));
+    Ok(if is_index_file(index) { cache.with_extension("json") } else { cache })
+}
+
+/// Reads the registry index. In offline mode, the last downloaded index is used
+/// (local git indexes are still cloned).
+pub fn read_registry_index(opts: &GlobalOptions, credentials: &Credentials, offline: bool) -> Result<BTreeMap<String, RegistryEntry>> {
+    let index = match &opts.registry {
+        Some(registry) => &registry.index,
+        None => return Err(Error::msg("Registry dependencies need a [registry] in the global config")),
+    };
+
+    let cache = registry_cache_path(opts, index)?;
+    let content = if is_index_file(index) {
+        if !offline {
+            credentials.reporter().info(&format!("Downloading registry index from \"{}\"", index));
+            let content = ureq::get(index).call()?.into_string()?;
+            std::fs::create_dir_all(cache.parent().unwrap())?;
+            let mut file = File::create(&cache)?;
+            file.write_all(content.as_bytes())?;
+            file.flush()?;
+        } else if !cache.exists() {
+            return Err(Error::msg("Registry index was never downloaded, can't use it in offline mode"));
+        }
+        std::fs::read_to_string(&cache)?
+    } else {
+        if !offline || is_local_url(index) {
+            // the index is small, so a fresh clone is cheaper than figuring out what changed
+            if cache.exists() {
+                remove_dir_all::remove_dir_all(&cache)?;
+            }
+            std::fs::create_dir_all(&cache)?;
+
+            let (url, credentials) = credentials.resolve_url(index);
+            if !is_local_url(index) {
+                credentials.ask_passphrase_for(&url)?;
+            }
+
+            credentials.reporter().info(&format!("Cloning registry index from \"{}\"", index));
+            RepoBuilder::new().fetch_options(credentials.fetch_options(&url)).clone(&local_url(&url)?, &cache)?;
+        } else if !cache.exists() {
+            return Err(Error::msg("Registry index was never cloned, can't use it in offline mode"));
+        }
+
+        std::fs::read_to_string(cache.join("index.json"))?
+    };
+
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Fills in the git url and ref of a registry dependency.
+pub fn resolve_registry_dependency(index: &BTreeMap<String, RegistryEntry>, name: &str, dep: &TomlDependency) -> Result<TomlDependency> {
+    let entry = match index.get(name) {
+        Some(entry) => entry,
+        None => return Err(Error::msg(format!("Dependency \"{}\" not found in registry", name))),
+    };
+
+    let mut dep = dep.clone();
+    dep.git = Some(entry.git.clone());
+    match &dep.version {
+        Some(version) => {
+            match entry.versions.as_ref().and_then(|versions| versions.get(version)) {
+                Some(tag) => dep.tag = Some(tag.clone()),
+                None => return Err(Error::msg(format!("Version \"{}\" of \"{}\" not found in registry", version, name))),
+            }
+        }
+        None => {
+            if dep.branch.is_none() && dep.tag.is_none() && dep.rev.is_none() {
+                dep.branch = entry.branch.clone();
+                dep.tag = entry.tag.clone();
+                dep.rev = entry.rev.clone();
+            }
+        }
+    }
+
+    Ok(dep)
+}
+
@@ -0,0 +1,39 @@
+//! Progress of the library. Nothing in dep-core prints, it tells a [`Reporter`] what it's doing
+//! and the embedding tool decides how to show it, like the `dep` binary which prints it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    // what's being done, like which dependency gets cloned
+    Info,
+    // something went wrong, but the run goes on, like a checkout restored after a failed update
+    Warning,
+}
+
+/// Gets the progress messages. Closures taking the level and the message are reporters as well.
+pub trait Reporter: Send + Sync {
+    fn report(&self, level: Level, message: &str);
+
+    fn info(&self, message: &str) {
+        self.report(Level::Info, message);
+    }
+
+    fn warn(&self, message: &str) {
+        self.report(Level::Warning, message);
+    }
+}
+
+impl<F> Reporter for F
+    where F: Fn(Level, &str) + Send + Sync
+{
+    fn report(&self, level: Level, message: &str) {
+        self(level, message);
+    }
+}
+
+/// Drops all messages, for tools which don't show any progress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silent;
+
+impl Reporter for Silent {
+    fn report(&self, _level: Level, _message: &str) {}
+}
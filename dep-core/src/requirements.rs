@@ -0,0 +1,243 @@
+//! Which manifests ask for a dependency: the manifest of the project, the manifests it includes,
+//! and the manifests of vendored dependencies.
+//!
+//! dep vendors flat, the dependencies of a vendored dependency are only vendored if the
+//! project has them too. Their manifests still tell why a dependency is needed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Conflicts, GlobalOptions};
+use crate::error::{Error, Result};
+use crate::history::describe_reference;
+use crate::manifest::{dependency_dir, get_lib_dir, load_manifest, manifest_in, parse, ManifestFormat, TomlDependency, TomlDependencyV2, TomlManifest, TomlManifestV2};
+use crate::registry::is_registry_dependency;
+use crate::report::Reporter;
+use crate::update::git_url;
+use crate::url::repository_id;
+
+/// A manifest on the way from the project to a dependency.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub manifest: PathBuf,
+    // the vendored dependency the manifest is the one of, None for the project and includes
+    pub dependency: Option<String>,
+}
+
+/// A dependency as one manifest asks for it.
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub name: String,
+    pub dependency: TomlDependency,
+    // from the manifest of the project to the one asking for the dependency
+    pub chain: Vec<Link>,
+    // of the project the manifest belongs to, for repo
+    pub git_server: Option<String>,
+}
+
+impl Requirement {
+    /// Whether the project asks for it, in its manifest or an include.
+    pub fn is_direct(&self) -> bool {
+        self.chain.iter().all(|link| link.dependency.is_none())
+    }
+
+    /// The git url of the dependency, None for other kinds of dependencies.
+    pub fn git_url(&self, opts: &GlobalOptions) -> Option<String> {
+        let dep = &self.dependency;
+        if dep.path.is_some() || dep.url.is_some() || is_registry_dependency(dep) {
+            return None;
+        }
+        git_url(opts, self.git_server.as_ref(), dep).ok()
+    }
+}
+
+/// The chain, like `deps.toml -> lib (lib/lib/deps.toml)`, and what the last manifest asks for, like `util, tag v1`.
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let chain = self.chain.iter().map(|link| match &link.dependency {
+            Some(dependency) => format!("{} ({})", dependency, link.manifest.to_string_lossy()),
+            None => link.manifest.to_string_lossy().to_string(),
+        }).collect::<Vec<_>>().join(" -> ");
+        let dep = &self.dependency;
+        match (&dep.path, &dep.url, &dep.version) {
+            (Some(path), _, _) => write!(f, "{}: {}, path {}", chain, self.name, path.to_string_lossy()),
+            (_, Some(url), _) => write!(f, "{}: {}, archive {}", chain, self.name, url),
+            (_, _, Some(version)) => write!(f, "{}: {}, version {}", chain, self.name, version),
+            _ => write!(f, "{}: {}, {}", chain, self.name, describe_reference(dep)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DeclaredProject {
+    git_server: Option<String>,
+}
+
+/// The dependencies of any manifest, with or without a project.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DeclaredDependencies {
+    project: Option<DeclaredProject>,
+    include: Option<Vec<PathBuf>>,
+    dependencies: Option<BTreeMap<String, TomlDependency>>,
+    dependency: Option<Vec<TomlDependencyV2>>,
+}
+
+struct Walk<'a> {
+    // where the dependencies of the project are vendored
+    dirs: &'a BTreeMap<String, PathBuf>,
+    // manifests on the current chain, against cycles
+    stack: Vec<PathBuf>,
+    requirements: Vec<Requirement>,
+}
+
+impl Walk<'_> {
+    fn manifest(&mut self, path: &Path, chain: &[Link], dependency: Option<String>, git_server: Option<&String>) -> Result<()> {
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) => return Err(Error::msg(format!("Could not read \"{}\": {}", path.to_string_lossy(), e))),
+        };
+        if self.stack.contains(&canonical) {
+            return Ok(());
+        }
+        let content = std::fs::read_to_string(path)?;
+        let declared = parse::<DeclaredDependencies>(&content, ManifestFormat::from_path(path))
+            .map_err(|e| Error::msg(format!("{}: {}", path.to_string_lossy(), e)))?;
+        // includes belong to the project including them
+        let git_server = declared.project.and_then(|project| project.git_server).or_else(|| git_server.cloned());
+        let mut chain = chain.to_vec();
+        chain.push(Link { manifest: path.to_path_buf(), dependency });
+        self.stack.push(canonical);
+
+        // in the order they override each other
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for include in declared.include.iter().flatten() {
+            self.manifest(&base.join(include), &chain, None, git_server.as_ref())?;
+        }
+        let v2_deps = TomlManifestV2::dependencies_into_v1(declared.dependency.unwrap_or_default())
+            .map_err(|e| Error::msg(format!("{}: {}", path.to_string_lossy(), e)))?;
+        let deps = declared.dependencies.unwrap_or_default().into_iter().chain(v2_deps).collect::<Vec<_>>();
+        for (name, dep) in &deps {
+            self.requirements.push(Requirement { name: name.clone(), dependency: dep.clone(), chain: chain.clone(), git_server: git_server.clone() });
+        }
+        for (name, _) in &deps {
+            if let Some(manifest) = self.dirs.get(name).and_then(|dir| manifest_in(dir)) {
+                self.manifest(&manifest, &chain, Some(name.clone()), None)?;
+            }
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// Every dependency every manifest asks for, starting at the manifest of the project. Only dependencies
+/// the project vendors are followed into their manifests. The ones the project asks for itself come first,
+/// where later ones override earlier ones.
+pub fn requirements(file_path: &Path, opts: &GlobalOptions) -> Result<Vec<Requirement>> {
+    let man = load_manifest(file_path)?;
+    let libdir = get_lib_dir(&man, opts);
+    let dirs = man.dependencies.iter().flatten()
+        .map(|(name, dep)| (name.clone(), dependency_dir(&libdir, name, dep)))
+        .collect();
+
+    let mut walk = Walk { dirs: &dirs, stack: Vec::new(), requirements: Vec::new() };
+    walk.manifest(file_path, &[], None, None)?;
+    let (mut direct, nested): (Vec<_>, Vec<_>) = walk.requirements.into_iter().partition(Requirement::is_direct);
+    direct.extend(nested);
+    Ok(direct)
+}
+
+/// A repository which manifests ask for at different refs.
+#[derive(Debug)]
+pub struct Conflict {
+    // the name in the manifest of the project, or in the first manifest asking for it
+    pub name: String,
+    // the one of the project first, if it asks for the repository
+    pub requirements: Vec<Requirement>,
+}
+
+/// Git repositories which the manifests of vendored dependencies ask for at another ref than the project does
+/// (or each other). Requirements the project overrides itself, like the ones of includes, don't count.
+pub fn conflicts(requirements: &[Requirement], opts: &GlobalOptions) -> Vec<Conflict> {
+    let mut direct: Vec<&Requirement> = Vec::new();
+    for requirement in requirements.iter().filter(|requirement| requirement.is_direct()) {
+        direct.retain(|other| other.name != requirement.name);
+        direct.push(requirement);
+    }
+    let nested = requirements.iter().filter(|requirement| !requirement.is_direct());
+
+    let mut repositories: Vec<(String, Vec<&Requirement>)> = Vec::new();
+    for requirement in direct.into_iter().chain(nested) {
+        let id = match requirement.git_url(opts) {
+            Some(url) => repository_id(&url),
+            None => continue,
+        };
+        match repositories.iter_mut().find(|(other, _)| *other == id) {
+            Some((_, group)) => group.push(requirement),
+            None => repositories.push((id, vec![requirement])),
+        }
+    }
+
+    let reference = |requirement: &Requirement| {
+        let dep = &requirement.dependency;
+        (dep.branch.clone(), dep.tag.clone(), dep.rev.clone())
+    };
+    repositories.into_iter()
+        .filter(|(_, group)| group.iter().any(|requirement| !requirement.is_direct()))
+        .filter(|(_, group)| group.iter().any(|requirement| reference(requirement) != reference(group[0])))
+        .map(|(_, group)| Conflict { name: group[0].name.clone(), requirements: group.into_iter().cloned().collect() })
+        .collect()
+}
+
+/// The numbers of a tag, so v1.10.0 sorts after v1.9.2.
+fn version_key(tag: &str) -> Vec<u64> {
+    tag.split(|c: char| !c.is_ascii_digit()).filter(|part| !part.is_empty()).filter_map(|part| part.parse().ok()).collect()
+}
+
+/// Applies the `conflicts` strategy of the config to every conflict of the project, changing the ref of
+/// a dependency in `man` for highest-tag.
+pub fn resolve_conflicts(file_path: &Path, opts: &GlobalOptions, man: &mut TomlManifest, reporter: &dyn Reporter) -> Result<()> {
+    let found = conflicts(&requirements(file_path, opts)?, opts);
+    let mut errors = Vec::new();
+    for conflict in &found {
+        let chains = conflict.requirements.iter().map(|requirement| format!("  {}", requirement)).collect::<Vec<_>>().join("\n");
+        let vendored = conflict.requirements.iter().find(|requirement| requirement.is_direct())
+            .and_then(|requirement| man.dependencies.as_mut()?.get_mut(&requirement.name));
+        match opts.conflicts() {
+            Conflicts::Error => errors.push(format!("\"{}\" is asked for at different refs:\n{}", conflict.name, chains)),
+            Conflicts::Override => match vendored {
+                Some(_) => reporter.info(&format!("\"{}\" is asked for at different refs, vendoring the one of the project:\n{}", conflict.name, chains)),
+                None => reporter.info(&format!("\"{}\" is asked for at different refs, but not by the project:\n{}", conflict.name, chains)),
+            },
+            Conflicts::HighestTag => {
+                let tags = conflict.requirements.iter()
+                    .map(|requirement| match &requirement.dependency {
+                        TomlDependency { tag: Some(tag), branch: None, rev: None, .. } => Some(tag.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>();
+                let highest = match tags.and_then(|tags| tags.into_iter().max_by_key(|tag| version_key(tag))) {
+                    Some(highest) => highest,
+                    None => {
+                        errors.push(format!("\"{}\" is asked for at different refs, which aren't all tags:\n{}", conflict.name, chains));
+                        continue;
+                    }
+                };
+                match vendored {
+                    Some(dep) => {
+                        reporter.info(&format!("\"{}\" is asked for at different tags, vendoring the highest one, {}:\n{}", conflict.name, highest, chains));
+                        dep.tag = Some(highest);
+                    }
+                    None => reporter.info(&format!("\"{}\" is asked for at different tags, but not by the project:\n{}", conflict.name, chains)),
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::msg(format!("{}\nChange the refs, or set conflicts = \"override\" or \"highest-tag\" in [general]", errors.join("\n"))));
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Software bills of materials of the dependencies, written by `dep sbom`.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::config::GlobalOptions;
+use crate::error::Result;
+use crate::history::vendored_commit;
+use crate::manifest::{dependency_dir, get_lib_dir, TomlManifest};
+use crate::update::git_url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbomFormat {
+    // CycloneDX 1.5 json
+    CycloneDx,
+    // SPDX 2.3 json
+    Spdx,
+}
+
+/// A dependency as it appears in the bill of materials.
+struct Component {
+    name: String,
+    // git url, or the archive url
+    url: Option<String>,
+    is_git: bool,
+    // the vendored commit, or the pinned revision if it isn't vendored
+    commit: Option<String>,
+    // the tag or version, if there is no commit
+    version: Option<String>,
+    sha256: Option<String>,
+    // spdx ids
+    licenses: Vec<String>,
+}
+
+/// The spdx id of a license text, recognized by phrases every copy of it contains.
+fn identify_license(text: &str) -> Option<&'static str> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let has = |phrase: &str| text.contains(phrase);
+    // the texts of the gnu licenses mention later versions either way, so they are taken as the
+    // stricter -only, which is all the file itself grants
+    let license = if has("Apache License") && has("Version 2.0") {
+        "Apache-2.0"
+    } else if has("GNU LESSER GENERAL PUBLIC LICENSE") || has("GNU Lesser General Public License") {
+        if has("Version 2.1") { "LGPL-2.1-only" } else { "LGPL-3.0-only" }
+    } else if has("GNU AFFERO GENERAL PUBLIC LICENSE") {
+        "AGPL-3.0-only"
+    } else if has("GNU GENERAL PUBLIC LICENSE") {
+        if has("Version 3") { "GPL-3.0-only" } else { "GPL-2.0-only" }
+    } else if has("Mozilla Public License Version 2.0") || has("Mozilla Public License, version 2.0") {
+        "MPL-2.0"
+    } else if has("Boost Software License - Version 1.0") {
+        "BSL-1.0"
+    } else if has("This is free and unencumbered software released into the public domain") {
+        "Unlicense"
+    } else if has("Permission is hereby granted, free of charge") {
+        "MIT"
+    } else if has("Permission to use, copy, modify, and/or distribute this software") || has("ISC License") {
+        "ISC"
+    } else if has("This software is provided 'as-is'") && has("altered source versions") {
+        "Zlib"
+    } else if has("Redistribution and use in source and binary forms") {
+        if has("Neither the name") || has("names of its contributors") { "BSD-3-Clause" } else { "BSD-2-Clause" }
+    } else if has("CC0 1.0 Universal") {
+        "CC0-1.0"
+    } else {
+        return None;
+    };
+    Some(license)
+}
+
+/// Licenses of the license files (LICENSE, COPYING, ...) at the top of `dir`.
+pub fn detect_licenses(dir: &Path) -> Vec<String> {
+    let mut files = std::fs::read_dir(dir).map(|entries| entries.flatten().map(|entry| entry.path()).collect::<Vec<_>>()).unwrap_or_default();
+    files.sort();
+    let mut licenses = Vec::new();
+    for file in files.iter().filter(|file| file.is_file()) {
+        let name = file.file_name().map(|name| name.to_string_lossy().to_uppercase()).unwrap_or_default();
+        let is_license = ["LICENSE", "LICENCE", "COPYING", "UNLICENSE"].iter().any(|prefix| name.starts_with(prefix));
+        if !is_license {
+            continue;
+        }
+        let license = std::fs::read_to_string(file).ok().as_deref().and_then(identify_license);
+        if let Some(license) = license {
+            if !licenses.iter().any(|known| known == license) {
+                licenses.push(license.to_owned());
+            }
+        }
+    }
+    licenses
+}
+
+fn components(man: &TomlManifest, opts: &GlobalOptions) -> Vec<Component> {
+    let libdir = get_lib_dir(man, opts);
+    let mut components = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let dst = dependency_dir(&libdir, name, dep);
+        let is_git = dep.path.is_none() && dep.url.is_none();
+        let url = if dep.url.is_some() {
+            dep.url.clone()
+        } else if dep.path.is_some() {
+            None
+        } else {
+            // registry dependencies are resolved already, if they are vendored
+            git_url(opts, man.project.git_server.as_ref(), dep).ok().or_else(|| {
+                let repo = git2::Repository::open(&dst).ok()?;
+                let remote = repo.find_remote("origin").ok()?;
+                remote.url().map(|url| url.to_owned())
+            })
+        };
+        let commit = if is_git && dep.release.is_none() {
+            vendored_commit(&dst).map(|oid| oid.to_string()).or_else(|| dep.rev.clone())
+        } else {
+            None
+        };
+        components.push(Component {
+            name: name.clone(),
+            url,
+            is_git: is_git && dep.release.is_none(),
+            commit,
+            version: dep.tag.clone().or_else(|| dep.release.clone()).or_else(|| dep.version.clone()),
+            sha256: dep.sha256.clone(),
+            licenses: detect_licenses(&dst),
+        });
+    }
+    components
+}
+
+/// Seconds since the epoch as an utc timestamp, like 2024-01-31T12:00:00Z.
+fn timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // days to the civil calendar, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+fn cyclonedx(man: &TomlManifest, components: &[Component], created: &str) -> Value {
+    let components = components.iter().map(|component| {
+        let mut value = json!({
+            "type": "library",
+            "bom-ref": component.name,
+            "name": component.name,
+        });
+        if let Some(version) = component.commit.as_ref().or(component.version.as_ref()) {
+            value["version"] = json!(version);
+        }
+        if !component.licenses.is_empty() {
+            value["licenses"] = json!(component.licenses.iter().map(|id| json!({ "license": { "id": id } })).collect::<Vec<_>>());
+        }
+        if let Some(url) = &component.url {
+            let kind = if component.is_git { "vcs" } else { "distribution" };
+            value["externalReferences"] = json!([{ "type": kind, "url": url }]);
+        }
+        if let Some(sha256) = &component.sha256 {
+            value["hashes"] = json!([{ "alg": "SHA-256", "content": sha256 }]);
+        }
+        value
+    }).collect::<Vec<_>>();
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "timestamp": created,
+            "tools": [{ "name": "dep", "version": env!("CARGO_PKG_VERSION") }],
+            "component": { "type": "application", "bom-ref": man.project.name, "name": man.project.name },
+        },
+        "components": components,
+        "dependencies": [{ "ref": man.project.name, "dependsOn": components.iter().map(|component| component["bom-ref"].clone()).collect::<Vec<_>>() }],
+    })
+}
+
+fn spdx_id(name: &str) -> String {
+    let name = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' }).collect::<String>();
+    format!("SPDXRef-Package-{}", name)
+}
+
+/// Spdx download locations of git repositories look like `git+https://host/repo@commit`.
+fn download_location(component: &Component) -> String {
+    let url = match &component.url {
+        Some(url) => url,
+        None => return "NOASSERTION".to_owned(),
+    };
+    if !component.is_git {
+        return url.clone();
+    }
+    let url = if url.contains("://") {
+        url.clone()
+    } else {
+        // scp like urls, user@host:path
+        match url.split_once(':') {
+            Some((host, path)) if host.contains('@') => format!("ssh://{}/{}", host, path),
+            _ => return "NOASSERTION".to_owned(),
+        }
+    };
+    match &component.commit {
+        Some(commit) => format!("git+{}@{}", url, commit),
+        None => format!("git+{}", url),
+    }
+}
+
+fn spdx(man: &TomlManifest, components: &[Component], created: &str, serial: u128) -> Value {
+    let root = spdx_id(&man.project.name);
+    let mut packages = vec![json!({
+        "name": man.project.name,
+        "SPDXID": root,
+        "downloadLocation": man.project.repository.clone().unwrap_or_else(|| "NOASSERTION".to_owned()),
+        "filesAnalyzed": false,
+        "licenseConcluded": "NOASSERTION",
+        "licenseDeclared": "NOASSERTION",
+        "copyrightText": "NOASSERTION",
+    })];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": root,
+    })];
+    for component in components {
+        // every license found applies, there's no telling whether they are alternatives
+        let declared = if component.licenses.is_empty() { "NOASSERTION".to_owned() } else { component.licenses.join(" AND ") };
+        let mut package = json!({
+            "name": component.name,
+            "SPDXID": spdx_id(&component.name),
+            "downloadLocation": download_location(component),
+            "filesAnalyzed": false,
+            "licenseConcluded": "NOASSERTION",
+            "licenseDeclared": declared,
+            "copyrightText": "NOASSERTION",
+        });
+        if let Some(version) = component.commit.as_ref().or(component.version.as_ref()) {
+            package["versionInfo"] = json!(version);
+        }
+        if let Some(sha256) = &component.sha256 {
+            package["checksums"] = json!([{ "algorithm": "SHA256", "checksumValue": sha256 }]);
+        }
+        packages.push(package);
+        relationships.push(json!({
+            "spdxElementId": root,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": spdx_id(&component.name),
+        }));
+    }
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": man.project.name,
+        "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{:x}", man.project.name, serial),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: dep-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// The bill of materials of every dependency, with its url, resolved commit and the licenses of its license files.
+pub fn sbom(man: &TomlManifest, opts: &GlobalOptions, format: SbomFormat) -> Result<String> {
+    let components = components(man, opts);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let created = timestamp(now.as_secs());
+    let document = match format {
+        SbomFormat::CycloneDx => cyclonedx(man, &components, &created),
+        SbomFormat::Spdx => spdx(man, &components, &created, now.as_nanos()),
+    };
+    Ok(serde_json::to_string_pretty(&document)? + "\n")
+}
@@ -0,0 +1,153 @@
+//! Host aliases of `~/.ssh/config`, so urls like `git@work-github:org/repo` connect where ssh (and with it
+//! the git cli) would: `HostName`, `Port`, `User` and `IdentityFile` of the matching `Host` blocks are used.
+//!
+//! Like ssh, the first value of every keyword wins. `Match` blocks and `Include` aren't supported.
+
+use std::path::PathBuf;
+
+use crate::paths::{get_home_dir, normalize};
+use crate::url::GitUrl;
+
+/// What `~/.ssh/config` says about a host.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SshHost {
+    pub host_name: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity_file: Option<PathBuf>,
+}
+
+/// `*` matches any characters, `?` exactly one, like the patterns of `Host`.
+fn matches_pattern(pattern: &[char], host: &[char]) -> bool {
+    match (pattern.first(), host.first()) {
+        (None, None) => true,
+        (Some('*'), _) => matches_pattern(&pattern[1..], host) || (!host.is_empty() && matches_pattern(pattern, &host[1..])),
+        (Some('?'), Some(_)) => matches_pattern(&pattern[1..], &host[1..]),
+        (Some(p), Some(h)) => p.eq_ignore_ascii_case(h) && matches_pattern(&pattern[1..], &host[1..]),
+        _ => false,
+    }
+}
+
+/// Whether the patterns of a `Host` line match `host`. A matching negated pattern (`!pattern`) excludes it.
+fn matches_host(patterns: &[&str], host: &str) -> bool {
+    let host = host.chars().collect::<Vec<_>>();
+    let matches = |pattern: &str| matches_pattern(&pattern.chars().collect::<Vec<_>>(), &host);
+    let excluded = patterns.iter().filter_map(|pattern| pattern.strip_prefix('!')).any(matches);
+    !excluded && patterns.iter().filter(|pattern| !pattern.starts_with('!')).any(|pattern| matches(pattern))
+}
+
+/// Reads what the ssh `config` says about `host`.
+pub fn parse(config: &str, host: &str) -> SshHost {
+    let mut result = SshHost::default();
+    // directives before the first Host apply to every host
+    let mut active = true;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (keyword, value) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+            Some(index) => (&line[..index], line[index..].trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim()),
+            None => (line, ""),
+        };
+        let value = value.trim_matches('"');
+        match keyword.to_lowercase().as_str() {
+            "host" => active = matches_host(&value.split_whitespace().collect::<Vec<_>>(), host),
+            "match" => active = false,
+            _ if !active => {}
+            "hostname" if result.host_name.is_none() => result.host_name = Some(value.replace("%h", host)),
+            "port" if result.port.is_none() => result.port = value.parse().ok(),
+            "user" if result.user.is_none() => result.user = Some(value.to_owned()),
+            "identityfile" if result.identity_file.is_none() => result.identity_file = Some(normalize(&value)),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// What `~/.ssh/config` says about `host`, nothing if there is no config.
+pub fn lookup(host: &str) -> SshHost {
+    let path = match get_home_dir() {
+        Ok(home) => PathBuf::from(home).join(".ssh").join("config"),
+        Err(_) => return SshHost::default(),
+    };
+    match std::fs::read_to_string(path) {
+        Ok(config) => parse(&config, host),
+        Err(_) => SshHost::default(),
+    }
+}
+
+/// `url` as ssh would connect to it, with the `HostName`, `Port` and `User` of the config applied
+/// (a user or port in the url wins), and the `IdentityFile` for it. Other urls are returned as they are.
+///
+/// Urls with a port become `ssh://` urls, a relative path of a scp like url is taken from the root then.
+pub fn resolve_url(url: &str) -> (String, Option<PathBuf>) {
+    let mut parsed = match GitUrl::parse(url) {
+        Some(parsed) if parsed.is_ssh() => parsed,
+        _ => return (url.to_owned(), None),
+    };
+    let entry = lookup(&parsed.host);
+    if entry == SshHost::default() {
+        return (url.to_owned(), None);
+    }
+    parsed.user = parsed.user.or(entry.user);
+    parsed.host = entry.host_name.unwrap_or(parsed.host);
+    parsed.port = parsed.port.or(entry.port);
+    (parsed.to_string(), entry.identity_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_patterns_of_host_lines() {
+        let cases: [(&[&str], &str, bool); 12] = [
+            (&["github.com"], "github.com", true),
+            (&["GitHub.com"], "github.COM", true),
+            (&["github.com"], "gist.github.com", false),
+            (&["*"], "anything.example", true),
+            (&["*.corp.com"], "git.corp.com", true),
+            (&["*.corp.com"], "corp.com", false),
+            (&["git?.corp.com"], "git1.corp.com", true),
+            (&["git?.corp.com"], "git.corp.com", false),
+            (&["work-*"], "work-github", true),
+            (&["*.corp.com", "!legacy.corp.com"], "legacy.corp.com", false),
+            (&["!legacy.corp.com"], "git.corp.com", false),
+            (&["a.com", "b.com"], "b.com", true),
+        ];
+        for (patterns, host, matches) in cases {
+            assert_eq!(matches_host(patterns, host), matches, "{:?} against {}", patterns, host);
+        }
+    }
+
+    #[test]
+    fn the_first_value_of_matching_blocks_wins() {
+        let config = "
+            # defaults for everything
+            User default
+
+            Host work-github
+                HostName github.com
+                Port 2222
+                IdentityFile \"/keys/work\"
+
+            Host *github*
+                HostName=other.com
+                User git
+                IdentityFile /keys/other
+
+            Match host work-github
+                User matched
+        ";
+        let host = parse(config, "work-github");
+        assert_eq!(host.host_name.as_deref(), Some("github.com"));
+        assert_eq!(host.port, Some(2222));
+        assert_eq!(host.user.as_deref(), Some("default"));
+        assert_eq!(host.identity_file, Some(PathBuf::from("/keys/work")));
+
+        let host = parse(config, "github.com");
+        assert_eq!((host.host_name.as_deref(), host.port), (Some("other.com"), None));
+        assert_eq!(parse(config, "gitlab.com"), SshHost { user: Some("default".to_owned()), ..Default::default() });
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use path_clean::PathClean;
+
+use crate::cache::disk_size;
+use crate::config::GlobalOptions;
+use crate::error::{Error, Result};
+use crate::history::{describe_reference, vendored_commit};
+use crate::hooks::Hooks;
+use crate::gitconfig::GitConfig;
+use crate::manifest::{dependency_dir, get_lib_dir, manifest_in, load_manifest_with_origins, read_manifest, TomlDependency, TomlManifest};
+use crate::paths::absolute_path;
+use crate::registry::is_registry_dependency;
+use crate::ssh_config;
+use crate::update::{find_orphans, git_url};
+use crate::url::web_url;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DependencyState {
+    Missing,
+    // path dependency, linked to the path of the manifest
+    Linked,
+    // the target of the link doesn't exist anymore
+    BrokenLink(PathBuf),
+    // the link points somewhere else than the path of the manifest
+    WrongLink { target: PathBuf, expected: PathBuf },
+    // a path dependency, but not a symlink
+    NotALink,
+    // git checkout with the current commit, and branch if any
+    Checkout { rev: String, branch: Option<String> },
+    // the directory of a git dependency isn't a repository
+    NotARepository,
+    // archives and release assets
+    Unpacked,
+    // entry of the lib dir without a dependency
+    Orphaned,
+}
+
+impl fmt::Display for DependencyState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependencyState::Missing => write!(f, "missing"),
+            DependencyState::Linked => write!(f, "linked"),
+            DependencyState::BrokenLink(target) => write!(f, "broken link to \"{}\"", target.to_string_lossy()),
+            DependencyState::WrongLink { target, expected } => write!(f, "links to \"{}\" instead of \"{}\"", target.to_string_lossy(), expected.to_string_lossy()),
+            DependencyState::NotALink => write!(f, "not a link"),
+            DependencyState::Checkout { rev, branch: Some(branch) } => write!(f, "at {} ({})", &rev[..rev.len().min(10)], branch),
+            DependencyState::Checkout { rev, branch: None } => write!(f, "at {}", &rev[..rev.len().min(10)]),
+            DependencyState::NotARepository => write!(f, "not a git repository"),
+            DependencyState::Unpacked => write!(f, "unpacked"),
+            DependencyState::Orphaned => write!(f, "not in the manifest"),
+        }
+    }
+}
+
+impl DependencyState {
+    /// States which `dep update` repairs, or which need attention.
+    pub fn is_problem(&self) -> bool {
+        matches!(self, DependencyState::Missing | DependencyState::BrokenLink(_) | DependencyState::WrongLink { .. }
+            | DependencyState::NotALink | DependencyState::NotARepository | DependencyState::Orphaned)
+    }
+}
+
+/// Checks the symlink of a path dependency at `dst` against the `path` of the manifest.
+pub fn link_state(dst: &Path, path: &Path) -> Result<DependencyState> {
+    let metadata = match dst.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(DependencyState::Missing),
+    };
+    if !metadata.file_type().is_symlink() {
+        return Ok(DependencyState::NotALink);
+    }
+    let target = std::fs::read_link(dst)?;
+    // relative links are relative to the directory of the link
+    let resolved = match dst.parent() {
+        Some(parent) if target.is_relative() => absolute_path(parent.join(&target))?,
+        _ => target.clean(),
+    };
+    let expected = absolute_path(path)?;
+    if !resolved.exists() {
+        Ok(DependencyState::BrokenLink(target))
+    } else if resolved != expected {
+        Ok(DependencyState::WrongLink { target, expected })
+    } else {
+        Ok(DependencyState::Linked)
+    }
+}
+
+fn dependency_state(dst: &Path, dep: &TomlDependency) -> Result<DependencyState> {
+    if let Some(path) = &dep.path {
+        return link_state(dst, path);
+    }
+    if !dst.exists() {
+        return Ok(DependencyState::Missing);
+    }
+    if dep.url.is_some() || dep.release.is_some() {
+        return Ok(DependencyState::Unpacked);
+    }
+    let repo = match git2::Repository::open(dst) {
+        Ok(repo) => repo,
+        Err(_) => return Ok(DependencyState::NotARepository),
+    };
+    let head = repo.head()?;
+    let rev = head.peel_to_commit()?.id().to_string();
+    let branch = if head.is_branch() { head.shorthand().map(|s| s.to_owned()) } else { None };
+    Ok(DependencyState::Checkout { rev, branch })
+}
+
+/// The state of every dependency on disk, followed by the orphaned entries of the lib dir.
+pub fn status(man: &TomlManifest, opts: &GlobalOptions) -> Result<Vec<(String, PathBuf, DependencyState)>> {
+    let libdir = get_lib_dir(man, opts);
+    let mut states = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let dst = dependency_dir(&libdir, name, dep);
+        let state = dependency_state(&dst, dep)?;
+        states.push((name.clone(), dst, state));
+    }
+    for orphan in find_orphans(man, &libdir)? {
+        let name = orphan.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        states.push((name, orphan, DependencyState::Orphaned));
+    }
+    Ok(states)
+}
+
+fn status_code(status: git2::Status) -> String {
+    let index = if status.contains(git2::Status::INDEX_NEW) {
+        'A'
+    } else if status.contains(git2::Status::INDEX_MODIFIED) {
+        'M'
+    } else if status.contains(git2::Status::INDEX_DELETED) {
+        'D'
+    } else if status.contains(git2::Status::INDEX_RENAMED) {
+        'R'
+    } else if status.contains(git2::Status::INDEX_TYPECHANGE) {
+        'T'
+    } else {
+        ' '
+    };
+    let worktree = if status.contains(git2::Status::WT_MODIFIED) {
+        'M'
+    } else if status.contains(git2::Status::WT_DELETED) {
+        'D'
+    } else if status.contains(git2::Status::WT_RENAMED) {
+        'R'
+    } else if status.contains(git2::Status::WT_TYPECHANGE) {
+        'T'
+    } else {
+        ' '
+    };
+    if status.contains(git2::Status::CONFLICTED) {
+        "UU".to_owned()
+    } else if status.contains(git2::Status::WT_NEW) {
+        "??".to_owned()
+    } else {
+        format!("{}{}", index, worktree)
+    }
+}
+
+/// Status code and path of every changed file, like `git status --short` shows them.
+pub type ChangedFiles = Vec<(String, String)>;
+
+/// Changed files of a checkout, untracked ones included.
+pub fn changed_files(dir: &Path) -> Result<ChangedFiles> {
+    let repo = git2::Repository::open(dir)?;
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let changes = repo.statuses(Some(&mut options))?.iter()
+        .filter(|entry| entry.status() != git2::Status::CURRENT)
+        .map(|entry| (status_code(entry.status()), entry.path().unwrap_or_default().to_owned()))
+        .collect();
+    Ok(changes)
+}
+
+/// Changed files of every vendored git dependency, leaving out the ones without changes.
+pub fn local_changes(man: &TomlManifest, opts: &GlobalOptions) -> Result<Vec<(String, PathBuf, ChangedFiles)>> {
+    let libdir = get_lib_dir(man, opts);
+    let mut changes = Vec::new();
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let dst = dependency_dir(&libdir, name, dep);
+        let is_git = dep.path.is_none() && dep.url.is_none() && dep.release.is_none();
+        if !is_git || !dst.join(".git").exists() {
+            continue;
+        }
+        let files = changed_files(&dst)?;
+        if !files.is_empty() {
+            changes.push((name.clone(), dst, files));
+        }
+    }
+    Ok(changes)
+}
+
+/// Everything known about a dependency, as `dep info` shows it.
+#[derive(Debug)]
+pub struct DependencyInfo {
+    // the manifest that defines the dependency
+    pub origin: PathBuf,
+    // git url, archive url, path or registry version
+    pub source: String,
+    // the url the url_rewrite hook fetches from instead
+    pub fetched_from: Option<String>,
+    // what the manifest asks for, like "branch main", git dependencies only
+    pub reference: Option<String>,
+    // the vendored commit
+    pub commit: Option<String>,
+    pub dir: PathBuf,
+    pub state: DependencyState,
+    // bytes on disk, of the linked directory for path dependencies
+    pub size: u64,
+    // changed files of a git checkout
+    pub changed_files: Option<ChangedFiles>,
+    // seconds since the epoch of the last checkout, download or link
+    pub updated: Option<u64>,
+    // the hooks of the project which run for the dependency
+    pub hooks: Vec<&'static str>,
+}
+
+fn seconds(time: std::io::Result<std::time::SystemTime>) -> Option<u64> {
+    Some(time.ok()?.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// When the dependency at `dst` was vendored: the newest entry of the reflog of HEAD for checkouts,
+/// otherwise the time the download or link was written.
+fn updated_at(dst: &Path, dep: &TomlDependency) -> Option<u64> {
+    if let Ok(repo) = git2::Repository::open(dst) {
+        let newest = repo.reflog("HEAD").ok().and_then(|reflog| reflog.get(0).map(|entry| entry.committer().when().seconds()));
+        return match newest {
+            Some(time) => Some(time.max(0) as u64),
+            None => seconds(repo.path().join("HEAD").metadata().and_then(|metadata| metadata.modified())),
+        };
+    }
+    let marker = if dep.path.is_some() { dst.to_path_buf() } else { dst.join(".dep-archive") };
+    seconds(marker.symlink_metadata().and_then(|metadata| metadata.modified()))
+}
+
+/// Collects what the manifest, the lib dir and the hooks (if the project has any) tell about the dependency `name`.
+pub fn info(file_path: &Path, opts: &GlobalOptions, hooks: Option<&Hooks>, name: &str) -> Result<DependencyInfo> {
+    let (man, origins) = load_manifest_with_origins(file_path)?;
+    let dep = match man.dependencies.as_ref().and_then(|deps| deps.get(name)) {
+        Some(dep) => dep,
+        None => return Err(Error::msg(format!("Unknown dependency \"{}\"", name))),
+    };
+    let is_git = dep.path.is_none() && dep.url.is_none() && !is_registry_dependency(dep);
+
+    let source = if let Some(path) = &dep.path {
+        format!("path {}", path.to_string_lossy())
+    } else if let Some(url) = &dep.url {
+        format!("archive {}", url)
+    } else if is_registry_dependency(dep) {
+        format!("registry, version {}", dep.version.as_deref().unwrap_or("*"))
+    } else {
+        let url = git_url(opts, man.project.git_server.as_ref(), dep)?;
+        match (&dep.release, &dep.asset) {
+            (Some(release), Some(asset)) => format!("asset {} of release {} of {}", asset, release, url),
+            _ => url,
+        }
+    };
+    let fetched_from = match hooks {
+        Some(hooks) if is_git && dep.release.is_none() => {
+            let url = git_url(opts, man.project.git_server.as_ref(), dep)?;
+            Some(hooks.url_rewrite(name, &url)?).filter(|rewritten| *rewritten != url)
+        }
+        _ => None,
+    };
+
+    let dir = dependency_dir(&get_lib_dir(&man, opts), name, dep);
+    let size = match &dep.path {
+        Some(path) => disk_size(path),
+        None => disk_size(&dir),
+    };
+    let changed_files = if is_git && dir.join(".git").exists() { Some(changed_files(&dir)?) } else { None };
+    Ok(DependencyInfo {
+        origin: origins[name].clone(),
+        source,
+        fetched_from,
+        reference: if is_git && dep.release.is_none() { Some(describe_reference(dep)) } else { None },
+        commit: vendored_commit(&dir).map(|commit| commit.to_string()),
+        state: dependency_state(&dir, dep)?,
+        size,
+        changed_files,
+        updated: updated_at(&dir, dep),
+        hooks: hooks.map(|hooks| hooks.defined()).unwrap_or_default(),
+        dir,
+    })
+}
+
+/// The page of a dependency in the browser: the `homepage` (or `repository`) of its own manifest, if it's vendored
+/// and has one, otherwise the page of its git repository (the one it was cloned from for registry dependencies).
+pub fn homepage(man: &TomlManifest, opts: &GlobalOptions, name: &str) -> Result<String> {
+    let dep = match man.dependencies.as_ref().and_then(|deps| deps.get(name)) {
+        Some(dep) => dep,
+        None => return Err(Error::msg(format!("Unknown dependency \"{}\"", name))),
+    };
+    let dir = dependency_dir(&get_lib_dir(man, opts), name, dep);
+    let project = manifest_in(&dir).and_then(|manifest| read_manifest(&manifest).ok()).map(|man| man.project);
+    if let Some(project) = project {
+        if let Some(homepage) = project.homepage {
+            return Ok(homepage);
+        }
+        if let Some(repository) = project.repository {
+            return Ok(web_url(&repository).unwrap_or(repository));
+        }
+    }
+
+    let url = git_url(opts, man.project.git_server.as_ref(), dep).ok().or_else(|| {
+        let repo = git2::Repository::open(&dir).ok()?;
+        let remote = repo.find_remote("origin").ok()?;
+        remote.url().map(|url| url.to_owned())
+    });
+    // aliases of the git and ssh config name the host of the page
+    let page = url.and_then(|url| web_url(&ssh_config::resolve_url(&GitConfig::load().rewrite(&url)).0));
+    match page {
+        Some(page) => Ok(page),
+        None => Err(Error::msg(format!("\"{}\" has no homepage and isn't in a git repository with a web page", name))),
+    }
+}
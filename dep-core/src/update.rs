@@ -0,0 +1,640 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+
+use crate::cache::{cached_repository, record_use, shared_checkout};
+use crate::config::{resolve_server, server_protocol, GlobalOptions};
+use crate::error::{Error, Result};
+use crate::fetch::{fetch_archive, fetch_release_asset, fetch_source_archive};
+use crate::filelock::{lock_dir, LOCK_FILE};
+use crate::git::{checkout, checkout_worktree, is_local_url, local_url, run_git, verify_signature, CheckoutOptions, Credentials};
+use crate::history::{render_report, report_changes, revision_change, vendored_commit, ReportFormat};
+use crate::hooks::Hooks;
+use crate::manifest::{dependency_dir, dependency_order, get_lib_dir, is_floating, load_manifest_with_origins, rename_dependency, set_dependency_keys, TomlDependency, TomlManifest};
+use crate::mirror::mirror_path;
+use crate::paths::{absolute_path, get_home_dir, make_symlink, remove_link};
+use crate::policy::{enforce, enforce_url, mirror_url, requires_signatures};
+use crate::registry::{is_registry_dependency, read_registry_index, registry_cache_path, resolve_registry_dependency};
+use crate::report::Reporter;
+use crate::status::{link_state, DependencyState};
+use crate::url::make_url;
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    // only these dependencies get updated, all if empty
+    pub names: Vec<String>,
+    // removes and fetches these dependencies again, while the others are updated as usual.
+    // without names the lib dir (or only the selected dependencies) is removed and starts from a clean state
+    pub force: Option<Vec<String>>,
+    // fails right away if another dep process uses the lib dir or the cache, instead of waiting for it
+    pub no_wait: bool,
+    // continues with the other dependencies when one fails, and prints a summary at the end
+    pub keep_going: bool,
+    // removes entries of the lib dir which don't belong to a dependency anymore
+    pub prune: bool,
+    // only local repositories are updated
+    pub offline: bool,
+    // updates checkouts with uncommitted changes or local commits anyway, which drops them
+    pub discard_local: bool,
+    // number of dependencies updated in parallel, 0 and 1 mean one after another
+    pub jobs: usize,
+    // dep.rhai script with hooks, see [`crate::hooks`]
+    pub hooks: Option<PathBuf>,
+    // writes the revisions that changed into this file, besides printing them
+    pub report: Option<(ReportFormat, PathBuf)>,
+    // directory made by `dep mirror`, git dependencies are fetched from their mirror in it
+    pub mirror: Option<PathBuf>,
+    // the manifest of the project, recorded as the user of the cache entries it needs
+    pub manifest: Option<PathBuf>,
+}
+
+/// Everything needed to update a single dependency.
+struct Context<'a> {
+    opts: &'a GlobalOptions,
+    credentials: &'a Credentials,
+    hooks: Option<Hooks>,
+    git_server: Option<&'a String>,
+    libdir: &'a Path,
+    // every selected dependency, or only the forced ones, are fetched again
+    force: bool,
+    forced: &'a [String],
+    offline: bool,
+    discard_local: bool,
+    mirror: Option<&'a Path>,
+}
+
+/// Resolves registry dependencies and vendors every dependency of the manifest into its lib dir.
+pub fn update(mut man: TomlManifest, opts: &GlobalOptions, credentials: &Credentials, options: &UpdateOptions) -> Result<()> {
+    let offline = options.offline;
+    let project = options.manifest.as_ref().map(absolute_path).transpose()?;
+    let hooks = match &options.hooks {
+        Some(path) => Some(Hooks::load(path, &man)?),
+        None => None,
+    };
+
+    // before dependencies get deselected by name
+    let libdir = get_lib_dir(&man, opts);
+    let orphans = find_orphans(&man, &libdir)?;
+    let mut order = Vec::new();
+    let forced = options.force.as_deref().unwrap_or_default();
+    let force_all = options.force.as_ref().map(|names| names.is_empty()).unwrap_or(false);
+
+    if let Some(deps) = &mut man.dependencies {
+        for name in options.names.iter().chain(forced) {
+            if !deps.contains_key(name) {
+                return Err(Error::msg(format!("Unknown dependency \"{}\"", name)));
+            }
+        }
+        // of all dependencies, needs which aren't selected are left as they are
+        order = dependency_order(deps)?;
+        if !options.names.is_empty() {
+            deps.retain(|name, _| options.names.contains(name));
+            order.retain(|name| options.names.contains(name));
+        }
+
+        if deps.values().any(is_registry_dependency) {
+            let _cache_lock = lock_dir(&opts.cache_dir()?, !options.no_wait, credentials.reporter())?;
+            let index = read_registry_index(opts, credentials, offline)?;
+            if let Some(registry) = &opts.registry {
+                record_use(&opts.cache_dir()?, &registry_cache_path(opts, &registry.index)?, &registry.index, project.as_deref())?;
+            }
+            for (name, dep) in deps.iter_mut() {
+                if is_registry_dependency(dep) {
+                    *dep = resolve_registry_dependency(&index, name, dep)?;
+                }
+            }
+        }
+    } else if let Some(name) = options.names.iter().chain(forced).next() {
+        return Err(Error::msg(format!("Unknown dependency \"{}\"", name)));
+    }
+    // before anything gets touched
+    enforce(&man, opts)?;
+
+    // checkouts borrow the objects of the repositories in the cache (or are worktrees of them, or links to shared ones),
+    // the project keeps them from being collected
+    let worktrees = opts.general.worktrees.unwrap_or(false);
+    let mut borrowed = Vec::new();
+    for dep in man.dependencies.iter().flat_map(|deps| deps.values()) {
+        if dep.path.is_some() || dep.url.is_some() || dep.release.is_some() {
+            continue;
+        }
+        if let Ok(url) = git_url(opts, man.project.git_server.as_ref(), dep) {
+            let path = cached_repository(opts, &url)?;
+            if worktrees || path.exists() {
+                borrowed.push((url.clone(), path));
+            }
+            if let Some(rev) = dep.rev.as_ref().filter(|_| opts.general.shared_checkouts.unwrap_or(false)) {
+                borrowed.push((url.clone(), shared_checkout(opts, &url, rev)?));
+            }
+        }
+    }
+    if !borrowed.is_empty() {
+        let cache = opts.cache_dir()?;
+        let _cache_lock = lock_dir(&cache, !options.no_wait, credentials.reporter())?;
+        for (url, path) in &borrowed {
+            record_use(&cache, path, url, project.as_deref())?;
+        }
+    }
+
+    if !libdir.exists() {
+        credentials.reporter().info(&format!("Creating lib dir: {}", libdir.to_string_lossy()));
+        std::fs::create_dir_all(&libdir)?;
+    }
+    let _lock = lock_dir(&libdir, !options.no_wait, credentials.reporter())?;
+    if force_all && options.names.is_empty() {
+        check_lib_dir_removal(&libdir)?;
+        // everything but the lock, which has to stay while it's held
+        credentials.reporter().info(&format!("Deleting old lib dir: {}", libdir.to_string_lossy()));
+        for entry in std::fs::read_dir(&libdir)? {
+            let entry = entry?;
+            if entry.file_name() != LOCK_FILE {
+                remove_entry(&entry.path())?;
+            }
+        }
+    }
+
+    // --force without names already removed them with the lib dir
+    for orphan in orphans.iter().filter(|orphan| orphan.symlink_metadata().is_ok()) {
+        if options.prune {
+            credentials.reporter().info(&format!("Removing \"{}\", it's not in the manifest anymore", orphan.to_string_lossy()));
+            remove_entry(orphan)?;
+        } else {
+            credentials.reporter().info(&format!("\"{}\" is not in the manifest anymore, \"dep update --prune\" removes it", orphan.to_string_lossy()));
+        }
+    }
+
+    let deps = match &man.dependencies {
+        None => return Ok(()),
+        Some(deps) => deps,
+    };
+
+    let needs_ssh = |d: &TomlDependency| {
+        d.release.is_none() && (d.git.as_deref().map(|git| !is_local_url(git)).unwrap_or(false)
+            || (d.repo.is_some() && (d.server.is_some() || man.project.git_server.is_some())))
+    };
+    // before the dependencies run in parallel, so prompts don't interleave
+    for dep in deps.values().filter(|d| !offline && needs_ssh(d)) {
+        let (url, credentials) = credentials.for_dependency(dep).resolve_url(&git_url(opts, man.project.git_server.as_ref(), dep)?);
+        credentials.ask_passphrase_for(&url)?;
+    }
+
+    let ctx = Context {
+        opts,
+        credentials,
+        hooks,
+        git_server: man.project.git_server.as_ref(),
+        libdir: &libdir,
+        // without names, the whole lib dir was recreated already
+        force: force_all && !options.names.is_empty(),
+        forced,
+        offline,
+        discard_local: options.discard_local,
+        mirror: options.mirror.as_deref(),
+    };
+
+    // with --keep-going, every dependency is tried and failures are reported at the end
+    let results: Mutex<Vec<(String, Result<()>)>> = Mutex::new(Vec::new());
+    let changes = Mutex::new(Vec::new());
+    // the needs which get updated as well
+    let needs = |name: &String| deps[name].needs.iter().flatten().filter(|need| deps.contains_key(*need)).cloned().collect::<Vec<_>>();
+    let run = |name: &String, dep: &TomlDependency| {
+        // counts as finished, so nothing waits for it
+        if credentials.is_cancelled() {
+            results.lock().unwrap().push((name.clone(), Err(Error::Interrupted)));
+            return false;
+        }
+        let failed_need = needs(name).into_iter()
+            .find(|need| results.lock().unwrap().iter().any(|(other, result)| other == need && result.is_err()));
+        let dst = dependency_dir(&libdir, name, dep);
+        let old = vendored_commit(&dst);
+        let result = match failed_need {
+            Some(need) => Err(Error::msg(format!("\"{}\" needs \"{}\", which failed to update", name, need))),
+            None => update_dependency(&ctx, name, dep)
+                .and_then(|_| revision_change(name, dep, &dst, old))
+                .map(|change| if let Some(change) = change {
+                    changes.lock().unwrap().push(change);
+                }),
+        };
+        let failed = result.is_err();
+        results.lock().unwrap().push((name.clone(), result));
+        options.keep_going || !failed
+    };
+
+    if options.jobs <= 1 {
+        for name in &order {
+            if !run(name, &deps[name]) {
+                break;
+            }
+        }
+    } else {
+        // every dependency waits for the ones it needs, and nothing new starts once one failed (without --keep-going)
+        let queue = Mutex::new(order.iter().collect::<Vec<_>>());
+        let finished = Condvar::new();
+        let stopped = |results: &[(String, Result<()>)]| {
+            credentials.is_cancelled() || (!options.keep_going && results.iter().any(|(_, result)| result.is_err()))
+        };
+        std::thread::scope(|scope| {
+            for _ in 0..options.jobs.min(deps.len()) {
+                scope.spawn(|| loop {
+                    let next = {
+                        let mut queue = queue.lock().unwrap();
+                        loop {
+                            let done = {
+                                let results = results.lock().unwrap();
+                                if stopped(&results) {
+                                    break None;
+                                }
+                                results.iter().map(|(name, _)| name.clone()).collect::<BTreeSet<_>>()
+                            };
+                            match queue.iter().position(|name| needs(name).iter().all(|need| done.contains(need))) {
+                                Some(next) => break Some(queue.remove(next)),
+                                None if queue.is_empty() => break None,
+                                None => queue = finished.wait(queue).unwrap(),
+                            }
+                        }
+                    };
+                    let next = match next {
+                        Some(name) => run(name, &deps[name]),
+                        None => break,
+                    };
+                    finished.notify_all();
+                    if !next {
+                        break;
+                    }
+                });
+            }
+        });
+    }
+
+    // what did move is reported, even if other dependencies failed
+    let mut changes = changes.into_inner().unwrap();
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    report_changes(&changes, credentials.reporter());
+    if let Some((format, path)) = &options.report {
+        std::fs::write(path, render_report(&changes, *format)?)?;
+        credentials.reporter().info(&format!("Wrote the update report to \"{}\"", path.to_string_lossy()));
+    }
+
+    let mut results = results.into_inner().unwrap();
+    if credentials.is_cancelled() {
+        return Err(Error::Interrupted);
+    }
+    if !options.keep_going {
+        return match results.into_iter().find_map(|(_, result)| result.err()) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+    }
+
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    credentials.reporter().info("");
+    credentials.reporter().info("Summary:");
+    for (name, result) in &results {
+        match result {
+            Ok(_) => credentials.reporter().info(&format!("  {:<width$}  ok", name, width = width)),
+            Err(e) => credentials.reporter().info(&format!("  {:<width$}  failed: {}", name, e, width = width)),
+        }
+    }
+    if failed > 0 {
+        return Err(Error::msg(format!("{} of {} dependencies failed to update", failed, results.len())));
+    }
+    Ok(())
+}
+
+fn update_dependency(ctx: &Context, name: &str, dep: &TomlDependency) -> Result<()> {
+    let libdir = &dep.clone().into.unwrap_or_else(|| ctx.libdir.to_path_buf());
+    if !libdir.exists() {
+        ctx.credentials.reporter().info(&format!("Creating lib dir: {}", libdir.to_string_lossy()));
+        std::fs::create_dir_all(libdir)?;
+    }
+
+    let force = ctx.force || ctx.forced.iter().any(|n| n == name);
+    let name = &dep.clone().name.unwrap_or_else(|| name.to_owned());
+
+    let dst = libdir.join(Path::new(name));
+
+    if let Some(hooks) = &ctx.hooks {
+        if !hooks.before_fetch(name, dep)? {
+            ctx.credentials.reporter().info(&format!("Skipping \"{}\"", name));
+            return Ok(());
+        }
+    }
+
+    // mirrors are local as well
+    let is_local_git = ctx.mirror.is_some() || dep.git.as_deref().map(is_local_url).unwrap_or(false);
+    let is_remote = dep.path.is_none() && (dep.release.is_some() || dep.url.is_some() || !is_local_git);
+    if ctx.offline && is_remote {
+        if dst.exists() {
+            ctx.credentials.reporter().info(&format!("Offline, keeping \"{}\" as it is", name));
+            return Ok(());
+        }
+        return Err(Error::msg(format!("\"{}\" can't be fetched in offline mode", name)));
+    }
+
+    if force && (dst.exists() || dst.symlink_metadata().is_ok()) {
+        ctx.credentials.reporter().info(&format!("Deleting old \"{}\"", dst.to_string_lossy()));
+        remove_entry(&dst)?;
+    }
+
+    match (&dep.path, &dep.url) {
+        (Some(path), _) => match link_state(&dst, path)? {
+            DependencyState::Missing => {
+                ctx.credentials.reporter().info(&format!("Linking path \"{}\" into \"{}\" as \"{}\"", path.to_string_lossy(), libdir.to_string_lossy(), name));
+                make_symlink(path, &dst)?;
+            }
+            state @ (DependencyState::BrokenLink(_) | DependencyState::WrongLink { .. }) => {
+                ctx.credentials.reporter().info(&format!("\"{}\" {}, relinking it to \"{}\"", name, state, path.to_string_lossy()));
+                // relative links are relative to the directory of the link
+                let previous = libdir.join(std::fs::read_link(&dst)?);
+                remove_link(&dst)?;
+                if let Err(e) = make_symlink(path, &dst) {
+                    // the old link is better than none
+                    if dst.symlink_metadata().is_err() {
+                        make_symlink(&previous, &dst)?;
+                    }
+                    return Err(e.into());
+                }
+            }
+            DependencyState::NotALink => {
+                return Err(Error::msg(format!("\"{}\" is not a link to \"{}\", remove it or use --force", dst.to_string_lossy(), path.to_string_lossy())));
+            }
+            _ => (),
+        },
+        (None, Some(url)) => {
+            let url = match &ctx.hooks {
+                Some(hooks) => hooks.url_rewrite(name, url)?,
+                None => url.clone(),
+            };
+            enforce_url(ctx.opts, name, &url)?;
+            let url = mirror_url(ctx.opts, &url);
+            let sha256 = dep.sha256.as_deref()
+                .ok_or_else(|| Error::msg(format!("Archive dependency \"{}\" needs the sha256 checksum of the archive", name)))?;
+            fetch_archive(&url, sha256, libdir, name, ctx.credentials.reporter())?;
+        }
+        (None, None) => {
+            let manifest_url = git_url(ctx.opts, ctx.git_server, dep)?;
+            let url = match &ctx.hooks {
+                Some(hooks) => hooks.url_rewrite(name, &manifest_url)?,
+                None => manifest_url.clone(),
+            };
+            // insteadOf of the git config and host aliases of ~/.ssh/config, before the policy looks at the host
+            let (url, credentials) = ctx.credentials.for_dependency(dep).resolve_url(&url);
+            enforce_url(ctx.opts, name, &url)?;
+            let url = match ctx.mirror {
+                // mirrors are found by the url of the manifest
+                Some(dir) if dep.release.is_none() => {
+                    let path = mirror_path(dir, &manifest_url);
+                    if !path.exists() {
+                        return Err(Error::msg(format!("\"{}\" has no mirror in \"{}\", run \"dep mirror\" first", name, dir.to_string_lossy())));
+                    }
+                    absolute_path(path)?.to_string_lossy().to_string()
+                }
+                _ => mirror_url(ctx.opts, &url),
+            };
+
+            // existing clones stay clones until they get recreated
+            let shared = match &dep.rev {
+                Some(rev) if dep.release.is_none() && ctx.opts.general.shared_checkouts.unwrap_or(false) => Some(shared_checkout(ctx.opts, &manifest_url, rev)?),
+                _ => None,
+            }.filter(|shared| !matches!(link_state(&dst, shared), Ok(DependencyState::NotALink) | Err(_)));
+            // the shared checkout stays at its rev for the other projects
+            if shared.is_none() && dst.symlink_metadata().map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false) {
+                ctx.credentials.reporter().info(&format!("Unlinking \"{}\" from its shared checkout", name));
+                remove_link(&dst)?;
+            }
+
+            if dep.release.is_some() {
+                fetch_release_asset(ctx.opts, &url, dep, libdir, name, ctx.credentials.reporter())?;
+            } else {
+                // archives have no history, lfs objects or signatures, existing clones stay clones until they get recreated
+                let archive = dep.archive.unwrap_or(false) && ctx.mirror.is_none() && !dep.lfs.unwrap_or(false)
+                    && !requires_signatures(ctx.opts) && !dst.join(".git").exists();
+                if !(archive && fetch_source_archive(ctx.opts, &url, dep, libdir, name, ctx.credentials.reporter())?) {
+                    let (dir, checkout_name) = match &shared {
+                        Some(shared) => (shared.parent().unwrap_or(libdir).to_path_buf(), shared.file_name().unwrap_or_default().to_string_lossy().to_string()),
+                        None => (libdir.to_path_buf(), name.clone()),
+                    };
+                    // other projects update the shared checkouts as well
+                    let _lock = match &shared {
+                        Some(_) => Some(lock_dir(&dir, true, ctx.credentials.reporter())?),
+                        None => None,
+                    };
+                    let options = CheckoutOptions {
+                        offline: ctx.offline,
+                        discard_local: ctx.discard_local,
+                        preserve_local: ctx.opts.general.preserve_local_changes.unwrap_or(false),
+                        reference: Some(cached_repository(ctx.opts, &manifest_url)?).filter(|path| path.exists()),
+                        filter: dep.filter.clone().or_else(|| ctx.opts.general.filter.clone()),
+                        shallow_since: dep.shallow_since.clone(),
+                    };
+                    if ctx.opts.general.worktrees.unwrap_or(false) && !dir.join(&checkout_name).join(".git").is_dir() {
+                        checkout_worktree(&credentials, &url, dep, &dir, &checkout_name, &cached_repository(ctx.opts, &manifest_url)?, &options)?;
+                    } else {
+                        // clones from before worktrees were turned on stay clones, until they get recreated
+                        checkout(&credentials, &url, dep, &dir, &checkout_name, &options)?;
+                    }
+                    if let Some(shared) = &shared {
+                        link_shared_checkout(shared, &dst, libdir, name, ctx.credentials.reporter())?;
+                    }
+                    if requires_signatures(ctx.opts) {
+                        verify_signature(&dst, dep)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(hooks) = &ctx.hooks {
+        hooks.after_checkout(name, dep, &dst)?;
+    }
+    Ok(())
+}
+
+/// Links the entry of a dependency in the lib dir to its shared checkout, instead of a link to another one.
+fn link_shared_checkout(shared: &Path, dst: &Path, libdir: &Path, name: &str, reporter: &dyn Reporter) -> Result<()> {
+    match link_state(dst, shared)? {
+        DependencyState::Linked => (),
+        DependencyState::Missing => {
+            reporter.info(&format!("Linking the shared checkout \"{}\" into \"{}\" as \"{}\"", shared.to_string_lossy(), libdir.to_string_lossy(), name));
+            make_symlink(shared, dst)?;
+        }
+        _ => {
+            reporter.info(&format!("Relinking \"{}\" to the shared checkout \"{}\"", name, shared.to_string_lossy()));
+            remove_link(dst)?;
+            make_symlink(shared, dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// Git url of a dependency, given as `git` or as `repo` on its server or the server of the project.
+pub fn git_url(opts: &GlobalOptions, git_server: Option<&String>, dep: &TomlDependency) -> Result<String> {
+    let server = dep.server.as_ref().or(git_server)
+        .map(|server| resolve_server(opts, server));
+    match (server, &dep.repo, &dep.git) {
+        (Some(server), Some(repo), None) => Ok(make_url(&server, repo, dep.protocol.or_else(|| server_protocol(opts, &server)))),
+        (None, None, Some(repo)) => Ok(local_url(repo)?),
+        (Some(_), None, Some(repo)) => Ok(local_url(repo)?),
+        _ => Err(Error::msg("Could not get git url or dependency path")),
+    }
+}
+
+/// Refuses to delete lib dirs which resolve to the root, the home dir, the project itself
+/// or something outside of the project (the current directory).
+pub fn check_lib_dir_removal(libdir: &Path) -> Result<()> {
+    let dir = std::fs::canonicalize(libdir)?;
+    let project = std::fs::canonicalize(std::env::current_dir()?)?;
+    let home = get_home_dir().ok().and_then(|home| std::fs::canonicalize(home).ok());
+
+    let reason = if dir.parent().is_none() {
+        Some("it's the root directory")
+    } else if Some(&dir) == home.as_ref() {
+        Some("it's the home directory")
+    } else if dir == project {
+        Some("it's the project directory")
+    } else if !dir.starts_with(&project) {
+        Some("it's outside of the project")
+    } else {
+        None
+    };
+    match reason {
+        Some(reason) => Err(Error::msg(format!(
+            "Refusing to delete lib dir \"{}\" ({}), {}", libdir.to_string_lossy(), dir.to_string_lossy(), reason
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Removes a checkout, symlink or file of the lib dir.
+fn remove_entry(path: &Path) -> Result<()> {
+    let file_type = path.symlink_metadata()?.file_type();
+    if file_type.is_symlink() {
+        remove_link(path)?;
+    } else if file_type.is_dir() {
+        remove_dir_all::remove_dir_all(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Entries of the lib dir which don't belong to any dependency of the manifest, e.g. after
+/// a dependency was removed or renamed. Hidden entries are never reported.
+pub fn find_orphans(man: &TomlManifest, libdir: &Path) -> Result<Vec<PathBuf>> {
+    if !libdir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let expected = man.dependencies.iter().flatten()
+        .map(|(name, dep)| absolute_path(dependency_dir(libdir, name, dep)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut orphans = Vec::new();
+    for entry in std::fs::read_dir(libdir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // parents of dependencies with `into` are part of the lib dir too
+        let path = absolute_path(entry.path())?;
+        if !expected.iter().any(|dir| dir.starts_with(&path)) {
+            orphans.push(entry.path());
+        }
+    }
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Writes the currently vendored commit of floating dependencies as `rev` into the manifest.
+/// Without names, all floating dependencies get pinned.
+pub fn pin(file_path: &Path, opts: &GlobalOptions, names: &[String], reporter: &dyn Reporter) -> Result<()> {
+    let (man, origins) = load_manifest_with_origins(file_path)?;
+    let deps = man.dependencies.clone().unwrap_or_default();
+    for name in names {
+        if !deps.contains_key(name) {
+            return Err(Error::msg(format!("Unknown dependency \"{}\"", name)));
+        }
+    }
+
+    let libdir = get_lib_dir(&man, opts);
+    // revisions get written into the manifest that defines the dependency
+    let mut revs: BTreeMap<&PathBuf, BTreeMap<String, String>> = BTreeMap::new();
+    for (name, dep) in &deps {
+        if !names.is_empty() && !names.contains(name) {
+            continue;
+        }
+        if !is_floating(dep) {
+            if !names.is_empty() {
+                reporter.info(&format!("\"{}\" is not following a branch, skipping", name));
+            }
+            continue;
+        }
+
+        let dst = dependency_dir(&libdir, name, dep);
+        let repo = match git2::Repository::open(&dst) {
+            Ok(repo) => repo,
+            Err(_) => return Err(Error::msg(format!("\"{}\" is not checked out, run \"dep update\" first", name))),
+        };
+        let rev = repo.head()?.peel_to_commit()?.id().to_string();
+
+        reporter.info(&format!("Pinning \"{}\" to \"{}\"", name, rev));
+        revs.entry(&origins[name]).or_default().insert(name.clone(), rev);
+    }
+
+    for (path, revs) in revs {
+        set_dependency_keys(path, "rev", &revs)?;
+    }
+    Ok(())
+}
+
+/// Renames the dependency `old`, which is the name in the manifest or an `as`, and moves its vendored directory.
+///
+/// Registry dependencies and those with an `as` keep their name, which registry packages are looked up by,
+/// and get `as = "<new>"` instead.
+pub fn rename(file_path: &Path, opts: &GlobalOptions, old: &str, new: &str, reporter: &dyn Reporter) -> Result<()> {
+    let (man, origins) = load_manifest_with_origins(file_path)?;
+    let deps = man.dependencies.clone().unwrap_or_default();
+    let (name, dep) = match deps.get_key_value(old) {
+        Some(found) => found,
+        None => match deps.iter().find(|(_, dep)| dep.name.as_deref() == Some(old)) {
+            Some(found) => found,
+            None => return Err(Error::msg(format!("Unknown dependency \"{}\"", old))),
+        },
+    };
+    let dir_name = dep.name.is_some() || is_registry_dependency(dep);
+    if !dir_name && deps.contains_key(new) {
+        return Err(Error::msg(format!("There is a dependency \"{}\" already", new)));
+    }
+    let renamed = if dir_name {
+        TomlDependency { name: Some(new.to_owned()), ..dep.clone() }
+    } else {
+        dep.clone()
+    };
+
+    let libdir = get_lib_dir(&man, opts);
+    let src = dependency_dir(&libdir, name, dep);
+    let dst = dependency_dir(&libdir, if dir_name { name } else { new }, &renamed);
+    // a link is there if its target isn't
+    let vendored = src.symlink_metadata().is_ok();
+    if vendored && dst.symlink_metadata().is_ok() {
+        return Err(Error::msg(format!("\"{}\" exists already", dst.to_string_lossy())));
+    }
+    let _lock = if libdir.exists() { Some(lock_dir(&libdir, true, reporter)?) } else { None };
+
+    reporter.info(&format!("Renaming \"{}\" to \"{}\"", old, new));
+    rename_dependency(&origins[name], name, new, dir_name)?;
+    if vendored {
+        reporter.info(&format!("Moving \"{}\" to \"{}\"", src.to_string_lossy(), dst.to_string_lossy()));
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&src, &dst)?;
+        // worktrees are registered with their path in the shared repository
+        if dst.join(".git").is_file() {
+            run_git(&dst, &["worktree", "repair"])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,330 @@
+//! Git urls, split into scheme, user, host, port and path, and made from a `server` and a `repo`.
+//!
+//! Besides urls with a scheme (`ssh://git@host:7999/path`, `https://host/path`), git takes scp like
+//! ssh urls (`git@host:path`), which can't have a port. They are written as `ssh://` urls when they get one.
+
+use std::fmt;
+
+use crate::config::Protocol;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitUrl {
+    // ssh, https, git, git+ssh, ... None for scp like urls
+    pub scheme: Option<String>,
+    pub user: Option<String>,
+    // ipv6 addresses keep their brackets
+    pub host: String,
+    pub port: Option<u16>,
+    // without the slash after the host, relative to the home dir for scp like urls
+    pub path: String,
+}
+
+/// Splits `[user@]host[:port]` of an url, None if the port isn't a number.
+fn split_authority(authority: &str) -> Option<(Option<String>, String, Option<u16>)> {
+    let (user, host_port) = match authority.rsplit_once('@') {
+        Some((user, host_port)) => (Some(user.to_owned()), host_port),
+        None => (None, authority),
+    };
+    // the colons of [::1] aren't a port
+    let port_start = match host_port.strip_prefix('[') {
+        Some(rest) => rest.find(']').map(|end| end + 2)?,
+        None => host_port.find(':').unwrap_or(host_port.len()),
+    };
+    let (host, port) = host_port.split_at(port_start.min(host_port.len()));
+    let port = match port.strip_prefix(':') {
+        Some(port) => Some(port.parse().ok()?),
+        None if port.is_empty() => None,
+        None => return None,
+    };
+    Some((user, host.to_owned(), port))
+}
+
+impl GitUrl {
+    /// Parses an url with a scheme or a scp like url. Local paths (like `../repo` or `C:\repo`) are None.
+    pub fn parse(url: &str) -> Option<GitUrl> {
+        if let Some((scheme, rest)) = url.split_once("://") {
+            let (authority, path) = match rest.split_once('/') {
+                Some((authority, path)) => (authority, path),
+                None => (rest, ""),
+            };
+            let (user, host, port) = split_authority(authority)?;
+            return Some(GitUrl { scheme: Some(scheme.to_lowercase()), user, host, port, path: path.to_owned() });
+        }
+        let (authority, path) = url.split_once(':')?;
+        // windows drive letters and local paths with a colon
+        if authority.len() < 2 || authority.contains('/') || authority.contains('\\') || authority.starts_with('.') {
+            return None;
+        }
+        let (user, host) = match authority.rsplit_once('@') {
+            Some((user, host)) => (Some(user.to_owned()), host.to_owned()),
+            None => (None, authority.to_owned()),
+        };
+        Some(GitUrl { scheme: None, user, host, port: None, path: path.to_owned() })
+    }
+
+    /// Parses the `server` of a dependency, or of `[servers]`. Unlike urls, servers without a scheme can have
+    /// a port (`git.corp.com:7999`), or a path the repositories are in (`git@github.com:org`).
+    pub fn parse_server(server: &str) -> Option<GitUrl> {
+        let server = server.trim_end_matches('/');
+        if server.contains("://") {
+            return GitUrl::parse(server);
+        }
+        let (authority, path) = match server.split_once(':') {
+            Some((authority, path)) if path.chars().all(|c| c.is_ascii_digit()) && !path.is_empty() => {
+                let (user, host, port) = split_authority(&format!("{}:{}", authority, path))?;
+                return Some(GitUrl { scheme: None, user, host, port, path: String::new() });
+            }
+            Some((authority, path)) => (authority, path),
+            None => (server, ""),
+        };
+        if authority.len() < 2 || authority.contains('/') || authority.contains('\\') || authority.starts_with('.') {
+            return None;
+        }
+        GitUrl::parse(&format!("{}:{}", authority, path))
+    }
+
+    /// Whether git reaches the url over ssh.
+    pub fn is_ssh(&self) -> bool {
+        self.scheme.as_deref().map(|scheme| scheme.contains("ssh")).unwrap_or(true)
+    }
+
+    pub fn is_http(&self) -> bool {
+        matches!(self.scheme.as_deref(), Some("http") | Some("https"))
+    }
+
+    /// The url of `repo` below the path of this one.
+    pub fn join(&self, repo: &str) -> GitUrl {
+        let repo = repo_path(repo);
+        let path = match self.path.trim_end_matches('/') {
+            "" => repo,
+            path => format!("{}/{}", path, repo),
+        };
+        GitUrl { path, ..self.clone() }
+    }
+}
+
+impl fmt::Display for GitUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let user = self.user.as_ref().map(|user| format!("{}@", user)).unwrap_or_default();
+        match (&self.scheme, self.port) {
+            (None, None) => write!(f, "{}{}:{}", user, self.host, self.path),
+            // scp like urls can't have a port, their path is taken from the root then
+            (None, Some(port)) => write!(f, "ssh://{}{}:{}/{}", user, self.host, port, self.path.trim_start_matches('/')),
+            (Some(scheme), port) => {
+                let port = port.map(|port| format!(":{}", port)).unwrap_or_default();
+                write!(f, "{}://{}{}{}/{}", scheme, user, self.host, port, self.path)
+            }
+        }
+    }
+}
+
+/// `repo` without empty segments (leading, trailing or doubled slashes), which any number of groups can be in
+/// front of (`group/subgroup/project`). A `.git` suffix is kept, as some servers need it, and others take both.
+pub fn repo_path(repo: &str) -> String {
+    let mut segments = repo.split(['/', '\\']).filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+    // project/.git is the repository of the working tree project
+    if segments.len() > 1 && segments.last() == Some(&".git") {
+        segments.pop();
+        return format!("{}.git", segments.join("/"));
+    }
+    segments.join("/")
+}
+
+/// The url of `repo` on `server`. ssh urls get the user git if the server has none, servers without a scheme are
+/// reached over ssh. `protocol` switches a server to ssh or https, only its host and path are kept then.
+/// Servers which are local directories get the repo appended as path.
+pub fn make_url(server: &str, repo: &str, protocol: Option<Protocol>) -> String {
+    let mut url = match GitUrl::parse_server(server) {
+        Some(url) => url,
+        None => return format!("{}/{}", server.trim_end_matches('/'), repo_path(repo)),
+    };
+    match protocol {
+        Some(Protocol::Https) if !url.is_http() => {
+            url = GitUrl { scheme: Some("https".to_owned()), user: None, port: None, ..url };
+        }
+        Some(Protocol::Ssh) if !url.is_ssh() => {
+            url = GitUrl { scheme: None, user: None, port: None, ..url };
+        }
+        _ => {}
+    }
+    if url.is_ssh() && url.user.is_none() {
+        url.user = Some("git".to_owned());
+    }
+    url.join(repo).to_string()
+}
+
+/// The page of the repository in the browser: its url over https, without user, ssh port and `.git` suffix.
+/// None for local repositories and urls of other protocols than ssh, http(s) and git.
+pub fn web_url(url: &str) -> Option<String> {
+    let url = GitUrl::parse(url)?;
+    let scheme = match url.scheme.as_deref() {
+        Some("http") => "http",
+        Some("https") | Some("git") => "https",
+        _ if url.is_ssh() => "https",
+        _ => return None,
+    };
+    // the port of http(s) servers serves the pages too, the one of ssh and git daemons doesn't
+    let port = url.port.filter(|_| url.is_http()).map(|port| format!(":{}", port)).unwrap_or_default();
+    let path = url.path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    Some(format!("{}://{}{}/{}", scheme, url.host, port, path))
+}
+
+/// What tells repositories apart: host and path of the url, without user, port, `.git` suffix and case,
+/// so the ssh and https urls of a repository are the same one. Local paths stay as they are.
+pub fn repository_id(url: &str) -> String {
+    match GitUrl::parse(url) {
+        Some(url) => {
+            let path = repo_path(&url.path);
+            let path = path.strip_suffix(".git").unwrap_or(&path);
+            format!("{}/{}", url.host, path).to_lowercase()
+        }
+        None => repo_path(url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls_with_a_scheme() {
+        let url = GitUrl::parse("ssh://git@git.corp.com:7999/scm/repo.git").unwrap();
+        assert_eq!(url.scheme.as_deref(), Some("ssh"));
+        assert_eq!(url.user.as_deref(), Some("git"));
+        assert_eq!(url.host, "git.corp.com");
+        assert_eq!(url.port, Some(7999));
+        assert_eq!(url.path, "scm/repo.git");
+        assert_eq!(url.to_string(), "ssh://git@git.corp.com:7999/scm/repo.git");
+
+        let url = GitUrl::parse("https://github.com/org/repo").unwrap();
+        assert_eq!((url.user.as_deref(), url.port), (None, None));
+        assert!(url.is_http() && !url.is_ssh());
+    }
+
+    #[test]
+    fn parses_scp_like_urls() {
+        let url = GitUrl::parse("git@github.com:org/repo").unwrap();
+        assert_eq!(url.scheme, None);
+        assert_eq!(url.user.as_deref(), Some("git"));
+        assert_eq!(url.host, "github.com");
+        assert_eq!(url.path, "org/repo");
+        assert!(url.is_ssh());
+        assert_eq!(url.to_string(), "git@github.com:org/repo");
+    }
+
+    #[test]
+    fn local_paths_are_no_urls() {
+        assert_eq!(GitUrl::parse("../mirror/repo.git"), None);
+        assert_eq!(GitUrl::parse("C:\\mirror\\repo.git"), None);
+        assert_eq!(GitUrl::parse("C:/mirror/repo.git"), None);
+        assert_eq!(GitUrl::parse("/srv/mirror/repo.git"), None);
+    }
+
+    #[test]
+    fn keeps_ipv6_hosts() {
+        let url = GitUrl::parse("ssh://git@[::1]:2222/repo").unwrap();
+        assert_eq!(url.host, "[::1]");
+        assert_eq!(url.port, Some(2222));
+        assert_eq!(url.to_string(), "ssh://git@[::1]:2222/repo");
+    }
+
+    #[test]
+    fn rejects_invalid_ports() {
+        assert_eq!(GitUrl::parse("ssh://host:port/repo"), None);
+        assert_eq!(GitUrl::parse("ssh://host:99999/repo"), None);
+    }
+
+    #[test]
+    fn scp_like_urls_with_a_port_become_ssh_urls() {
+        let mut url = GitUrl::parse("git@host:org/repo").unwrap();
+        url.port = Some(2222);
+        assert_eq!(url.to_string(), "ssh://git@host:2222/org/repo");
+    }
+
+    #[test]
+    fn joins_paths() {
+        let url = GitUrl::parse("https://git.corp.com/scm/").unwrap();
+        assert_eq!(url.join("/org/repo").to_string(), "https://git.corp.com/scm/org/repo");
+        let url = GitUrl::parse("https://git.corp.com").unwrap();
+        assert_eq!(url.join("org/repo").to_string(), "https://git.corp.com/org/repo");
+    }
+
+    #[test]
+    fn normalizes_repo_paths() {
+        assert_eq!(repo_path("group/subgroup/project"), "group/subgroup/project");
+        assert_eq!(repo_path("/group//subgroup/project/"), "group/subgroup/project");
+        assert_eq!(repo_path("group/subgroup/project.git/"), "group/subgroup/project.git");
+        assert_eq!(repo_path("group/project/.git"), "group/project.git");
+        assert_eq!(repo_path("group\\project"), "group/project");
+    }
+
+    #[test]
+    fn makes_urls_of_subgroups() {
+        let repo = "group/subgroup/subsubgroup/project";
+        assert_eq!(make_url("gitlab.com", repo, None), "git@gitlab.com:group/subgroup/subsubgroup/project");
+        assert_eq!(make_url("gitlab.com", repo, Some(Protocol::Https)), "https://gitlab.com/group/subgroup/subsubgroup/project");
+        assert_eq!(make_url("https://gitlab.com/", repo, Some(Protocol::Ssh)), "git@gitlab.com:group/subgroup/subsubgroup/project");
+        assert_eq!(make_url("ssh://gitlab.corp.com:2222", repo, None), "ssh://git@gitlab.corp.com:2222/group/subgroup/subsubgroup/project");
+        assert_eq!(make_url("git@gitlab.com:group", "subgroup/project", None), "git@gitlab.com:group/subgroup/project");
+        assert_eq!(make_url("https://gitlab.com/group/", "/subgroup//project/", None), "https://gitlab.com/group/subgroup/project");
+    }
+
+    #[test]
+    fn keeps_the_git_suffix() {
+        assert_eq!(make_url("gitlab.com", "group/subgroup/project.git", None), "git@gitlab.com:group/subgroup/project.git");
+        assert_eq!(make_url("gitlab.com", "group/subgroup/project.git/", Some(Protocol::Https)), "https://gitlab.com/group/subgroup/project.git");
+        assert_eq!(make_url("../mirrors/", "/group/project.git", None), "../mirrors/group/project.git");
+    }
+
+    #[test]
+    fn makes_web_urls() {
+        assert_eq!(web_url("git@github.com:org/repo.git").as_deref(), Some("https://github.com/org/repo"));
+        assert_eq!(web_url("ssh://git@git.corp.com:7999/scm/repo").as_deref(), Some("https://git.corp.com/scm/repo"));
+        assert_eq!(web_url("https://token@gitlab.com/group/subgroup/repo/").as_deref(), Some("https://gitlab.com/group/subgroup/repo"));
+        assert_eq!(web_url("http://git.local:3000/org/repo.git").as_deref(), Some("http://git.local:3000/org/repo"));
+        assert_eq!(web_url("git://git.kernel.org/pub/scm/git/git.git").as_deref(), Some("https://git.kernel.org/pub/scm/git/git"));
+        assert_eq!(web_url("file:///srv/git/repo"), None);
+        assert_eq!(web_url("../repo"), None);
+    }
+
+    #[test]
+    fn identifies_repositories() {
+        assert_eq!(repository_id("git@github.com:Org/Repo.git"), "github.com/org/repo");
+        assert_eq!(repository_id("https://github.com/org/repo/"), "github.com/org/repo");
+        assert_eq!(repository_id("ssh://git@github.com:22/org/repo"), "github.com/org/repo");
+        assert_ne!(repository_id("https://github.com/org/repo"), repository_id("https://gitlab.com/org/repo"));
+        assert_eq!(repository_id("/srv/git/repo"), "srv/git/repo");
+    }
+
+    #[test]
+    fn makes_urls_of_servers() {
+        assert_eq!(make_url("git.corp.com", "org/repo", None), "git@git.corp.com:org/repo");
+        assert_eq!(make_url("me@git.corp.com", "org/repo", None), "me@git.corp.com:org/repo");
+        assert_eq!(make_url("git@github.com:org", "repo", None), "git@github.com:org/repo");
+        assert_eq!(make_url("ssh://git.corp.com:7999", "org/repo", None), "ssh://git@git.corp.com:7999/org/repo");
+        assert_eq!(make_url("ssh://me@git.corp.com:7999/", "org/repo", None), "ssh://me@git.corp.com:7999/org/repo");
+        assert_eq!(make_url("git.corp.com:7999", "org/repo", None), "ssh://git@git.corp.com:7999/org/repo");
+        assert_eq!(make_url("https://git.corp.com", "org/repo", None), "https://git.corp.com/org/repo");
+        assert_eq!(make_url("git://git.corp.com", "org/repo", None), "git://git.corp.com/org/repo");
+        assert_eq!(make_url("file:///srv/git", "org/repo", None), "file:///srv/git/org/repo");
+        assert_eq!(make_url("../mirrors", "org/repo", None), "../mirrors/org/repo");
+    }
+
+    #[test]
+    fn joins_ssh_servers_with_a_slash() {
+        // joined with a colon like scp urls, the repo would end up in the port
+        assert_eq!(make_url("ssh://git.corp.com", "org/repo", None), "ssh://git@git.corp.com/org/repo");
+        assert_eq!(make_url("ssh://me@git.corp.com", "org/repo", None), "ssh://me@git.corp.com/org/repo");
+        assert_eq!(make_url("ssh://git@git.corp.com:7999/scm", "org/repo", None), "ssh://git@git.corp.com:7999/scm/org/repo");
+    }
+
+    #[test]
+    fn switches_the_protocol() {
+        assert_eq!(make_url("git.corp.com", "org/repo", Some(Protocol::Https)), "https://git.corp.com/org/repo");
+        assert_eq!(make_url("ssh://git@git.corp.com:7999/scm", "org/repo", Some(Protocol::Https)), "https://git.corp.com/scm/org/repo");
+        assert_eq!(make_url("https://token@git.corp.com/gitlab", "org/repo", Some(Protocol::Ssh)), "git@git.corp.com:gitlab/org/repo");
+        assert_eq!(make_url("https://git.corp.com", "org/repo", Some(Protocol::Https)), "https://git.corp.com/org/repo");
+        assert_eq!(make_url("git.corp.com", "org/repo", Some(Protocol::Ssh)), "git@git.corp.com:org/repo");
+    }
+}
@@ -0,0 +1,378 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Debug, Parser)]
+#[command(name = "dep", version, about = "A very basic, git based, flat dependency manager.")]
+pub struct Cli {
+    /// Format of the manifest. Detected from the existing deps.* file by default
+    #[arg(long, global = true, value_enum)]
+    pub manifest_format: Option<Format>,
+
+    /// Path of the global config to use. Can also be set with DEP_CONFIG
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Don't access the network. Only local repositories are updated
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Reads the passphrase of the protected ssh key from the first line of a file. Can also be set with DEP_SSH_PASSPHRASE
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "passphrase_stdin")]
+    pub passphrase_file: Option<PathBuf>,
+
+    /// Reads the passphrase of the protected ssh key from the first line of stdin
+    #[arg(long, global = true)]
+    pub passphrase_stdin: bool,
+
+    /// Never asks for anything and fails instead. The default when stdin isn't a terminal
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Prints the global config path
+    Global {
+        #[command(subcommand)]
+        action: Option<GlobalAction>,
+    },
+    /// Creates an empty manifest
+    Init {
+        /// Asks for the project settings and a first dependency
+        #[arg(short, long, conflicts_with = "template")]
+        interactive: bool,
+        /// Copies a template from the templates dir of the global config, a directory or a git url
+        #[arg(short, long)]
+        template: Option<String>,
+    },
+    /// Updates all dependencies, or only the given ones
+    Update {
+        /// Dependencies to update, all if none are given
+        names: Vec<String>,
+        /// Removes and fetches these dependencies again, while the others are updated as usual.
+        /// Without names it removes the lib dir (or the given dependencies) and starts from a clean state
+        #[arg(short, long, value_name = "NAME", num_args = 0..)]
+        force: Option<Vec<String>>,
+        /// Doesn't ask before --force deletes the lib dir, needed when not running in a terminal
+        #[arg(short, long)]
+        yes: bool,
+        /// Updates checkouts with uncommitted changes or local commits anyway, which drops them
+        #[arg(long)]
+        discard_local: bool,
+        /// Fails right away if another dep process uses the lib dir or the cache, instead of waiting
+        #[arg(long)]
+        no_wait: bool,
+        /// Continues with the other dependencies when one fails, and prints a summary at the end
+        #[arg(short, long)]
+        keep_going: bool,
+        /// Removes vendored directories of dependencies which aren't in the manifest anymore
+        #[arg(long)]
+        prune: bool,
+        /// Number of dependencies to update in parallel
+        #[arg(short, long, default_value_t = 1, value_name = "N")]
+        jobs: usize,
+        /// Writes the revisions that changed into a report, like for pull request descriptions
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        report: Option<ReportFormat>,
+        /// File of the report, dep-report.md or dep-report.json by default
+        #[arg(long, value_name = "PATH", requires = "report")]
+        report_file: Option<PathBuf>,
+        /// Fetches the git dependencies from the bare mirrors of this directory, made by dep mirror
+        #[arg(long, value_name = "DIR")]
+        mirror: Option<PathBuf>,
+    },
+    /// Pins dependencies that follow a branch to their currently vendored revision
+    Pin {
+        /// Dependencies to pin, all floating ones if none are given
+        names: Vec<String>,
+    },
+    /// Shows everything known about a dependency: source, ref, vendored commit, directory, size, changes and hooks
+    Info {
+        /// The dependency to show
+        name: String,
+    },
+    /// Opens the web page of a dependency (its homepage, or the page of its git repository) in the browser
+    Open {
+        /// The dependency to open
+        name: String,
+    },
+    /// Shows the chains of manifests (includes and manifests of vendored dependencies) which ask for a dependency
+    Why {
+        /// The name of the dependency, or its `as`
+        name: String,
+    },
+    /// Renames a dependency and moves its vendored directory
+    Rename {
+        /// The name of the dependency, or its `as`
+        old: String,
+        /// The new name
+        new: String,
+    },
+    /// Shows the diff from the pinned revision (or the tip of the remote branch or tag) to the vendored tree
+    Diff {
+        /// The dependency to compare
+        name: String,
+    },
+    /// Shows the upstream commits between the vendored commit and the tip of the remote branch or tag
+    Log {
+        /// The dependency to show the commits of
+        name: String,
+        /// Starts at the pinned revision instead of the vendored commit
+        #[arg(long)]
+        since_locked: bool,
+    },
+    /// Collects the changes of every dependency since the revisions of the committed manifest
+    Changelog {
+        /// Writes the changelog into a file instead of printing it
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Runs a command in every vendored dependency, with DEP_NAME, DEP_PATH and DEP_URL set
+    Foreach {
+        /// The command and its arguments, after --
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Runs a command with DEP_LIB_DIR and DEP_<NAME>_DIR set for every dependency
+    Exec {
+        /// The command and its arguments, after --
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Writes a file with the paths of the dependencies for build systems, which updates regenerate
+    Emit {
+        #[arg(value_enum)]
+        format: EmitKind,
+        /// Path of the file, "-" prints it. Updates only regenerate files at the default path
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Writes the dependencies as the manifest of another tool
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Adds dependencies from the manifest of another tool to the manifest
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Writes a software bill of materials with the url, resolved commit and licenses of every dependency
+    Sbom {
+        #[arg(long, value_enum, default_value = "cyclonedx")]
+        format: SbomKind,
+        /// Writes the bill of materials into a file instead of printing it
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Reports known vulnerabilities of the vendored or pinned revisions from the OSV database
+    Audit {
+        /// A downloaded copy of the database (a directory or zip of OSV json files) to use instead of the OSV API
+        #[arg(long, value_name = "PATH")]
+        database: Option<PathBuf>,
+    },
+    /// Creates or updates bare mirrors of every git dependency in a directory, for networks without access to the servers
+    Mirror {
+        /// The directory of the mirrors
+        #[arg(value_name = "DIR")]
+        dir: PathBuf,
+    },
+    /// Packs the checked out revisions of all vendored dependencies and the manifests into one file, for offline transfer
+    Bundle {
+        /// The bundle to write, like deps.depbundle
+        #[arg(value_name = "PATH")]
+        output: PathBuf,
+    },
+    /// Vendors the dependencies of a bundle made by dep bundle at their bundled revisions, without the network
+    Restore {
+        /// The bundle to unpack
+        #[arg(value_name = "PATH")]
+        bundle: PathBuf,
+    },
+    /// Fetches the git dependencies (or the given urls) into the cache, without touching the lib dir
+    Prefetch {
+        /// Urls to fetch instead of those of the manifest
+        urls: Vec<String>,
+    },
+    /// Manages the cache of registry indexes, templates and repositories
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Shows the state of every vendored dependency, like broken links or missing checkouts
+    Status,
+    /// Shows the changed files of every vendored git dependency, like git status --short
+    St,
+    /// Validates the manifest, without touching the network or file system
+    Check,
+    /// Prints the json schema of the manifest or the global config
+    Schema {
+        #[arg(value_enum, default_value = "manifest")]
+        kind: SchemaKind,
+    },
+    /// Converts the manifest to version 2
+    Migrate,
+    /// Checks the environment (home dir, ssh keys, servers, symlinks, cache) and the manifest
+    Doctor,
+    /// Reads or changes the global config (or the manifest)
+    Config {
+        /// Operate on the manifest instead of the global config
+        #[arg(long)]
+        project: bool,
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Stores the passphrase of the ssh key in the keychain of the OS, so protected keys don't ask for it
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Runs dep-<command> from the PATH
+    #[command(external_subcommand)]
+    External(Vec<String>),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GlobalAction {
+    /// Moves a legacy $HOME/.deprc to the current config location
+    Migrate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuthAction {
+    /// Asks for the passphrase of the ssh key of the global config (unless it is given) and stores it
+    Set {
+        /// Stores the passphrase of this key instead, like the ssh-key of a dependency
+        #[arg(long, value_name = "PATH")]
+        key: Option<PathBuf>,
+    },
+    /// Removes the stored passphrase
+    Clear {
+        /// Removes the passphrase of this key instead
+        #[arg(long, value_name = "PATH")]
+        key: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheAction {
+    /// Removes what no existing project uses (or wasn't used for a while) and repacks the rest,
+    /// the repositories clones borrow objects from are always kept
+    Gc {
+        /// Also removes what projects use, but wasn't used for more than this many days
+        #[arg(long, value_name = "DAYS")]
+        max_age: Option<u64>,
+    },
+    /// Lists the cached urls with their size, last use and the projects which use them
+    List,
+    /// Shows the size of the cache, and how much of it no project uses
+    Stats,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum EmitKind {
+    /// .deps.env with shell variables, to source it
+    Env,
+    /// deps.properties, mapping every dependency to its directory
+    Props,
+    /// deps.cmake with DEP_<NAME>_DIR variables, and add_subdirectory calls with DEP_ADD_SUBDIRECTORIES
+    Cmake,
+    /// A wrap file in subprojects for every git checkout
+    Meson,
+    /// deps.bzl with a dep_repositories() macro of local repositories, to call from WORKSPACE
+    Bazel,
+    /// deps.mk with DEP_<NAME>_DIR variables and a deps target running dep update
+    Make,
+    /// Deps.props with Dep<Name>Dir properties and include paths, for visual studio projects
+    Msbuild,
+    /// deps-patch.toml with [patch] sections for the crates of git dependencies, for cargo --config
+    CargoPatch,
+    /// Replace directives in go.mod for every vendored go module, "-o -" prints them
+    GoReplace,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportFormat {
+    /// Writes the git dependencies into .gitmodules, which updates regenerate
+    Submodules {
+        /// Also records the pinned (or vendored) revisions as gitlinks in the index of the project
+        #[arg(long)]
+        stage: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ImportSource {
+    /// Adds the submodules of the project, pinned to their recorded commits
+    Submodules,
+    /// Adds the git dependencies of a Cargo.toml, pinned to the commits of its Cargo.lock
+    Cargo {
+        /// The cargo manifest, the Cargo.lock next to it is read too if there is one
+        #[arg(default_value = "Cargo.toml", value_name = "PATH")]
+        manifest: PathBuf,
+    },
+    /// Adds the dependencies of a vcpkg.json, with the repositories of the ports in $VCPKG_ROOT
+    Vcpkg {
+        #[arg(default_value = "vcpkg.json", value_name = "PATH")]
+        manifest: PathBuf,
+    },
+    /// Adds the requirements of a conanfile.txt or conanfile.py as registry dependencies
+    Conan {
+        #[arg(default_value = "conanfile.txt", value_name = "PATH")]
+        recipe: PathBuf,
+    },
+    /// Adds the git modules of a peru.yaml, vendored where its imports put them
+    Peru {
+        #[arg(default_value = "peru.yaml", value_name = "PATH")]
+        file: PathBuf,
+    },
+    /// Adds the git sources of a gitman.yml, pinned to its locked commits
+    Gitman {
+        #[arg(default_value = "gitman.yml", value_name = "PATH")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SbomKind {
+    /// CycloneDX 1.5 json
+    Cyclonedx,
+    /// SPDX 2.3 json
+    Spdx,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SchemaKind {
+    Manifest,
+    ManifestV2,
+    Global,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Prints the value of a dotted key, like ssh.protected
+    Get { key: String },
+    /// Sets a key. Values which aren't valid toml are taken as strings
+    Set { key: String, value: String },
+    /// Removes a key
+    Unset { key: String },
+    /// Prints all keys
+    List,
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use dep_core::cache::record_use;
+use dep_core::config::GlobalOptions;
+use dep_core::filelock::lock_dir;
+use dep_core::git::{checkout, is_local_url, local_url, CheckoutOptions};
+use dep_core::manifest::{find_manifest, parse, write_manifest, ManifestFormat, TomlDependency, TomlManifest, TomlProject};
+use dep_core::paths::{absolute_path, cache_name, get_templates_dir};
+use dep_core::Credentials;
+
+use crate::prompt;
+
+fn project_name() -> std::io::Result<String> {
+    Ok(std::env::current_dir()?.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or("".to_owned()))
+}
+
+/// The manifest written by a plain `dep init`.
+pub fn default_manifest() -> std::io::Result<TomlManifest> {
+    Ok(TomlManifest {
+        include: None,
+        project: TomlProject {
+            name: project_name()?,
+            authors: Some(vec![whoami::username()]),
+            lib_dir: None,
+            git_server: None,
+
+            // package metadata
+            description: None,
+            homepage: None,
+            repository: None,
+            metadata: None,
+        },
+        dependencies: None,
+        config: None,
+    })
+}
+
+fn optional(answer: String) -> Option<String> {
+    if answer.is_empty() { None } else { Some(answer) }
+}
+
+/// Anything that doesn't look like an url or a local path is a repo on the git server.
+fn is_repo(source: &str) -> bool {
+    !(source.contains("://") || source.contains('@') || source.starts_with('.') || source.starts_with('/') || source.starts_with('~'))
+}
+
+fn quote(value: &str) -> String {
+    toml_edit::Value::from(value).to_string()
+}
+
+/// Writes the answers as deps.toml, with a comment for every key.
+fn commented_manifest(man: &TomlManifest, opts: &GlobalOptions) -> String {
+    let project = &man.project;
+    let mut content = String::new();
+    content += "[project]\n";
+    content += &format!("name = {}\n", quote(&project.name));
+    if let Some(authors) = &project.authors {
+        content += &format!("authors = [{}]\n", authors.iter().map(|a| quote(a)).collect::<Vec<_>>().join(", "));
+    }
+
+    content += "\n# directory the dependencies get vendored into, relative to the project\n";
+    content += &format!("# without it, default-lib-dir of the global config (\"{}\") is used\n", opts.general.default_lib_dir.to_string_lossy());
+    match &project.lib_dir {
+        Some(lib_dir) => content += &format!("lib-dir = {}\n", quote(&lib_dir.to_string_lossy())),
+        None => content += &format!("# lib-dir = {}\n", quote(&opts.general.default_lib_dir.to_string_lossy())),
+    }
+
+    content += "\n# server of dependencies given as repo = 'user/name'\n";
+    content += "# either a host or the name of a server in [servers] of the global config\n";
+    match &project.git_server {
+        Some(server) => content += &format!("git-server = {}\n", quote(server)),
+        None => content += "# git-server = 'github.com'\n",
+    }
+
+    content += "\n[dependencies]\n";
+    content += "# some_repo = { git = 'https://github.com/user/some_repo', branch = 'main' }\n";
+    content += "# some_private_repo = { repo = 'user/some_private_repo', tag = 'v1.0.0' }\n";
+    content += "# some_local_repo = { path = '../some/local/folder' }\n";
+    for (name, dep) in man.dependencies.iter().flatten() {
+        let mut keys = Vec::new();
+        if let Some(git) = &dep.git {
+            keys.push(format!("git = {}", quote(git)));
+        }
+        if let Some(repo) = &dep.repo {
+            keys.push(format!("repo = {}", quote(repo)));
+        }
+        if let Some(branch) = &dep.branch {
+            keys.push(format!("branch = {}", quote(branch)));
+        }
+        content += &format!("{} = {{ {} }}\n", toml_edit::Key::new(name.as_str()).display_repr(), keys.join(", "));
+    }
+    content
+}
+
+/// Asks for the project settings and optionally a first dependency, then writes the manifest.
+/// Toml manifests get comments explaining every key.
+pub fn interactive(file_path: &Path, opts: &GlobalOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut man = default_manifest()?;
+    man.project.name = prompt("Project name", &man.project.name)?;
+    man.project.lib_dir = optional(prompt("Lib dir (empty for the default lib dir)", "")?).map(PathBuf::from);
+    man.project.git_server = optional(prompt("Git server (empty for none)", "")?);
+
+    if let Some(name) = optional(prompt("Name of the first dependency (empty to skip)", "")?) {
+        let source = prompt(if man.project.git_server.is_some() { "Git url, or repo on the git server" } else { "Git url" }, "")?;
+        if source.is_empty() {
+            return Err(format!("\"{}\" needs a git url", name).into());
+        }
+        let (git, repo) = if man.project.git_server.is_some() && is_repo(&source) { (None, Some(source)) } else { (Some(source), None) };
+        let branch = optional(prompt("Branch (empty for the default branch)", "")?);
+
+        let mut dependencies = BTreeMap::new();
+        dependencies.insert(name, TomlDependency { git, repo, branch, ..Default::default() });
+        man.dependencies = Some(dependencies);
+    }
+
+    if ManifestFormat::from_path(file_path) != ManifestFormat::Toml {
+        write_manifest(file_path, &man)?;
+    } else {
+        let content = commented_manifest(&man, opts);
+        // make sure nothing got lost in the hand written version
+        parse::<TomlManifest>(&content, ManifestFormat::Toml)?;
+        std::fs::write(file_path, content)?;
+    }
+    println!("Created {}", file_path.to_string_lossy());
+    Ok(())
+}
+
+fn is_template_url(template: &str) -> bool {
+    template.contains("://") || template.starts_with("git@") || template.ends_with(".git")
+}
+
+/// Finds the directory of a template: a local directory, a git url (cloned into the cache)
+/// or the name of a directory in the templates dir of the global config.
+fn template_dir(template: &str, file_path: &Path, opts: &GlobalOptions, credentials: &Credentials, offline: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if is_template_url(template) {
+        let _lock = lock_dir(&opts.cache_dir()?, true, &crate::print_progress)?;
+        let cache = opts.cache_dir()?.join("templates");
+        let name = cache_name(template);
+        if offline && cache.join(&name).exists() {
+            println!("Offline, using the cached template \"{}\"", template);
+        } else if offline {
+            return Err(format!("Template \"{}\" can't be fetched in offline mode", template).into());
+        } else {
+            if !is_local_url(template) {
+                credentials.ask_passphrase_for(template)?;
+            }
+            let dep = TomlDependency { git: Some(template.to_owned()), ..Default::default() };
+            let options = CheckoutOptions { offline, ..Default::default() };
+            checkout(credentials, &local_url(template)?, &dep, &cache, &name, &options)?;
+        }
+        record_use(&opts.cache_dir()?, &cache.join(&name), template, Some(&absolute_path(file_path)?))?;
+        return Ok(cache.join(name));
+    }
+
+    let path = Path::new(template);
+    if path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    let templates = get_templates_dir()?;
+    let dir = templates.join(template);
+    if !dir.is_dir() {
+        return Err(format!("Unknown template \"{}\", templates are looked up in \"{}\"", template, templates.to_string_lossy()).into());
+    }
+    Ok(dir)
+}
+
+/// Copies the template into the project, without replacing files which already exist.
+fn copy_template(src: &Path, dst: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            copy_template(&entry.path(), &target)?;
+        } else if target.exists() {
+            println!("Keeping existing \"{}\"", target.to_string_lossy());
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Initializes the project from a template. `{{name}}` and `{{author}}` in its manifest
+/// are replaced with the project name and the current user.
+pub fn from_template(file_path: &Path, template: &str, opts: &GlobalOptions, credentials: &Credentials, offline: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let src = template_dir(template, file_path, opts, credentials, offline)?;
+    let project = file_path.parent().unwrap_or(Path::new("."));
+    copy_template(&src, project)?;
+
+    let manifest = find_manifest(None);
+    if !manifest.exists() {
+        println!("Template \"{}\" has no manifest, creating an empty one", template);
+        write_manifest(file_path, &default_manifest()?)?;
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(&manifest)?
+        .replace("{{name}}", &project_name()?)
+        .replace("{{author}}", &whoami::username());
+    std::fs::write(&manifest, content)?;
+    println!("Created {} from template \"{}\"", manifest.to_string_lossy(), template);
+    Ok(())
+}
@@ -1,579 +1,854 @@
-#[macro_use]
-extern crate serde_derive;
-
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::exit;
-
-use argparse::ArgumentParser;
-use argparse::Store;
-use argparse::StoreTrue;
-use git2;
-use git2::build::CheckoutBuilder;
-use git2::build::RepoBuilder;
-use git2::FetchOptions;
-use git2::RemoteCallbacks;
-
-use path_clean::PathClean;
-
-fn absolute_path<P>(path: P) -> std::io::Result<PathBuf>
-    where
-        P: AsRef<Path>,
-{
-    let path = path.as_ref();
-    if path.is_absolute() {
-        Ok(path.to_path_buf().clean())
-    } else {
-        Ok(std::env::current_dir()?.join(path).clean())
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+mod cli;
+mod init;
+
+use clap::Parser;
+use schemars::schema_for;
+
+use cli::{AuthAction, CacheAction, Cli, Commands, ConfigAction, EmitKind, ExportFormat, Format, GlobalAction, ImportSource, SbomKind, SchemaKind};
+
+use dep_core::audit::audit;
+use dep_core::bundle::{bundle, restore};
+use dep_core::cache::{format_age, format_size, gc, list, prefetch, BORROWED, CACHE_KINDS};
+use dep_core::check::{check_dependency, check_manifest, find_line, with_suggestion};
+use dep_core::config::{load_global_options, migrate_global_config, GlobalOptions, SshOptions};
+use dep_core::manifest::{add_dependencies, find_manifest, get_lib_dir, is_manifest_v2, load_manifest, load_manifest_with_origins, migrate, parse, write_manifest};
+use dep_core::manifest::{ManifestFormat, TomlManifest, TomlManifestV2};
+use dep_core::doctor::{diagnose, Status};
+use dep_core::emit::{emit, refresh, EmitFormat};
+use dep_core::exec::{exec, foreach};
+use dep_core::export::{self, export_submodules, GITMODULES};
+use dep_core::filelock::LOCK_FILE;
+use dep_core::git::default_ssh_keys;
+use dep_core::gitconfig::GitConfig;
+use dep_core::import::{from_cargo, from_conan, from_gitman, from_peru, from_submodules, from_vcpkg};
+use dep_core::history::{changelog, diff, log, ReportFormat};
+use dep_core::hooks::Hooks;
+use dep_core::keychain::{clear_passphrase, store_passphrase, ASKS_FOR_PASSPHRASE};
+use dep_core::metadata::metadata;
+use dep_core::mirror::{git_dependency_urls, mirror};
+use dep_core::paths::{absolute_path, get_global_config_path};
+use dep_core::requirements::{requirements, resolve_conflicts, Requirement};
+use dep_core::sbom::{sbom, SbomFormat};
+use dep_core::status::{homepage, info, local_changes, status};
+use dep_core::update::{check_lib_dir_removal, pin, rename};
+use dep_core::{update, Credentials, Error, Level, UpdateOptions};
+
+/// Imported dependencies without a url are looked up in the registry.
+const REGISTRY_HINT: &str = "Dependencies without a git url come from the registry, add their urls where it has none";
+
+fn print_problems(file_path: &Path, content: &str, problems: &[(Option<usize>, String)]) {
+    let lines = content.lines().collect::<Vec<_>>();
+    for (line, message) in problems {
+        match line {
+            Some(line) => {
+                eprintln!("{}:{}: {}", file_path.to_string_lossy(), line, message);
+                if let Some(text) = lines.get(line - 1) {
+                    eprintln!("    {} | {}", line, text);
+                }
+            }
+            None => eprintln!("{}: {}", file_path.to_string_lossy(), message),
+        }
     }
 }
 
-#[cfg(windows)]
-mod systools {
-    use std::env::VarError;
-    use std::os::windows::fs::symlink_dir;
-    use std::path::Path;
-    use crate::absolute_path;
-
-    pub fn make_symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), std::io::Error> {
-        symlink_dir(absolute_path(src).unwrap(), absolute_path(dst).unwrap())
+fn check(file_path: &Path, opts: &GlobalOptions) -> Result<bool, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(file_path)?;
+    let problems = check_manifest(&content, ManifestFormat::from_path(file_path), opts);
+    print_problems(file_path, &content, &problems);
+    let mut ok = problems.is_empty();
+
+    // dependencies from included manifests only get checked if everything could be parsed
+    if ok {
+        match load_manifest_with_origins(file_path) {
+            Ok((man, origins)) => {
+                for (name, dep) in man.dependencies.iter().flatten() {
+                    let origin = &origins[name];
+                    if origin == file_path {
+                        continue;
+                    }
+                    let content = std::fs::read_to_string(origin)?;
+                    let line = find_line(&content, name, None);
+                    let problems = check_dependency(dep, man.project.git_server.as_ref(), opts).into_iter()
+                        .map(|message| (line, format!("\"{}\": {}", name, message)))
+                        .collect::<Vec<_>>();
+                    print_problems(origin, &content, &problems);
+                    ok &= problems.is_empty();
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ok = false;
+            }
+        }
     }
 
-    pub fn get_home_dir_env_var() -> &'static str {
-        "USERPROFILE"
+    if ok {
+        println!("No problems found");
     }
+    Ok(ok)
+}
 
-    pub fn get_home_dir() -> Result<String, VarError> {
-        std::env::var(get_home_dir_env_var())
+fn print_config(prefix: &str, item: &toml_edit::Item) {
+    match item {
+        toml_edit::Item::Table(table) => {
+            for (key, item) in table.iter() {
+                print_config(&if prefix.is_empty() { key.to_owned() } else { format!("{}.{}", prefix, key) }, item);
+            }
+        }
+        toml_edit::Item::Value(value) => println!("{} = {}", prefix, value.to_string().trim()),
+        toml_edit::Item::ArrayOfTables(tables) => {
+            for (index, table) in tables.iter().enumerate() {
+                for (key, item) in table.iter() {
+                    print_config(&format!("{}.{}.{}", prefix, index, key), item);
+                }
+            }
+        }
+        toml_edit::Item::None => (),
     }
 }
 
-#[cfg(unix)]
-mod systools {
-    use std::env::VarError;
-    use std::os::unix::fs::symlink;
-    use std::path::Path;
-    use crate::absolute_path;
-
-    pub fn make_symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<(), std::io::Error> {
-        symlink(absolute_path(src).unwrap(), absolute_path(dst).unwrap())
+/// `dep config get <key>`, `set <key> <value>`, `unset <key>` and `list` on the global config
+/// or the manifest. Keys are dotted paths like `ssh.protected`. Changes which would make the
+/// file unreadable for dep are refused.
+fn config(path: &Path, is_manifest: bool, action: &ConfigAction) -> Result<(), Box<dyn std::error::Error>> {
+    if is_manifest && ManifestFormat::from_path(path) != ManifestFormat::Toml {
+        return Err(Box::new(Error::msg("dep config only supports TOML manifests")));
     }
 
-    pub fn get_home_dir_env_var() -> &'static str {
-        "HOME"
-    }
+    let content = if path.exists() { std::fs::read_to_string(path)? } else { String::new() };
+    let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+    let parts = |key: &str| key.split('.').map(|s| s.to_owned()).collect::<Vec<_>>();
 
-    pub fn get_home_dir() -> Result<String, VarError> {
-        std::env::var(get_home_dir_env_var())
+    match action {
+        ConfigAction::List => {
+            print_config("", doc.as_item());
+            return Ok(());
+        }
+        ConfigAction::Get { key } => {
+            let mut item = doc.as_item();
+            for part in parts(key) {
+                item = match item.get(&part) {
+                    Some(item) => item,
+                    None => return Err(Box::new(Error::msg(format!("\"{}\" is not set", key)))),
+                };
+            }
+            match item.as_str() {
+                Some(value) => println!("{}", value),
+                None => print_config(key, item),
+            }
+            return Ok(());
+        }
+        ConfigAction::Set { key, value } => {
+            // values which aren't valid toml (like VENDOR) are taken as strings
+            let value = value.parse::<toml_edit::Value>().unwrap_or_else(|_| toml_edit::Value::from(value.as_str()));
+            let parts = parts(key);
+            let (last, parents) = parts.split_last().unwrap();
+            let mut item = doc.as_item_mut();
+            for part in parents {
+                if item.get(part).is_none() {
+                    item[part.as_str()] = toml_edit::table();
+                }
+                item = &mut item[part.as_str()];
+            }
+            item[last.as_str()] = toml_edit::Item::Value(value);
+        }
+        ConfigAction::Unset { key } => {
+            let parts = parts(key);
+            let (last, parents) = parts.split_last().unwrap();
+            let mut item = doc.as_item_mut();
+            for part in parents {
+                item = match item.get_mut(part) {
+                    Some(item) => item,
+                    None => return Ok(()),
+                };
+            }
+            if let Some(table) = item.as_table_like_mut() {
+                table.remove(last);
+            }
+        }
     }
-}
-
-fn normalize<P>(path: &P) -> PathBuf
-    where P: AsRef<Path>
-{
-    let path_string = path.as_ref().to_string_lossy().to_string();
-    let split_char = if path_string.contains("/") {
-        "/"
-    } else {
-        "\\"
-    };
-
-    let parts = path_string.split(split_char);
-
-    let mut result = PathBuf::new();
 
-    for part in parts {
-        if part.starts_with("$") {
-            let var = &part[1..];
-            result.push(&std::env::var(var).unwrap());
-        } else if part.starts_with("%") && part.ends_with("%") {
-            let var = &part[1..part.len() - 1];
-            result.push(&std::env::var(var).unwrap());
-        } else if part == "~" {
-            result.push(&std::env::var(systools::get_home_dir_env_var()).unwrap());
+    let content = doc.to_string();
+    let valid = if is_manifest {
+        if is_manifest_v2(&content, ManifestFormat::Toml) {
+            parse::<TomlManifestV2>(&content, ManifestFormat::Toml).map(|_| ())
         } else {
-            result.push(part);
+            parse::<TomlManifest>(&content, ManifestFormat::Toml).map(|_| ())
         }
+    } else {
+        toml::de::from_str::<GlobalOptions>(&content).map(|_| ()).map_err(|e| e.to_string())
+    };
+    if let Err(e) = valid {
+        return Err(Box::new(Error::msg(format!("Not changing \"{}\", it would become invalid: {}", path.to_string_lossy(), with_suggestion(&e)))));
     }
 
-    result
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub struct SshOptions {
-    private: PathBuf,
-    public: PathBuf,
-    protected: bool,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub struct GeneralOptions {
-    default_lib_dir: PathBuf,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub struct GlobalOptions {
-    general: GeneralOptions,
-    ssh: Option<SshOptions>,
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    file.flush()?;
+    Ok(())
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
-#[serde(rename_all = "kebab-case")]
-pub struct TomlDependency {
-    path: Option<PathBuf>,
-    repo: Option<String>,
-    git: Option<String>,
-    branch: Option<String>,
-    tag: Option<String>,
-    rev: Option<String>,
-    into: Option<PathBuf>,
-    #[serde(rename="as")]
-    name: Option<String>,
-}
+fn doctor(explicit_config: Option<&Path>, file_path: &Path, offline: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut ok = true;
+    let opts = match load_global_options(explicit_config, file_path, &print_progress) {
+        Ok(opts) => opts,
+        Err(e) => {
+            println!("error   global config: {}", e);
+            println!("        fix: correct the global config, dep config list shows what was read");
+            ok = false;
+            GlobalOptions::default()
+        }
+    };
+    let man = load_manifest(file_path).ok();
+    let offline = offline || opts.is_offline();
+
+    for diagnostic in diagnose(&opts, explicit_config, man.as_ref(), offline) {
+        let status = match diagnostic.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+        println!("{:<8}{}: {}", status, diagnostic.check, diagnostic.message);
+        if let Some(fix) = &diagnostic.fix {
+            println!("        fix: {}", fix);
+        }
+        ok &= diagnostic.status != Status::Error;
+    }
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
-#[serde(rename_all = "kebab-case")]
-pub struct TomlProject {
-    name: String,
-    lib_dir: Option<PathBuf>,
-    git_server: Option<String>,
-
-    // package metadata
-    authors: Option<Vec<String>>,
-    description: Option<String>,
-    homepage: Option<String>,
-    repository: Option<String>,
-    metadata: Option<toml::Value>,
+    println!();
+    if file_path.exists() {
+        println!("Checking {}", file_path.to_string_lossy());
+        ok &= check(file_path, &opts)?;
+    } else {
+        println!("No manifest found, run \"dep init\" to create one");
+    }
+    Ok(ok)
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "kebab-case")]
-pub struct TomlManifest {
-    project: TomlProject,
-    dependencies: Option<BTreeMap<String, TomlDependency>>,
-}
+/// Shows what `--force` deletes and asks for confirmation, unless `--yes` was given.
+fn confirm_lib_dir_removal(libdir: &Path, yes: bool, interactive: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    check_lib_dir_removal(libdir)?;
+    if yes {
+        return Ok(true);
+    }
+    if !interactive {
+        return Err(Box::new(Error::InputRequired(format!("--force would delete \"{}\", pass --yes to confirm", libdir.to_string_lossy()))));
+    }
 
-fn read(file: &mut File) -> std::result::Result<String, std::io::Error> {
-    let mut content = String::new();
-    match file.read_to_string(&mut content) {
-        Ok(_) => Ok(content),
-        Err(e) => Err(e),
+    let mut entries = std::fs::read_dir(libdir)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+        .filter(|name| name.as_ref().map(|name| name != LOCK_FILE).unwrap_or(true))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    println!("This deletes \"{}\" with {} entries:", libdir.to_string_lossy(), entries.len());
+    for entry in &entries {
+        println!("  {}", entry);
     }
+    let answer = prompt("Continue? [y/N]", "")?;
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
 }
 
-#[derive(Debug)]
-struct Options {
-    command: String,
-    force: bool,
+/// Finds the executable of an external command, `dep-<name>` on the PATH.
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    let file_name = format!("dep-{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).map(|dir| dir.join(&file_name)).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|path| path.is_file())
 }
 
-fn get_options() -> Options {
-    let mut command = "".to_string();
-    let mut force = false;
-    {
-        // this block limits scope of borrows by ap.refer() method
-        let mut ap = ArgumentParser::new();
-        ap.set_description("Dependency manager.");
-        ap.refer(&mut force)
-            .add_option(&["--force", "-f"], StoreTrue, "force checkout. Removes the vendor dir and starts from a clean state.");
-        ap.refer(&mut command)
-            .add_argument("command", Store, "the command to execute. [init, update]");
-        ap.parse_args_or_exit();
+/// Runs an external command with the remaining arguments. The resolved project is passed
+/// as json in `DEP_METADATA`, if there is a manifest.
+fn run_plugin(plugin: &Path, args: &[String], explicit_config: Option<&Path>, file_path: &Path) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut command = Command::new(plugin);
+    command.args(args).env("DEP_MANIFEST", file_path);
+    if let Some(config) = explicit_config {
+        command.env("DEP_CONFIG", config);
     }
-    Options {
-        command: command.to_lowercase().trim().to_string(),
-        force,
-    }
-}
-
-static mut GLOBAL_OPTIONS: Option<GlobalOptions> = None;
-
-fn set_global_options(opts: &GlobalOptions) {
-    unsafe {
-        GLOBAL_OPTIONS = Some(opts.clone());
+    if file_path.exists() {
+        let opts = load_global_options(explicit_config, file_path, &print_progress)?;
+        let metadata = metadata(file_path, &opts)?;
+        command.env("DEP_LIB_DIR", &metadata.lib_dir).env("DEP_METADATA", serde_json::to_string(&metadata)?);
     }
+    let status = command.status()?;
+    Ok(status.code().unwrap_or(1))
 }
 
-fn get_global_options() -> GlobalOptions {
-    unsafe {
-        match &GLOBAL_OPTIONS {
-            Some(opts) => opts.clone(),
-            None => GlobalOptions {
-                ssh: Some(SshOptions {
-                    private: Path::new(&format!("${}/.ssh/id_rsa", systools::get_home_dir_env_var())).to_path_buf(),
-                    public: Path::new(&format!("${}/.ssh/id_rsa.pub", systools::get_home_dir_env_var())).to_path_buf(),
-                    protected: false,
-                }),
-                general: GeneralOptions {
-                    default_lib_dir: Path::new("VENDOR").to_path_buf()
-                },
-            },
+/// Opens `url` with `$BROWSER`, or the opener of the OS.
+fn open_in_browser(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = match std::env::var("BROWSER") {
+        Ok(browser) if !browser.is_empty() => Command::new(browser),
+        _ if cfg!(windows) => {
+            // the empty title keeps start from taking the url as the title of the window
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
         }
+        _ if cfg!(target_os = "macos") => Command::new("open"),
+        _ => Command::new("xdg-open"),
+    };
+    let status = command.arg(url).status().map_err(|e| Error::msg(format!("Could not open \"{}\": {}", url, e)))?;
+    if !status.success() {
+        return Err(Box::new(Error::msg(format!("Could not open \"{}\"", url))));
     }
+    Ok(())
 }
 
-static mut PASSPHRASE: Option<String> = None;
-
-fn set_passphrase(str: &String) {
-    unsafe {
-        PASSPHRASE = Some(str.clone());
+fn run(cli: Cli, cancel: Arc<AtomicBool>) -> Result<(), Box<dyn std::error::Error>> {
+    let format = cli.manifest_format.map(|format| match format {
+        Format::Toml => ManifestFormat::Toml,
+        Format::Json => ManifestFormat::Json,
+        Format::Yaml => ManifestFormat::Yaml,
+    });
+    let file_path = &find_manifest(format);
+
+    let explicit_config = cli.config.as_deref();
+    if let Commands::External(args) = &cli.command {
+        let plugin = match find_plugin(&args[0]) {
+            Some(plugin) => plugin,
+            None => {
+                eprintln!("Unknown command: \"{}\"", args[0]);
+                exit(2);
+            }
+        };
+        exit(run_plugin(&plugin, &args[1..], explicit_config, file_path)?);
     }
-}
 
-fn get_passphrase() -> String {
-    unsafe {
-        match &PASSPHRASE {
-            Some(s) => s.clone(),
-            None => "".to_owned(),
+    if let Commands::Doctor = &cli.command {
+        if !doctor(explicit_config, file_path, cli.offline)? {
+            exit(1);
         }
+        return Ok(());
     }
-}
-
-fn main() -> std::result::Result<(), Box<std::error::Error>> {
-    match systools::get_home_dir() {
-        Ok(dir) => {
-            let global_config_path = Path::new(&dir).join(".deprc");
-            if !global_config_path.exists() {
-                let opts = get_global_options();
-
-                let mut file = File::create(&global_config_path)?;
-                let val = toml::ser::to_string_pretty(&opts)?;
 
-                file.write_all(val.as_bytes())?;
-                file.flush()?;
-                println!("Initializing global configuration.");
-                set_global_options(&opts);
+    let opts = load_global_options(explicit_config, file_path, &print_progress)?;
+    let offline = cli.offline || opts.is_offline();
+    let passphrase = given_passphrase(&cli)?;
+    let interactive = !cli.non_interactive && std::io::stdin().is_terminal();
+    let git_config = GitConfig::load();
+    let new_credentials = || Credentials::new(opts.ssh.clone(), read_password)
+        .with_login_prompt(read_login)
+        .with_hosts(opts.credentials.clone())
+        .with_cancel(cancel.clone())
+        .with_passphrase(passphrase.clone())
+        .with_interactive(interactive)
+        .with_host_key_checking(opts.host_key_checking())
+        .with_git_config(git_config.clone())
+        .with_backend(opts.backend())
+        .with_reporter(print_progress);
+    if !interactive {
+        // git run for partial clones, lfs and submodules mustn't wait for a login or passphrase either
+        std::env::set_var("GIT_TERMINAL_PROMPT", "0");
+        if std::env::var_os("GIT_SSH_COMMAND").is_none() {
+            std::env::set_var("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+        }
+    }
+    match &cli.command {
+        Commands::Global { action: Some(GlobalAction::Migrate) } => migrate_global_config(&print_progress)?,
+        Commands::Global { action: None } => {
+            match get_global_config_path(explicit_config) {
+                Ok(global_config_path) => {
+                    println!("Global configuration path: \"{}\"", global_config_path.to_string_lossy());
+                }
+                _ => {
+                    eprintln!("Could not get homedir, using default global config");
+                }
+            };
+        }
+        Commands::Config { project, action } => {
+            if *project {
+                config(file_path, true, action)?;
             } else {
-                let mut file = File::open(&global_config_path)?;
-
-                let config = read(&mut file)?;
+                config(&get_global_config_path(explicit_config)?, false, action)?;
+            }
+        }
+        Commands::Init { interactive: questions, template } => {
+            if Path::exists(file_path) {
+                eprintln!("Already initialized");
+                exit(1);
+            }
 
-                set_global_options(&toml::de::from_str(&config)?);
+            if let Some(template) = template {
+                let credentials = new_credentials();
+                init::from_template(file_path, template, &opts, &credentials, offline)?;
+            } else if *questions && !interactive {
+                return Err(Box::new(Error::InputRequired("dep init --interactive has to ask questions, use --template or edit the manifest instead".to_owned())));
+            } else if *questions {
+                init::interactive(file_path, &opts)?;
+            } else {
+                write_manifest(file_path, &init::default_manifest()?)?;
             }
         }
-        _ => {
-            eprintln!("Could not get homedir, using default global config");
+        Commands::Schema { kind } => {
+            let schema = match kind {
+                SchemaKind::Manifest => schema_for!(TomlManifest),
+                SchemaKind::ManifestV2 => schema_for!(TomlManifestV2),
+                SchemaKind::Global => schema_for!(GlobalOptions),
+            };
+            println!("{}", serde_json::to_string_pretty(&schema)?);
         }
-    };
-
-    let file_path = Path::new("./deps.toml");
-
-    let opts = get_global_options();
-
-    let options = get_options();
-    if options.command == "global" {
-        match systools::get_home_dir() {
-            Ok(dir) => {
-                let global_config_path = Path::new(&dir).join(".deprc");
-                println!("Global configuration path: \"{}\"", global_config_path.to_string_lossy());
+        Commands::Check => {
+            if !check(file_path, &opts)? {
+                exit(1);
             }
-            _ => {
-                eprintln!("Could not get homedir, using default global config");
+        }
+        Commands::Migrate => migrate(file_path, &opts, &print_progress)?,
+        Commands::Diff { name } => {
+            let man = load_manifest(file_path)?;
+            let credentials = new_credentials();
+            print!("{}", diff(&man, &opts, &credentials, name, offline)?);
+        }
+        Commands::Log { name, since_locked } => {
+            let man = load_manifest(file_path)?;
+            let credentials = new_credentials();
+            let entries = log(&man, &opts, &credentials, name, *since_locked, offline)?;
+            if entries.is_empty() {
+                println!("\"{}\" is up to date", name);
+            }
+            for entry in &entries {
+                println!("{} {} ({})", &entry.id[..10], entry.summary, entry.author);
             }
-        };
-    } else if options.command == "init" {
-        if Path::exists(file_path) {
-            eprintln!("Already initialized");
-            exit(1);
         }
-
-        let man = TomlManifest {
-            project: TomlProject {
-                name: std::env::current_dir()?.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or("".to_owned()),
-                authors: Some(vec![whoami::username()]),
-                lib_dir: None,
-                git_server: None,
-
-                // package metadata
-                description: None,
-                homepage: None,
-                repository: None,
-                metadata: None,
-            },
-            dependencies: None,
-        };
-
-        let mut file = File::create(&file_path)?;
-        let val = toml::ser::to_string_pretty(&man)?;
-
-        file.write_all(val.as_bytes())?;
-        file.flush()?;
-    } else if options.command == "update" {
-        let mut file = File::open(&file_path)?;
-
-        let config = read(&mut file)?;
-
-        let man: TomlManifest = toml::de::from_str(&config)?;
-
-        let libdir = match &man.project.lib_dir {
-            Some(dir) => dir.clone(),
-            None => opts.general.default_lib_dir.clone(),
-        };
-        if !libdir.exists() {
-            println!("Creating lib dir: {}", libdir.to_string_lossy());
-            std::fs::create_dir_all(&libdir)?;
-        } else if options.force {
-            println!("Deleting old lib dir: {}", libdir.to_string_lossy());
-            remove_dir_all::remove_dir_all(&libdir)?;
-            println!("Creating lib dir: {}", libdir.to_string_lossy());
-            std::fs::create_dir_all(&libdir)?;
-        }
-
-        match &man.dependencies {
-            None => (),
-            Some(deps) => {
-                if deps.values().any(|d| d.git.is_some() || (d.repo.is_some() && man.project.git_server.is_some())) {
-                    match opts.ssh {
-                        Some(ssh) => {
-                            if ssh.protected {
-                                match read_password() {
-                                    Ok(pass) => set_passphrase(&pass.clone()),
-                                    Err(e) => return Err(Box::new(e)),
-                                };
-                            }
-                        }
-                        _ => (),
-                    }
+        Commands::Changelog { output } => {
+            let man = load_manifest(file_path)?;
+            let document = changelog(file_path, &man, &opts)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(path, document)?;
+                    println!("Wrote the changelog to \"{}\"", path.to_string_lossy());
                 }
-
-
-                for (name, dep) in deps {
-                    let libdir = &dep.clone().into.unwrap_or_else(|| libdir.clone());
-                    if !libdir.exists() {
-                        println!("Creating lib dir: {}", libdir.to_string_lossy());
-                        std::fs::create_dir_all(&libdir)?;
+                None => print!("{}", document),
+            }
+        }
+        Commands::Sbom { format, output } => {
+            let man = load_manifest(file_path)?;
+            let format = match format {
+                SbomKind::Cyclonedx => SbomFormat::CycloneDx,
+                SbomKind::Spdx => SbomFormat::Spdx,
+            };
+            let document = sbom(&man, &opts, format)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(path, document)?;
+                    println!("Wrote the bill of materials to \"{}\"", path.to_string_lossy());
+                }
+                None => print!("{}", document),
+            }
+        }
+        Commands::Audit { database } => {
+            let man = load_manifest(file_path)?;
+            let findings = audit(&man, &opts, database.as_deref(), offline, &print_progress)?;
+            for (name, advisories) in &findings {
+                for advisory in advisories {
+                    let mut line = format!("\"{}\": {}", name, advisory.id);
+                    if !advisory.aliases.is_empty() {
+                        line += &format!(" ({})", advisory.aliases.join(", "));
+                    }
+                    if let Some(summary) = &advisory.summary {
+                        line += &format!(" {}", summary);
                     }
+                    if !advisory.fixed.is_empty() {
+                        line += &format!(", fixed in {}", advisory.fixed.iter().map(|fix| &fix[..fix.len().min(10)]).collect::<Vec<_>>().join(", "));
+                    }
+                    println!("{}", line);
+                }
+            }
+            if findings.is_empty() {
+                println!("No known vulnerabilities found");
+            } else {
+                let count = findings.iter().map(|(_, advisories)| advisories.len()).sum::<usize>();
+                println!("Found {} known {} in {} {}", count, if count == 1 { "vulnerability" } else { "vulnerabilities" },
+                    findings.len(), if findings.len() == 1 { "dependency" } else { "dependencies" });
+                exit(1);
+            }
+        }
+        Commands::Mirror { dir } => {
+            let man = load_manifest(file_path)?;
+            let credentials = new_credentials();
+            let count = mirror(&man, &opts, &credentials, dir, offline)?;
+            println!("Mirrored {} {} into \"{}\"", count, if count == 1 { "dependency" } else { "dependencies" }, dir.to_string_lossy());
+        }
+        Commands::Bundle { output } => {
+            let count = bundle(file_path, &opts, output, &print_progress)?;
+            println!("Bundled {} {} into \"{}\"", count, if count == 1 { "dependency" } else { "dependencies" }, output.to_string_lossy());
+        }
+        Commands::Restore { bundle } => {
+            let count = restore(bundle, &opts, &print_progress)?;
+            println!("Restored {} {} from \"{}\"", count, if count == 1 { "dependency" } else { "dependencies" }, bundle.to_string_lossy());
+        }
+        Commands::Prefetch { urls } => {
+            let credentials = new_credentials();
+            let (urls, project) = if urls.is_empty() {
+                let man = load_manifest(file_path)?;
+                let mut urls = git_dependency_urls(&man, &opts, &credentials, offline)?.into_iter().map(|(_, url)| url).collect::<Vec<_>>();
+                // dependencies on other refs of the same repository
+                urls.sort();
+                urls.dedup();
+                (urls, Some(absolute_path(file_path)?))
+            } else {
+                (urls.clone(), None)
+            };
+            let count = prefetch(&opts, &credentials, &urls, project.as_deref(), offline)?;
+            println!("Prefetched {} {}", count, if count == 1 { "repository" } else { "repositories" });
+        }
+        Commands::Auth { action } => {
+            let key = match action {
+                AuthAction::Set { key } | AuthAction::Clear { key } => key,
+            };
+            let ssh = match (key, &opts.ssh) {
+                // the ssh-key of a dependency
+                (Some(key), _) => SshOptions { private: key.clone(), public: PathBuf::from(format!("{}.pub", key.to_string_lossy())), protected: true },
+                (None, Some(ssh)) => ssh.clone(),
+                (None, None) => match default_ssh_keys().into_iter().next() {
+                    Some(ssh) => ssh,
+                    None => return Err(Box::new(Error::msg("There is no ssh key in [ssh] of the global config or in ~/.ssh, pass one with --key"))),
+                },
+            };
+            let ssh = &ssh;
+            match action {
+                AuthAction::Set { .. } => {
+                    let given = match &passphrase {
+                        Some(passphrase) => Some(passphrase.clone()),
+                        None if interactive && ASKS_FOR_PASSPHRASE => None,
+                        None if interactive => Some(read_password(&ssh.private)?),
+                        None => return Err(Box::new(Error::CredentialsRequired(
+                            "There is nobody to ask for the passphrase, pass it with DEP_SSH_PASSPHRASE, --passphrase-file or --passphrase-stdin".to_owned()))),
+                    };
+                    store_passphrase(ssh, given.as_deref())?;
+                    println!("Stored the passphrase of \"{}\" in the keychain", ssh.private.to_string_lossy());
+                }
+                AuthAction::Clear { .. } => match clear_passphrase(ssh)? {
+                    true => println!("Removed the passphrase of \"{}\" from the keychain", ssh.private.to_string_lossy()),
+                    false => println!("The keychain has no passphrase of \"{}\"", ssh.private.to_string_lossy()),
+                },
+            }
+        }
+        Commands::Cache { action: CacheAction::Gc { max_age } } => {
+            let reclaimed = gc(&opts, max_age.map(|days| days * 86400), &print_progress)?;
+            println!("Reclaimed {}", format_size(reclaimed));
+        }
+        Commands::Cache { action: CacheAction::List } => {
+            for listing in list(&opts)? {
+                println!("{} ({})", listing.url.as_deref().unwrap_or(&listing.key), listing.key);
+                let last_used = listing.last_used.map(|time| format!("used {}", format_age(time))).unwrap_or_else(|| "never used".to_owned());
+                let projects = if listing.projects.is_empty() { "no project".to_owned() } else { listing.projects.join(", ") };
+                println!("  {}, {}, by {}", format_size(listing.size), last_used, projects);
+            }
+        }
+        Commands::Cache { action: CacheAction::Stats } => {
+            let listings = list(&opts)?;
+            let total = listings.iter().map(|listing| listing.size).sum::<u64>();
+            let unused = listings.iter().filter(|listing| listing.projects.is_empty()).collect::<Vec<_>>();
+            // the repositories clones borrow from are kept
+            let collectable = unused.iter().filter(|listing| !listing.key.starts_with(&format!("{}/", BORROWED))).map(|listing| listing.size).sum();
+            let projects = listings.iter().flat_map(|listing| &listing.projects).collect::<BTreeSet<_>>();
+            println!("Cache dir: \"{}\"", opts.cache_dir()?.to_string_lossy());
+            for kind in CACHE_KINDS.iter() {
+                let entries = listings.iter().filter(|listing| listing.key.starts_with(&format!("{}/", kind))).collect::<Vec<_>>();
+                println!("{}: {} cached, {}", kind, entries.len(), format_size(entries.iter().map(|listing| listing.size).sum()));
+            }
+            println!("Total: {} cached, {}, used by {} {}", listings.len(), format_size(total), projects.len(), if projects.len() == 1 { "project" } else { "projects" });
+            if !unused.is_empty() {
+                println!("{} not used by any project, \"dep cache gc\" reclaims {}", unused.len(), format_size(collectable));
+            }
+        }
+        Commands::Foreach { command } => {
+            let man = load_manifest(file_path)?;
+            foreach(&man, &opts, command, &print_progress)?;
+        }
+        Commands::Exec { command } => {
+            let man = load_manifest(file_path)?;
+            exit(exec(&man, &opts, command)?);
+        }
+        Commands::Emit { format, output } => {
+            let man = load_manifest(file_path)?;
+            let format = match format {
+                EmitKind::Env => EmitFormat::Env,
+                EmitKind::Props => EmitFormat::Props,
+                EmitKind::Cmake => EmitFormat::Cmake,
+                EmitKind::Meson => EmitFormat::Meson,
+                EmitKind::Bazel => EmitFormat::Bazel,
+                EmitKind::Make => EmitFormat::Make,
+                EmitKind::Msbuild => EmitFormat::Msbuild,
+                EmitKind::CargoPatch => EmitFormat::CargoPatch,
+                EmitKind::GoReplace => EmitFormat::GoReplace,
+            };
+            let path = output.clone().unwrap_or_else(|| PathBuf::from(format.default_path()));
+            match emit(format, &man, &opts, &path)? {
+                Some(content) => print!("{}", content),
+                None => println!("Wrote \"{}\"", path.to_string_lossy()),
+            }
+        }
+        Commands::Export { format: ExportFormat::Submodules { stage } } => {
+            let man = load_manifest(file_path)?;
+            export_submodules(&man, &opts, Path::new(GITMODULES), *stage, &print_progress)?;
+            println!("Wrote \"{}\"", GITMODULES);
+        }
+        Commands::Import { source } => {
+            // what's left to do after the import
+            let (deps, hint) = match source {
+                ImportSource::Submodules => (from_submodules(Path::new("."))?, "Remove the submodules with \"git rm\" before running \"dep update\""),
+                ImportSource::Cargo { manifest } => (from_cargo(manifest)?, "Run \"dep update\" to vendor them, cargo keeps fetching its own copies"),
+                ImportSource::Vcpkg { manifest } => (from_vcpkg(manifest)?, REGISTRY_HINT),
+                ImportSource::Conan { recipe } => (from_conan(recipe)?, REGISTRY_HINT),
+                ImportSource::Peru { file } => (from_peru(file, &print_progress)?, "Run \"dep update\" to vendor them, and remove the modules from peru.yaml"),
+                ImportSource::Gitman { file } => (from_gitman(file, &print_progress)?, "Run \"dep update\" to vendor them, and remove the sources from gitman.yml"),
+            };
+            if !file_path.exists() {
+                write_manifest(file_path, &init::default_manifest()?)?;
+                println!("Created {}", file_path.to_string_lossy());
+            }
+            let added = add_dependencies(file_path, &opts, &deps, &print_progress)?;
+            for name in &added {
+                println!("Added \"{}\"", name);
+            }
+            println!("Imported {} of {} dependencies into {}", added.len(), deps.len(), file_path.to_string_lossy());
+            if !added.is_empty() {
+                println!("{}", hint);
+            }
+        }
+        Commands::St => {
+            let man = load_manifest(file_path)?;
+            let changes = local_changes(&man, &opts)?;
+            if changes.is_empty() {
+                println!("No local changes");
+            }
+            for (name, dir, files) in &changes {
+                println!("{} ({})", name, dir.to_string_lossy());
+                for (code, path) in files {
+                    println!("  {} {}", code, path);
+                }
+            }
+        }
+        Commands::Status => {
+            let man = load_manifest(file_path)?;
+            let states = status(&man, &opts)?;
+            let width = states.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0);
+            let dir_width = states.iter().map(|(_, dir, _)| dir.to_string_lossy().len()).max().unwrap_or(0);
+            for (name, dir, state) in &states {
+                println!("{:<width$}  {:<dir_width$}  {}", name, dir.to_string_lossy(), state, width = width, dir_width = dir_width);
+            }
+            if states.iter().any(|(_, _, state)| state.is_problem()) {
+                println!();
+                println!("\"dep update\" fetches missing dependencies and repairs links, \"dep update --prune\" removes orphaned ones");
+                exit(1);
+            }
+        }
+        Commands::Info { name } => {
+            let hooks = Path::new("./dep.rhai");
+            let hooks = if hooks.exists() { Some(Hooks::load(hooks, &load_manifest(file_path)?)?) } else { None };
+            let info = info(file_path, &opts, hooks.as_ref(), name)?;
+            let mut fields = vec![("manifest", info.origin.to_string_lossy().to_string()), ("source", info.source)];
+            if let Some(url) = info.fetched_from {
+                fields.push(("fetched from", url));
+            }
+            if let Some(reference) = info.reference {
+                fields.push(("ref", reference));
+            }
+            if let Some(commit) = info.commit {
+                fields.push(("commit", commit));
+            }
+            fields.push(("dir", info.dir.to_string_lossy().to_string()));
+            fields.push(("state", info.state.to_string()));
+            fields.push(("size", format_size(info.size)));
+            if let Some(files) = info.changed_files {
+                let changes = match files.len() {
+                    0 => "none".to_owned(),
+                    1 => "1 changed file, see \"dep st\"".to_owned(),
+                    count => format!("{} changed files, see \"dep st\"", count),
+                };
+                fields.push(("local changes", changes));
+            }
+            if let Some(updated) = info.updated {
+                fields.push(("updated", format_age(updated)));
+            }
+            if !info.hooks.is_empty() {
+                fields.push(("hooks", info.hooks.join(", ")));
+            }
+            let width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+            println!("{}", name);
+            for (key, value) in &fields {
+                println!("  {:<width$}  {}", format!("{}:", key), value, width = width + 1);
+            }
+        }
+        Commands::Open { name } => {
+            let man = load_manifest(file_path)?;
+            let page = homepage(&man, &opts, name)?;
+            println!("Opening \"{}\"", page);
+            open_in_browser(&page)?;
+        }
+        Commands::Why { name } => {
+            let found = requirements(file_path, &opts)?.into_iter()
+                .filter(|requirement| requirement.name == *name || requirement.dependency.name.as_deref() == Some(name.as_str()))
+                .collect::<Vec<_>>();
+            if found.is_empty() {
+                return Err(Box::new(Error::msg(format!("No manifest asks for \"{}\"", name))));
+            }
+            // the last one the project asks for overrides the others
+            let used = found.iter().rposition(Requirement::is_direct);
+            match used {
+                Some(index) => {
+                    println!("\"{}\" is vendored as asked for by", name);
+                    println!("  {}", found[index]);
+                }
+                None => println!("\"{}\" isn't vendored, the manifest of the project doesn't ask for it", name),
+            }
+            let others = found.iter().enumerate().filter(|(index, _)| Some(*index) != used).collect::<Vec<_>>();
+            if !others.is_empty() {
+                println!("{}", if used.is_some() { "It's also asked for by" } else { "It's asked for by" });
+                for (_, requirement) in others {
+                    println!("  {}", requirement);
+                }
+            }
+        }
+        Commands::Pin { names } => pin(file_path, &opts, names, &print_progress)?,
+        Commands::Rename { old, new } => {
+            rename(file_path, &opts, old, new, &print_progress)?;
+            // emitted files and .gitmodules have the directory of the dependency
+            let man = load_manifest(file_path)?;
+            refresh(&man, &opts, &print_progress)?;
+            export::refresh(&man, &opts, &print_progress)?;
+        }
+        Commands::Update { names, force, yes, discard_local, no_wait, keep_going, prune, jobs, report, report_file, mirror } => {
+            let mut man = load_manifest(file_path)?;
+            resolve_conflicts(file_path, &opts, &mut man, &print_progress)?;
+
+            let libdir = get_lib_dir(&man, &opts);
+            let force_all = force.as_ref().map(|forced| forced.is_empty()).unwrap_or(false);
+            if force_all && names.is_empty() && libdir.exists() && !confirm_lib_dir_removal(&libdir, *yes, interactive)? {
+                println!("Aborted");
+                exit(1);
+            }
 
-                    let name = &dep.clone().name.unwrap_or_else(|| name.clone());
-
-                    let dst = libdir.join(Path::new(name));
-
-                    match &dep.path {
-                        Some(path) => {
-                            if !dst.exists() {
-                                println!("Linking path \"{}\" into \"{}\" as \"{}\"", path.to_string_lossy(), libdir.to_string_lossy(), name);
-                                systools::make_symlink(&path, &dst)?;
-                            }
-                        }
-                        None => {
-                            let url = match (&man.project.git_server, &dep.repo, &dep.git) {
-                                (Some(server), Some(repo), None) => if !server.contains("@") {
-                                    if server.contains("://") {
-                                        let mut parts = server.split("://");
-                                        match (parts.nth(0), parts.nth(1)) {
-                                            (Some(protocol), Some(server)) => {
-                                                format!("{}://git@{}:{}", protocol, server, repo)
-                                            }
-                                            _ => unreachable!(),
-                                        }
-                                    } else {
-                                        format!("git@{}:{}", server, repo)
-                                    }
-                                } else {
-                                    format!("{}:{}", server, repo)
-                                },
-                                (None, None, Some(repo)) => repo.clone(),
-                                (Some(_), None, Some(repo)) => repo.clone(),
-                                _ => return Err(Box::new(git2::Error::from_str("Could not get git url or dependency path"))),
-                            };
-
-                            let mut cb = RemoteCallbacks::new();
-                            cb.credentials(credentials);
-
-                            let mut fo = FetchOptions::new();
-                            fo.remote_callbacks(cb);
-
-                            let co = CheckoutBuilder::new();
-
-                            match (&dep.branch, &dep.tag, &dep.rev) {
-                                (Some(branch_name), None, None) => {
-                                    println!("Cloning branch \"{}\" from \"{}\" into \"{}\" as \"{}\"", branch_name, url, libdir.to_string_lossy(), name);
-                                    if !dst.exists() {
-                                        std::fs::create_dir_all(&dst)?;
-                                        RepoBuilder::new().branch(branch_name).fetch_options(fo).with_checkout(co)
-                                            .clone(&url, Path::new(&dst))?;
-                                    } else {
-                                        let repo = git2::Repository::open(&dst)?;
-
-                                        let mut remote = repo.find_remote("origin")?;
-
-                                        let mut cb = RemoteCallbacks::new();
-                                        cb.credentials(credentials);
-
-                                        remote.connect_auth(git2::Direction::Fetch, Some(cb), None)?;
-
-                                        let mut cb = RemoteCallbacks::new();
-                                        cb.credentials(credentials);
-
-                                        let mut fo = FetchOptions::new();
-                                        fo.remote_callbacks(cb);
-
-                                        let mut co = CheckoutBuilder::new();
-                                        co.refresh(true);
-                                        co.recreate_missing(true);
-                                        co.update_index(true);
-                                        co.allow_conflicts(false);
-                                        co.remove_untracked(true);
-
-                                        let spec = format!("refs/heads/{}:refs/heads/{}", branch_name, branch_name);
-
-                                        remote.fetch(&[&spec], Some(&mut fo), None)?;
-                                        remote.download(&[&spec], Some(&mut fo))?;
-
-                                        remote.disconnect();
-
-                                        let local_branch_name = format!("refs/heads/{}", branch_name);
-
-                                        let local_branch = repo.find_branch(&branch_name, git2::BranchType::Local)?;
-                                        let local_branch_ref = local_branch.into_reference();
-                                        let local_branch_tree = local_branch_ref.peel_to_tree()?;
-
-                                        let local_branch = local_branch_tree.as_object();
-
-                                        repo.set_head(&local_branch_name)?;
-                                        repo.checkout_tree(&local_branch, Some(&mut co))?;
-                                        repo.reset(repo.head()?.peel_to_commit()?.as_object(), git2::ResetType::Mixed, None)?;
-                                        repo.cleanup_state()?;
-
-                                        // i don't know why, but if i don't repeat this block,
-                                        // the repo doesn't get cleaned up correctly when a branch is changed
-                                        // TODO: Maybe fix this some time
-                                        repo.set_head(&local_branch_name)?;
-                                        repo.checkout_tree(&local_branch, Some(&mut co))?;
-                                        repo.reset(repo.head()?.peel_to_commit()?.as_object(), git2::ResetType::Mixed, None)?;
-                                        repo.cleanup_state()?;
-                                    }
-                                }
-                                (None, Some(tag), None) => {
-                                    println!("Cloning tag \"{}\" from \"{}\" into \"{}\" as \"{}\"", tag, url, libdir.to_string_lossy(), name);
-                                    let repo = if !dst.exists() {
-                                        std::fs::create_dir_all(&dst)?;
-                                        RepoBuilder::new().fetch_options(fo).with_checkout(co)
-                                            .clone(&url, Path::new(&dst))?
-                                    } else {
-                                        git2::Repository::open(&dst)?
-                                    };
-                                    let mut remote = repo.find_remote("origin")?;
-
-                                    let full_tag = format!("refs/tags/{}", tag);
-
-                                    let mut cb = RemoteCallbacks::new();
-                                    cb.credentials(credentials);
-
-                                    let mut fo = FetchOptions::new();
-                                    fo.remote_callbacks(cb);
-
-                                    let mut co = CheckoutBuilder::new();
-
-                                    remote.download(&[&full_tag], Some(&mut fo))?;
-
-                                    repo.checkout_tree(repo.find_reference(&full_tag)?.peel_to_tag()?.as_object(), Some(&mut co))?;
-
-                                    repo.set_head(&full_tag)?;
-                                }
-                                (None, None, Some(rev)) => {
-                                    println!("Cloning revision \"{}\" from \"{}\" into \"{}\" as \"{}\"", rev, url, libdir.to_string_lossy(), name);
-                                    let repo = if !dst.exists() {
-                                        std::fs::create_dir_all(&dst)?;
-                                        RepoBuilder::new().fetch_options(fo).with_checkout(co)
-                                            .clone(&url, Path::new(&dst))?
-                                    } else {
-                                        git2::Repository::open(&dst)?
-                                    };
-
-                                    let mut cb = RemoteCallbacks::new();
-                                    cb.credentials(credentials);
-
-                                    let mut fo = FetchOptions::new();
-                                    fo.remote_callbacks(cb);
-
-                                    let mut co = CheckoutBuilder::new();
-
-                                    let commit = &repo.find_commit(git2::Oid::from_str(&rev)?)?;
+            let credentials = new_credentials();
+            let hooks = Path::new("./dep.rhai");
+            let hooks = if hooks.exists() { Some(hooks.to_path_buf()) } else { None };
+            let report = report.map(|format| match format {
+                cli::ReportFormat::Markdown => (ReportFormat::Markdown, report_file.clone().unwrap_or_else(|| PathBuf::from("dep-report.md"))),
+                cli::ReportFormat::Json => (ReportFormat::Json, report_file.clone().unwrap_or_else(|| PathBuf::from("dep-report.json"))),
+            });
+            let options = UpdateOptions {
+                names: names.clone(),
+                force: force.clone(),
+                discard_local: *discard_local,
+                no_wait: *no_wait,
+                keep_going: *keep_going,
+                prune: *prune,
+                offline,
+                jobs: *jobs,
+                hooks,
+                report,
+                mirror: mirror.clone(),
+                manifest: Some(file_path.to_path_buf()),
+            };
+            update(man.clone(), &opts, &credentials, &options)?;
+            refresh(&man, &opts, &print_progress)?;
+            export::refresh(&man, &opts, &print_progress)?;
+        }
+        Commands::Doctor | Commands::External(_) => unreachable!(),
+    }
 
-                                    repo.checkout_tree(&commit.as_object(), Some(&mut co))?;
+    Ok(())
+}
 
-                                    repo.set_head_detached(commit.id())?;
-                                }
-                                _ => {
-                                    println!("Cloning repository from \"{}\" into \"{}\" as \"{}\"", url, libdir.to_string_lossy(), name);
-                                    if !dst.exists() {
-                                        std::fs::create_dir_all(&dst)?;
-                                        RepoBuilder::new().fetch_options(fo).with_checkout(co)
-                                            .clone(&url, Path::new(&dst))?;
-                                    } else {
-                                        let repo = git2::Repository::open(&dst)?;
-                                        let mut remote = repo.find_remote("origin")?;
-
-                                        let mut cb = RemoteCallbacks::new();
-                                        cb.credentials(credentials);
-
-                                        let mut fo = FetchOptions::new();
-                                        fo.remote_callbacks(cb);
-
-                                        let mut co = CheckoutBuilder::new();
-
-                                        remote.download(&[], Some(&mut fo))?;
+/// Exit code after ctrl-c, like shells use for SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+/// Exit codes of --non-interactive runs which would have to ask for a passphrase, or for a confirmation.
+const EXIT_CREDENTIALS_REQUIRED: i32 = 3;
+const EXIT_INPUT_REQUIRED: i32 = 4;
+
+fn main() {
+    // the first ctrl-c lets the running update clean up, the second one exits right away
+    let cancel = Arc::new(AtomicBool::new(false));
+    let handler_cancel = cancel.clone();
+    let handler = ctrlc::set_handler(move || {
+        if handler_cancel.swap(true, Ordering::SeqCst) {
+            exit(EXIT_INTERRUPTED);
+        }
+        eprintln!("Interrupted, cleaning up (press ctrl-c again to exit immediately)");
+    });
+    if let Err(e) = handler {
+        eprintln!("Could not install the ctrl-c handler: {}", e);
+    }
 
-                                        repo.checkout_head(Some(&mut co))?;
-                                    }
-                                }
-                            };
-                        }
-                    }
-                }
-            }
+    if let Err(e) = run(Cli::parse(), cancel) {
+        if let Some(Error::Interrupted) = e.downcast_ref::<Error>() {
+            eprintln!("Interrupted");
+            exit(EXIT_INTERRUPTED);
         }
+        eprintln!("Error: {}", e);
+        exit(match e.downcast_ref::<Error>() {
+            Some(Error::CredentialsRequired(_)) => EXIT_CREDENTIALS_REQUIRED,
+            Some(Error::InputRequired(_)) => EXIT_INPUT_REQUIRED,
+            _ => 1,
+        });
+    }
+}
+
+/// Asks a question on stderr, an empty answer (or end of input) takes the default.
+fn prompt(question: &str, default: &str) -> std::io::Result<String> {
+    if default.is_empty() {
+        eprint!("{}: ", question);
     } else {
-        eprintln!("Unknown command: \"{}\"", options.command);
-        exit(2);
+        eprint!("{} [{}]: ", question, default);
     }
+    std::io::stderr().flush()?;
 
-    Ok(())
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default.to_owned() } else { answer.to_owned() })
 }
 
-fn read_password() -> Result<String, std::io::Error> {
-    let pass = rpassword::prompt_password_stderr("Enter Passphrase: ");
-    println!();
-    pass
+/// The passphrase of the ssh key given with --passphrase-file, --passphrase-stdin or DEP_SSH_PASSPHRASE, if any.
+fn given_passphrase(cli: &Cli) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if let Some(path) = &cli.passphrase_file {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => return Err(Box::new(Error::msg(format!("Could not read the passphrase from \"{}\": {}", path.to_string_lossy(), e)))),
+        };
+        return Ok(Some(content.lines().next().unwrap_or_default().to_owned()));
+    }
+    if cli.passphrase_stdin {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        return Ok(Some(line.trim_end_matches(['\n', '\r']).to_owned()));
+    }
+    Ok(std::env::var("DEP_SSH_PASSPHRASE").ok())
 }
 
+/// Asks for the login of an https url, only for the password if the url has the username.
+fn read_login(url: &str, username: Option<&str>) -> Result<(String, String), std::io::Error> {
+    eprintln!("Login for \"{}\"", url);
+    let username = match username {
+        Some(username) => username.to_owned(),
+        None => prompt("Username", "")?,
+    };
+    let password = rpassword::prompt_password_stderr("Password: ")?;
+    Ok((username, password))
+}
 
-pub fn credentials(
-    _user: &str,
-    user_from_url: Option<&str>,
-    _cred: git2::CredentialType,
-) -> Result<git2::Cred, git2::Error> {
-    let opts = get_global_options();
-    match opts.ssh {
-        Some(ssh) => {
-            let id_rsa_pub = Path::new(&ssh.public);
-
-            match user_from_url {
-                Some(user) => git2::Cred::ssh_key(user, Some(&normalize(&id_rsa_pub)), &normalize(&ssh.private), Some(get_passphrase().as_str())),
-                None => Err(git2::Error::from_str("Url does not contain username")),
-            }
-        }
-        None => {
-            match systools::get_home_dir() {
-                Ok(p) => {
-                    let base = Path::new(&p).join(".ssh");
-                    let id_rsa = base.join("id_rsa");
-                    let id_rsa_pub = base.join("id_rsa.pub");
-                    match user_from_url {
-                        Some(user) => git2::Cred::ssh_key(user, Some(&id_rsa_pub), &id_rsa, None),
-                        None => Err(git2::Error::from_str("Url does not contain username")),
-                    }
-                }
-                _ => Err(git2::Error::from_str("USERPROFILE not set")),
-            }
-        }
+/// Prints the progress of dep-core, warnings on stderr.
+fn print_progress(level: Level, message: &str) {
+    match level {
+        Level::Info => println!("{}", message),
+        Level::Warning => eprintln!("{}", message),
     }
 }
+
+fn read_password(key: &Path) -> Result<String, std::io::Error> {
+    let pass = rpassword::prompt_password_stderr(&format!("Enter Passphrase for \"{}\": ", key.to_string_lossy()));
+    println!();
+    pass
+}